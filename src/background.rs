@@ -0,0 +1,336 @@
+use anyhow::Result;
+use glam::Vec3;
+use std::rc::Rc;
+
+use crate::{
+    cubemap::Cubemap,
+    gpu::Gpu,
+    pipeline::{PipelineCache, RenderTargets},
+    shader::ShaderKey,
+    uniform::{Uniform, uniform_struct},
+};
+
+/// What a scene shows where there's no geometry. Colors are linear.
+#[derive(Clone, Debug)]
+pub enum Background {
+    Color(Vec3),
+    /// Blends vertically from the bottom color straight down to the top one straight up.
+    Gradient {
+        top: Vec3,
+        bottom: Vec3,
+    },
+    /// Follows the camera's rotation, but not its position.
+    Cubemap(Rc<Cubemap>),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Color(Vec3::ZERO)
+    }
+}
+
+impl Background {
+    /// Color the scene is cleared to, the other backgrounds are drawn over it.
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self {
+            Self::Color(color) => wgpu::Color {
+                r: color.x as f64,
+                g: color.y as f64,
+                b: color.z as f64,
+                a: 1.0,
+            },
+            _ => wgpu::Color::BLACK,
+        }
+    }
+}
+
+uniform_struct! {
+    struct BackgroundUniform {
+        top: Vec3,
+        _padding0: f32,
+        bottom: Vec3,
+        _padding1: f32,
+    }
+}
+
+/// Draws gradient and cube map backgrounds at the far plane, after the
+/// opaque geometry so that covered pixels are skipped by the depth test.
+pub struct BackgroundRenderer {
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    // Bound when the background has no cube map, so the layout stays the same
+    placeholder: Cubemap,
+    // Rebuilt when the cube map changes
+    bind_group: Option<(wgpu::TextureView, wgpu::BindGroup)>,
+    // Failed builds are kept as None, so that they're only reported once.
+    // Also keyed by the shader generation, to rebuild on reloads
+    pipeline: Option<((bool, RenderTargets, u64), Option<wgpu::RenderPipeline>)>,
+    visible: bool,
+}
+
+impl BackgroundRenderer {
+    pub fn new(gpu: &Gpu) -> Self {
+        let layout = gpu
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: "Background bind group layout".into(),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let uniform_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Background uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<BackgroundUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: "Background sampler".into(),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            layout,
+            uniform_buffer,
+            sampler,
+            placeholder: Cubemap::solid(gpu, Vec3::ZERO),
+            bind_group: None,
+            pipeline: None,
+            visible: false,
+        }
+    }
+
+    fn make_pipeline(
+        gpu: &mut Gpu,
+        layout: &wgpu::BindGroupLayout,
+        cubemap: bool,
+        targets: RenderTargets,
+    ) -> Result<wgpu::RenderPipeline> {
+        let mut shader = ShaderKey::builtin("background");
+        if cubemap {
+            shader = shader.with_define("BACKGROUND_CUBEMAP");
+        }
+        let module = gpu.pipelines.get_shader(&gpu.device, &shader)?;
+        if let Some(naga_module) = gpu.pipelines.module(&shader) {
+            BackgroundUniform::layout().check(naga_module, 1, 0)?;
+        }
+
+        let pipeline_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Background pipeline layout"),
+                bind_group_layouts: &[&gpu.layouts.camera, layout],
+                push_constant_ranges: &[],
+            });
+
+        PipelineCache::with_validation(&gpu.device, || {
+            gpu.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Background pipeline"),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &module,
+                        entry_point: Some("vs_main"),
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                        buffers: &[],
+                    },
+                    primitive: wgpu::PrimitiveState::default(),
+                    // Drawn at the far plane, where nothing else was drawn
+                    depth_stencil: targets.depth_format.map(|format| wgpu::DepthStencilState {
+                        format,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::LessEqual,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: targets.sample_count,
+                        ..Default::default()
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &module,
+                        entry_point: Some("fs_main"),
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                        targets: &[targets.color_format.map(Into::into)],
+                    }),
+                    multiview: None,
+                    cache: None,
+                })
+        })
+    }
+
+    fn make_bind_group(&self, device: &wgpu::Device, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Background bind group".into(),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Builds what the background needs and uploads its colors. Solid colors
+    /// are only cleared to, and draw nothing.
+    pub fn prepare(&mut self, gpu: &mut Gpu, background: &Background) {
+        let (top, bottom, cubemap) = match background {
+            Background::Color(_) => {
+                self.visible = false;
+                return;
+            }
+            Background::Gradient { top, bottom } => (*top, *bottom, None),
+            Background::Cubemap(cubemap) => (Vec3::ZERO, Vec3::ZERO, Some(cubemap)),
+        };
+        self.visible = true;
+
+        let key = (
+            cubemap.is_some(),
+            gpu.render_targets(),
+            gpu.pipelines.shader_generation(),
+        );
+        if self
+            .pipeline
+            .as_ref()
+            .is_none_or(|(built, _)| *built != key)
+        {
+            let pipeline = Self::make_pipeline(gpu, &self.layout, key.0, key.1)
+                .inspect_err(|err| log::error!("Failed to build background pipeline: {err}"))
+                .ok();
+            self.pipeline = Some((key, pipeline));
+        }
+
+        let view = cubemap.map_or(&self.placeholder.view, |cubemap| &cubemap.view);
+        if self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(bound, _)| bound != view)
+        {
+            let bind_group = self.make_bind_group(&gpu.device, view);
+            self.bind_group = Some((view.clone(), bind_group));
+        }
+
+        let uniform_data = BackgroundUniform {
+            top,
+            _padding0: 0.0,
+            bottom,
+            _padding1: 0.0,
+        };
+        gpu.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform_data));
+    }
+
+    /// Draws the background into a render pass using the scene's color and depth targets.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
+        if !self.visible {
+            return;
+        }
+        let (Some((_, Some(pipeline))), Some((_, bind_group))) = (&self.pipeline, &self.bind_group)
+        else {
+            return;
+        };
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::naga;
+
+    #[test]
+    fn uniform_matches_shader() {
+        for cubemap in [false, true] {
+            let mut shader = ShaderKey::builtin("background");
+            if cubemap {
+                shader = shader.with_define("BACKGROUND_CUBEMAP");
+            }
+            let module = shader.load(false).unwrap().parse().unwrap();
+            BackgroundUniform::layout().check(&module, 1, 0).unwrap();
+        }
+    }
+
+    #[test]
+    fn cubemap_variant_compiles() {
+        let module = ShaderKey::builtin("background")
+            .with_define("BACKGROUND_CUBEMAP")
+            .load(false)
+            .unwrap()
+            .parse()
+            .unwrap();
+        for (name, stage) in [
+            ("vs_main", naga::ShaderStage::Vertex),
+            ("fs_main", naga::ShaderStage::Fragment),
+        ] {
+            assert!(
+                module
+                    .entry_points
+                    .iter()
+                    .any(|entry| entry.name == name && entry.stage == stage)
+            );
+        }
+
+        // The cube map is bound next to the uniform
+        let cube = module.global_variables.iter().find(|(_, global)| {
+            global.binding
+                == Some(naga::ResourceBinding {
+                    group: 1,
+                    binding: 1,
+                })
+        });
+        assert!(cube.is_some_and(|(_, global)| matches!(
+            module.types[global.ty].inner,
+            naga::TypeInner::Image {
+                dim: naga::ImageDimension::Cube,
+                ..
+            }
+        )));
+    }
+}
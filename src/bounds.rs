@@ -0,0 +1,73 @@
+use glam::{Mat4, Vec3};
+
+use crate::camera::Ray;
+
+/// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Smallest box containing every point, `None` without any.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds, point| {
+            Some(match bounds {
+                Some(Self { min, max }) => Self {
+                    min: min.min(point),
+                    max: max.max(point),
+                },
+                None => Self {
+                    min: point,
+                    max: point,
+                },
+            })
+        })
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Box containing this one once transformed, usually larger when rotated.
+    pub fn transform(self, xform: Mat4) -> Self {
+        let corners = (0..8).map(|idx| {
+            let pick = |bit: usize, min: f32, max: f32| if idx & bit == 0 { min } else { max };
+            xform.transform_point3(Vec3::new(
+                pick(1, self.min.x, self.max.x),
+                pick(2, self.min.y, self.max.y),
+                pick(4, self.min.z, self.max.z),
+            ))
+        });
+
+        Self::from_points(corners).unwrap()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Radius of the sphere around the box, centered on it.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).length() / 2.0
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+    }
+
+    /// Distance along the ray to where it enters the box, zero if it starts inside.
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let inverse = ray.direction.recip();
+        let t0 = (self.min - ray.origin) * inverse;
+        let t1 = (self.max - ray.origin) * inverse;
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element();
+
+        (near <= far).then_some(near)
+    }
+}
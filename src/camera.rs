@@ -1,30 +1,111 @@
-use bytemuck::NoUninit;
-use glam::{Mat4, Vec3};
+use crate::uniform::uniform_struct;
+use glam::{Mat4, Vec2, Vec3};
 use std::num::NonZero;
 
 use crate::{
+    bounds::Aabb,
     gpu::Gpu,
     object::{DataStore, Object},
+    post::PostSettings,
 };
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in radians, widened horizontally by the aspect ratio.
+    Perspective { fov: f32 },
+    /// Half the width and height of the view in world units, independent of the aspect ratio.
+    Orthographic { xmag: f32, ymag: f32 },
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32, near: f32, far: f32) -> Mat4 {
+        match *self {
+            Self::Perspective { fov } => Mat4::perspective_lh(fov, aspect, near, far),
+            Self::Orthographic { xmag, ymag } => {
+                Mat4::orthographic_lh(-xmag, xmag, -ymag, ymag, near, far)
+            }
+        }
+    }
+
+    /// Distance from the center of `bounds` at which the projection sees all
+    /// of it. Orthographic projections are scaled to fit it instead.
+    pub fn frame(&mut self, bounds: Aabb, aspect: f32, near: f32) -> f32 {
+        let radius = bounds.radius();
+        match self {
+            Self::Perspective { fov } => {
+                let horizontal_fov = 2.0 * ((*fov / 2.0).tan() * aspect).atan();
+                radius / (fov.min(horizontal_fov) / 2.0).sin()
+            }
+            Self::Orthographic { xmag, ymag } => {
+                let scale = radius / xmag.min(*ymag);
+                *xmag *= scale;
+                *ymag *= scale;
+                radius + near
+            }
+        }
+    }
+
+    /// The other kind of projection, showing the plane `distance` away at the
+    /// same size, and the distance to see that plane from with it. Switching
+    /// to a perspective projection uses the default field of view.
+    pub fn toggled(&self, distance: f32, aspect: f32) -> (Self, f32) {
+        match *self {
+            Self::Perspective { fov } => {
+                let ymag = distance * (fov / 2.0).tan();
+                let xmag = ymag * aspect;
+                (Self::Orthographic { xmag, ymag }, distance)
+            }
+            Self::Orthographic { ymag, .. } => {
+                let fov = Camera::DEFAULT_FOV;
+                (Self::Perspective { fov }, ymag / (fov / 2.0).tan())
+            }
+        }
+    }
+}
+
+/// A half line, such as the one under the cursor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Normalized.
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+}
+
 #[derive(Clone)]
 pub struct Camera {
-    pub fov: f32,
+    /// Can be switched at any time, such as for an orthographic inspection view.
+    pub projection: Projection,
     pub near: f32,
     pub far: f32,
-    pub yaw: f32,
-    pub pitch: f32,
+    pub post: PostSettings,
     uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
-#[repr(C, packed)]
-#[derive(Copy, Clone, NoUninit)]
-pub struct CameraUniform {
-    pub projection: Mat4,
-    pub view: Mat4,
-    pub camera_pos: Vec3,
-    _padding: f32,
+uniform_struct! {
+    pub struct CameraUniform {
+        pub projection: Mat4,
+        pub view: Mat4,
+        pub camera_pos: Vec3,
+        pub far: f32,
+    }
+}
+
+impl CameraUniform {
+    pub fn new(projection: Mat4, view: Mat4, camera_pos: Vec3, far: f32) -> Self {
+        Self {
+            projection,
+            view,
+            camera_pos,
+            far,
+        }
+    }
 }
 
 impl Camera {
@@ -32,7 +113,13 @@ impl Camera {
     pub const DEFAULT_NEAR: f32 = 0.01;
     pub const DEFAULT_FAR: f32 = 100.0;
 
-    pub fn new_custom(gpu: &Gpu, store: &mut DataStore, fov: f32, near: f32, far: f32) -> Object {
+    pub fn new_custom(
+        gpu: &Gpu,
+        store: &mut DataStore,
+        projection: Projection,
+        near: f32,
+        far: f32,
+    ) -> Object {
         let uniform_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Camera uniform buffer"),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
@@ -40,25 +127,9 @@ impl Camera {
             mapped_at_creation: false,
         });
 
-        let camera_uniform_layout =
-            gpu.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: "Camera uniform variables layout".into(),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
-
         let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: "Camera uniform bind group".into(),
-            layout: &camera_uniform_layout,
+            layout: &gpu.layouts.camera,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
@@ -70,12 +141,10 @@ impl Camera {
         });
 
         let camera = Self {
-            fov,
+            projection,
             near,
             far,
-            // TODO - read this from constructor
-            yaw: 0.0,
-            pitch: 0.0,
+            post: PostSettings::default(),
             uniform_buffer,
             bind_group,
         };
@@ -84,28 +153,222 @@ impl Camera {
     }
 
     pub fn new(gpu: &Gpu, store: &mut DataStore) -> Object {
+        let projection = Projection::Perspective {
+            fov: Self::DEFAULT_FOV,
+        };
+
         Self::new_custom(
             gpu,
             store,
-            Self::DEFAULT_FOV,
+            projection,
             Self::DEFAULT_NEAR,
             Self::DEFAULT_FAR,
         )
     }
 
     pub fn update_camera_uniform(&self, gpu: &Gpu, xform: glam::Mat4, ratio: f32) {
-        let uniform_data = CameraUniform {
-            projection: self.get_projection_matrix(ratio),
-            view: xform.inverse(),
-            camera_pos: xform.to_scale_rotation_translation().2,
-            _padding: Default::default(),
-        };
+        let uniform_data = CameraUniform::new(
+            self.get_projection_matrix(ratio),
+            xform.inverse(),
+            xform.to_scale_rotation_translation().2,
+            self.far,
+        );
 
         gpu.queue
             .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform_data));
     }
 
     fn get_projection_matrix(&self, ratio: f32) -> Mat4 {
-        Mat4::perspective_lh(self.fov, ratio, self.near, self.far)
+        self.projection.matrix(ratio, self.near, self.far)
+    }
+
+    /// Distance from the center of `bounds` at which the camera sees all of it.
+    /// Orthographic projections are also scaled to fit it, keeping their aspect ratio.
+    pub fn frame(&mut self, bounds: Aabb, aspect: f32) -> f32 {
+        self.projection.frame(bounds, aspect, self.near)
+    }
+
+    fn view_projection(&self, xform: Mat4, viewport: Vec2) -> Mat4 {
+        self.get_projection_matrix(viewport.x / viewport.y) * xform.inverse()
+    }
+
+    /// Position in pixels from the top left of the viewport of a world space
+    /// point, with its depth from 0 at the near plane to 1 at the far plane.
+    /// `None` for points closer than the near plane.
+    pub fn project(&self, xform: Mat4, viewport: Vec2, point: Vec3) -> Option<Vec3> {
+        project(self.view_projection(xform, viewport), viewport, point)
+    }
+
+    /// World space ray through a pixel of the viewport, starting on the near plane.
+    pub fn unproject(&self, xform: Mat4, viewport: Vec2, pixel: Vec2) -> Ray {
+        unproject(self.view_projection(xform, viewport), viewport, pixel)
+    }
+}
+
+fn project(view_projection: Mat4, viewport: Vec2, point: Vec3) -> Option<Vec3> {
+    let clip = view_projection * point.extend(1.0);
+    if clip.w <= 0.0 || clip.z < 0.0 {
+        return None;
+    }
+
+    let ndc = clip.truncate() / clip.w;
+    let pixel = Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) / 2.0 * viewport;
+    Some(pixel.extend(ndc.z))
+}
+
+fn unproject(view_projection: Mat4, viewport: Vec2, pixel: Vec2) -> Ray {
+    let inverse = view_projection.inverse();
+
+    let ndc = pixel / viewport * 2.0 - 1.0;
+    let ndc = Vec2::new(ndc.x, -ndc.y);
+    let near = inverse.project_point3(ndc.extend(0.0));
+    let far = inverse.project_point3(ndc.extend(1.0));
+
+    Ray {
+        origin: near,
+        direction: (far - near).normalize(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    const VIEWPORT: Vec2 = Vec2::new(800.0, 600.0);
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    fn projections() -> [Projection; 2] {
+        [
+            Projection::Perspective {
+                fov: Camera::DEFAULT_FOV,
+            },
+            Projection::Orthographic {
+                xmag: 4.0,
+                ymag: 3.0,
+            },
+        ]
+    }
+
+    fn view_projection(projection: Projection, xform: Mat4) -> Mat4 {
+        projection.matrix(VIEWPORT.x / VIEWPORT.y, NEAR, FAR) * xform.inverse()
+    }
+
+    fn looking_at(position: Vec3, target: Vec3) -> Mat4 {
+        Object::empty()
+            .with_translation(position)
+            .with_look_at(target, Vec3::Y)
+            .get_local_xform()
+    }
+
+    #[test]
+    fn look_at_points_z_at_target() {
+        let position = Vec3::new(1.0, 2.0, -3.0);
+        for target in [
+            Vec3::ZERO,
+            Vec3::new(5.0, -1.0, 2.0),
+            Vec3::new(1.0, 10.0, -3.0),
+        ] {
+            let xform = looking_at(position, target);
+            let forward = xform.transform_vector3(Vec3::Z);
+            assert!(forward.abs_diff_eq((target - position).normalize(), 1e-5));
+            assert!(
+                xform
+                    .transform_point3(Vec3::ZERO)
+                    .abs_diff_eq(position, 1e-5)
+            );
+        }
+    }
+
+    #[test]
+    fn project_unproject_round_trip() {
+        let xform = looking_at(Vec3::new(2.0, 3.0, -8.0), Vec3::ZERO);
+        for projection in projections() {
+            let view_projection = view_projection(projection, xform);
+            for point in [
+                Vec3::ZERO,
+                Vec3::new(0.5, -0.3, 1.0),
+                Vec3::new(-1.0, 1.0, -2.0),
+            ] {
+                let projected = project(view_projection, VIEWPORT, point).unwrap();
+                assert!((0.0..=1.0).contains(&projected.z));
+
+                let ray = unproject(view_projection, VIEWPORT, projected.truncate());
+                let distance = (point - ray.origin).dot(ray.direction);
+                assert!(ray.at(distance).abs_diff_eq(point, 1e-3), "{projection:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn points_behind_are_not_projected() {
+        let xform = looking_at(Vec3::new(0.0, 0.0, -5.0), Vec3::ZERO);
+        for projection in projections() {
+            let view_projection = view_projection(projection, xform);
+            assert!(project(view_projection, VIEWPORT, Vec3::new(0.0, 0.0, -10.0)).is_none());
+        }
+    }
+
+    #[test]
+    fn frame_fits_bounds() {
+        let bounds = Aabb {
+            min: Vec3::new(-1.0, -0.5, -2.0),
+            max: Vec3::new(3.0, 1.5, 0.0),
+        };
+        let corners = (0..8).map(|idx| {
+            Vec3::select(
+                glam::BVec3::new(idx & 1 != 0, idx & 2 != 0, idx & 4 != 0),
+                bounds.max,
+                bounds.min,
+            )
+        });
+        let direction = Vec3::new(1.0, -1.0, 2.0).normalize();
+
+        // Both wider than high and higher than wide
+        for viewport in [VIEWPORT, Vec2::new(VIEWPORT.y, VIEWPORT.x)] {
+            let aspect = viewport.x / viewport.y;
+            for mut projection in projections() {
+                let distance = projection.frame(bounds, aspect, NEAR);
+                let xform = looking_at(bounds.center() - direction * distance, bounds.center());
+                let view_projection = projection.matrix(aspect, NEAR, FAR) * xform.inverse();
+
+                for corner in corners.clone() {
+                    let pixel = project(view_projection, viewport, corner).unwrap();
+                    assert!(pixel.cmpge(Vec3::ZERO).all(), "{projection:?} {pixel}");
+                    assert!(
+                        pixel.truncate().cmple(viewport).all(),
+                        "{projection:?} {pixel}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn toggled_projections_keep_the_pivot_plane_size() {
+        let aspect = VIEWPORT.x / VIEWPORT.y;
+        let direction = Vec3::new(1.0, -0.5, 2.0).normalize();
+        let pivot = Vec3::new(0.5, 1.0, -1.0);
+        // On the plane through the pivot facing the camera
+        let point = pivot + direction.any_orthonormal_vector() * 1.5;
+
+        let perspective = Projection::Perspective {
+            fov: Camera::DEFAULT_FOV,
+        };
+        let (orthographic, distance) = perspective.toggled(6.0, aspect);
+        assert!(matches!(orthographic, Projection::Orthographic { .. }));
+        let (toggled_back, toggled_distance) = orthographic.toggled(distance, aspect);
+        assert_eq!(toggled_back, perspective);
+        assert!((toggled_distance - 6.0).abs() < 1e-4);
+
+        let pixels =
+            [(perspective, 6.0), (orthographic, distance)].map(|(projection, distance)| {
+                let xform = looking_at(pivot - direction * distance, pivot);
+                let pivot = project(view_projection(projection, xform), VIEWPORT, pivot).unwrap();
+                let point = project(view_projection(projection, xform), VIEWPORT, point).unwrap();
+                (point - pivot).truncate()
+            });
+        assert!(pixels[0].abs_diff_eq(pixels[1], 1e-2), "{pixels:?}");
     }
 }
@@ -0,0 +1,290 @@
+use anyhow::{Result, anyhow};
+use glam::Vec3;
+use std::path::Path;
+
+use crate::{gpu::Gpu, pipeline::PipelineCache, shader::ShaderKey};
+
+/// A cube texture, such as an environment map.
+#[derive(Debug)]
+pub struct Cubemap {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl Cubemap {
+    pub const STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const WORKGROUP_SIZE: u32 = 8;
+
+    fn make_texture(
+        device: &wgpu::Device,
+        label: &str,
+        size: u32,
+        mip_level_count: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: label.into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            format,
+            usage: usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    fn from_texture(texture: wgpu::Texture) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+
+        Self { texture, view }
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.texture.mip_level_count()
+    }
+
+    /// A single linear color in every direction, such as a placeholder for a missing cube map.
+    pub fn solid(gpu: &Gpu, color: Vec3) -> Self {
+        let texture = Self::make_texture(
+            &gpu.device,
+            "Solid cube map",
+            1,
+            1,
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::COPY_DST,
+        );
+        let texel = color
+            .extend(1.0)
+            .to_array()
+            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8);
+        for layer in 0..6 {
+            Self::write_face(gpu, &texture, layer, 1, &texel);
+        }
+
+        Self::from_texture(texture)
+    }
+
+    /// An uninitialized half float cube map, written to by compute shaders.
+    pub fn new_storage(gpu: &Gpu, label: &str, size: u32, mip_level_count: u32) -> Self {
+        let texture = Self::make_texture(
+            &gpu.device,
+            label,
+            size,
+            mip_level_count,
+            Self::STORAGE_FORMAT,
+            wgpu::TextureUsages::STORAGE_BINDING,
+        );
+
+        Self::from_texture(texture)
+    }
+
+    /// One mip level as a 2D array with a layer per face, to bind as a storage texture.
+    pub fn face_view(&self, mip_level: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    fn write_face(gpu: &Gpu, texture: &wgpu::Texture, layer: u32, size: u32, texels: &[u8]) {
+        gpu.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            texels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size * 4),
+                rows_per_image: Some(size),
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Loads six square sRGB images of the same size, in +X, -X, +Y, -Y, +Z, -Z order.
+    pub fn load_faces(gpu: &Gpu, paths: [&Path; 6]) -> Result<Self> {
+        let faces = paths
+            .iter()
+            .map(|path| {
+                image::open(path)
+                    .map(|image| image.to_rgba8())
+                    .map_err(|err| {
+                        anyhow!("Failed to load cube map face {}: {err}", path.display())
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let size = faces[0].width();
+        for (face, path) in faces.iter().zip(paths) {
+            if face.dimensions() != (size, size) {
+                return Err(anyhow!(
+                    "Cube map face {} is {}x{}, expected {size}x{size}",
+                    path.display(),
+                    face.width(),
+                    face.height()
+                ));
+            }
+        }
+
+        let texture = Self::make_texture(
+            &gpu.device,
+            "Cube map",
+            size,
+            1,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureUsages::COPY_DST,
+        );
+        for (layer, face) in faces.iter().enumerate() {
+            Self::write_face(gpu, &texture, layer as u32, size, face);
+        }
+
+        Ok(Self::from_texture(texture))
+    }
+
+    /// Loads an equirectangular panorama, usually an `.hdr` file, and projects
+    /// it onto a cube map with faces of `size` texels on the GPU.
+    pub fn load_equirect(gpu: &mut Gpu, path: &Path, size: u32) -> Result<Self> {
+        let panorama = image::open(path)
+            .map_err(|err| anyhow!("Failed to load panorama {}: {err}", path.display()))?
+            .into_rgba32f();
+        let extent = wgpu::Extent3d {
+            width: panorama.width(),
+            height: panorama.height(),
+            depth_or_array_layers: 1,
+        };
+
+        let equirect = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: "Equirectangular panorama".into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        gpu.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &equirect,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(panorama.as_raw()),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(extent.width * 16),
+                rows_per_image: Some(extent.height),
+            },
+            extent,
+        );
+
+        let cubemap = Self::new_storage(gpu, "Environment cube map", size, 1);
+
+        let layout = gpu
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: "Equirect to cube bind group layout".into(),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: Self::STORAGE_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Equirect to cube bind group".into(),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &equirect.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.face_view(0)),
+                },
+            ],
+        });
+
+        let shader = ShaderKey::builtin("cubemap");
+        let module = gpu.pipelines.get_shader(&gpu.device, &shader)?;
+        let pipeline_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Equirect to cube pipeline layout"),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = PipelineCache::with_validation(&gpu.device, || {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("equirect_to_cube"),
+                    layout: Some(&pipeline_layout),
+                    module: &module,
+                    entry_point: Some("equirect_to_cube"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    cache: None,
+                })
+        })?;
+
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Equirect to cube encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Equirect to Cube Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            let groups = size.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups, groups, 6);
+        }
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(cubemap)
+    }
+}
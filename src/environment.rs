@@ -0,0 +1,298 @@
+use anyhow::Result;
+use glam::Vec3;
+
+use crate::{
+    cubemap::Cubemap,
+    gpu::Gpu,
+    pipeline::PipelineCache,
+    shader::ShaderKey,
+    uniform::{Uniform, uniform_struct},
+};
+
+uniform_struct! {
+    struct PrefilterUniform {
+        roughness: f32,
+    }
+}
+
+/// Ambient light of a scene, as image based lighting precomputed from an
+/// environment map.
+#[derive(Debug)]
+pub struct Environment {
+    pub irradiance: Cubemap,
+    /// Mip levels are prefiltered for increasing roughness, from 0 to 1.
+    pub specular: Cubemap,
+    pub brdf_lut: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    const IRRADIANCE_SIZE: u32 = 32;
+    const SPECULAR_SIZE: u32 = 128;
+    const SPECULAR_MIPS: u32 = 5;
+    const BRDF_LUT_SIZE: u32 = 256;
+
+    fn make_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: "Environment sampler".into(),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+    }
+
+    fn make_brdf_lut(device: &wgpu::Device, size: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: "BRDF LUT".into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            format: Cubemap::STORAGE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    /// Flat light of a single linear color from every direction, without reflections.
+    pub fn ambient(gpu: &Gpu, color: Vec3) -> Self {
+        // An empty LUT scales the reflections down to nothing
+        let brdf_lut = Self::make_brdf_lut(&gpu.device, 1);
+
+        Self {
+            irradiance: Cubemap::solid(gpu, color),
+            specular: Cubemap::solid(gpu, Vec3::ZERO),
+            brdf_lut: brdf_lut.create_view(&wgpu::TextureViewDescriptor::default()),
+            sampler: Self::make_sampler(&gpu.device),
+        }
+    }
+
+    fn make_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Environment bake bind group layout".into(),
+            entries: &[
+                entry(
+                    0,
+                    wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                ),
+                entry(
+                    1,
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                ),
+                entry(
+                    2,
+                    wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Cubemap::STORAGE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                ),
+                entry(
+                    3,
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                ),
+                entry(
+                    4,
+                    wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Cubemap::STORAGE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                ),
+            ],
+        })
+    }
+
+    fn make_pipelines(
+        gpu: &mut Gpu,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Result<[wgpu::ComputePipeline; 3]> {
+        let shader = ShaderKey::builtin("ibl");
+        let module = gpu.pipelines.get_shader(&gpu.device, &shader)?;
+        if let Some(naga_module) = gpu.pipelines.module(&shader) {
+            PrefilterUniform::layout().check(naga_module, 0, 3)?;
+        }
+        let pipeline_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Environment bake pipeline layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+        let make = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&pipeline_layout),
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    cache: None,
+                })
+        };
+
+        PipelineCache::with_validation(&gpu.device, || {
+            [make("irradiance"), make("prefilter"), make("brdf_lut")]
+        })
+    }
+
+    /// Precomputes the lighting of an environment map on the GPU: diffuse
+    /// irradiance, specular reflections prefiltered by roughness and the BRDF
+    /// lookup table they're combined with.
+    pub fn bake(gpu: &mut Gpu, source: &Cubemap) -> Result<Self> {
+        let layout = Self::make_layout(&gpu.device);
+        let [irradiance_pipeline, prefilter_pipeline, brdf_pipeline] =
+            Self::make_pipelines(gpu, &layout)?;
+
+        let sampler = Self::make_sampler(&gpu.device);
+        let irradiance = Cubemap::new_storage(gpu, "Irradiance map", Self::IRRADIANCE_SIZE, 1);
+        let specular = Cubemap::new_storage(
+            gpu,
+            "Prefiltered specular map",
+            Self::SPECULAR_SIZE,
+            Self::SPECULAR_MIPS,
+        );
+        let brdf_lut = Self::make_brdf_lut(&gpu.device, Self::BRDF_LUT_SIZE);
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Every dispatch gets the same bindings, but its own output and roughness
+        let make_bind_group = |output: &Cubemap, mip_level: u32| {
+            let roughness = mip_level as f32 / (output.mip_level_count() - 1).max(1) as f32;
+            let uniform_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Prefilter uniform buffer"),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                size: size_of::<PrefilterUniform>() as u64,
+                mapped_at_creation: false,
+            });
+            gpu.queue.write_buffer(
+                &uniform_buffer,
+                0,
+                bytemuck::bytes_of(&PrefilterUniform { roughness }),
+            );
+
+            gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: "Environment bake bind group".into(),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&output.face_view(mip_level)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&brdf_lut_view),
+                    },
+                ],
+            })
+        };
+        let groups = |size: u32| size.div_ceil(Cubemap::WORKGROUP_SIZE);
+
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Environment bake encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Environment Bake Pass"),
+                timestamp_writes: None,
+            });
+
+            compute_pass.set_pipeline(&irradiance_pipeline);
+            compute_pass.set_bind_group(0, &make_bind_group(&irradiance, 0), &[]);
+            let irradiance_groups = groups(Self::IRRADIANCE_SIZE);
+            compute_pass.dispatch_workgroups(irradiance_groups, irradiance_groups, 6);
+
+            compute_pass.set_pipeline(&prefilter_pipeline);
+            for mip_level in 0..Self::SPECULAR_MIPS {
+                compute_pass.set_bind_group(0, &make_bind_group(&specular, mip_level), &[]);
+                let mip_groups = groups(Self::SPECULAR_SIZE >> mip_level);
+                compute_pass.dispatch_workgroups(mip_groups, mip_groups, 6);
+            }
+
+            compute_pass.set_pipeline(&brdf_pipeline);
+            compute_pass.set_bind_group(0, &make_bind_group(&irradiance, 0), &[]);
+            let lut_groups = groups(Self::BRDF_LUT_SIZE);
+            compute_pass.dispatch_workgroups(lut_groups, lut_groups, 1);
+        }
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(Self {
+            irradiance,
+            specular,
+            brdf_lut: brdf_lut_view,
+            sampler,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_shader() {
+        let module = ShaderKey::builtin("ibl")
+            .load(false)
+            .unwrap()
+            .parse()
+            .unwrap();
+        PrefilterUniform::layout().check(&module, 0, 3).unwrap();
+    }
+
+    #[test]
+    fn bake_entry_points_compile() {
+        let module = ShaderKey::builtin("ibl")
+            .load(false)
+            .unwrap()
+            .parse()
+            .unwrap();
+        for name in ["irradiance", "prefilter", "brdf_lut"] {
+            let entry = module
+                .entry_points
+                .iter()
+                .find(|entry| entry.name == name)
+                .unwrap_or_else(|| panic!("Missing entry point {name}"));
+            assert_eq!(entry.stage, wgpu::naga::ShaderStage::Compute);
+            assert_eq!(
+                entry.workgroup_size,
+                [Cubemap::WORKGROUP_SIZE, Cubemap::WORKGROUP_SIZE, 1]
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{input::UserInput, scene::Scene, timestep::Steps};
+
+/// First person camera, moved around with keys and turned with the mouse.
+pub struct FlyController {
+    /// Top speed in units per second, changed with the mouse wheel.
+    pub speed: f32,
+    velocity: Vec3,
+    // Simulated positions around the frame, and where the camera was left
+    previous_position: Vec3,
+    position: Vec3,
+    shown_position: Option<Vec3>,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            speed: Self::DEFAULT_SPEED,
+            velocity: Vec3::ZERO,
+            previous_position: Vec3::ZERO,
+            position: Vec3::ZERO,
+            shown_position: None,
+        }
+    }
+}
+
+impl FlyController {
+    const DEFAULT_SPEED: f32 = 10.0;
+    const MIN_SPEED: f32 = 0.1;
+    const MAX_SPEED: f32 = 1000.0;
+    // Speed change per wheel step, and while boosting
+    const SPEED_FACTOR: f32 = 1.2;
+    const BOOST_FACTOR: f32 = 4.0;
+    // How quickly the velocity catches up with the keys held, per second
+    const ACCELERATION: f32 = 10.0;
+    const DAMPING: f32 = 6.0;
+    // Radians per unit of mouse motion
+    const LOOK_SPEED: f32 = 0.0025;
+    // Just short of straight up or down, where the yaw would be lost
+    const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+    /// Turns the active camera, then moves it by the steps due.
+    /// The camera is left between the last two steps, so that it moves
+    /// smoothly whether frames are shorter or longer than steps.
+    pub fn update(&mut self, scene: &mut Scene, input: UserInput, steps: Steps) {
+        let Some(camera) = scene.get_camera_object() else {
+            return;
+        };
+        let (scale, _, position) = camera.get_local_xform().to_scale_rotation_translation();
+
+        // Starts over from wherever the camera was moved by anything else
+        if self.shown_position != Some(position) {
+            self.previous_position = position;
+            self.position = position;
+        }
+
+        // Yaw and pitch are read back from the camera, so that it can be
+        // placed or turned by anything else too
+        let forward = camera
+            .get_local_xform()
+            .transform_vector3(Vec3::Z)
+            .normalize();
+        let yaw = forward.x.atan2(forward.z) + input.yaw * Self::LOOK_SPEED;
+        let pitch = ((-forward.y).asin() + input.pitch * Self::LOOK_SPEED)
+            .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        let rotation = Quat::from_rotation_y(yaw) * Quat::from_rotation_x(pitch);
+
+        self.speed = (self.speed * Self::SPEED_FACTOR.powf(input.zoom))
+            .clamp(Self::MIN_SPEED, Self::MAX_SPEED);
+        let speed = if input.boost {
+            self.speed * Self::BOOST_FACTOR
+        } else {
+            self.speed
+        };
+
+        // Eases towards the target velocity
+        let direction = input.direction();
+        let target = rotation * direction * speed;
+        let rate = if direction == Vec3::ZERO {
+            Self::DAMPING
+        } else {
+            Self::ACCELERATION
+        };
+        let blend = 1.0 - (-rate * steps.step).exp();
+        for _ in 0..steps.count {
+            self.velocity = self.velocity.lerp(target, blend);
+            self.previous_position = self.position;
+            self.position += self.velocity * steps.step;
+        }
+
+        let position = self.previous_position.lerp(self.position, steps.alpha);
+        self.shown_position = Some(position);
+        camera.set_xform(Mat4::from_scale_rotation_translation(
+            scale, rotation, position,
+        ));
+    }
+}
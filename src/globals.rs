@@ -1,21 +1,98 @@
+use crate::environment::Environment;
 use crate::gpu::Gpu;
-use bytemuck::NoUninit;
+use crate::light::Lights;
+use crate::shadow::ShadowMaps;
+use crate::uniform::uniform_struct;
+use glam::Vec2;
 use std::num::NonZero;
+use std::time::Instant;
 
+/// Per frame state shared with every shader.
 pub struct Globals {
-    begin: std::time::Instant,
+    begin: Instant,
+    last_update: Option<Instant>,
+    uniform_data: GlobalsUniform,
     globals_uniform: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    // Same as the above, minus the shadow maps which can't be bound while rendering them
+    pub shadow_pass_bind_group: wgpu::BindGroup,
 }
 
-#[repr(C, packed)]
-#[derive(Copy, Clone, NoUninit)]
-struct GlobalsUniform {
-    pub time: f32,
+uniform_struct! {
+    pub struct GlobalsUniform {
+        pub time: f32,
+        pub delta_time: f32,
+        pub frame: u32,
+        _padding: u32,
+        pub resolution: Vec2,
+        pub mouse: Vec2,
+    }
 }
 
 impl Globals {
-    pub fn new(gpu: &Gpu) -> Self {
+    fn make_bind_group(
+        gpu: &Gpu,
+        globals_uniform: &wgpu::Buffer,
+        lights: &Lights,
+        shadow_maps: &wgpu::TextureView,
+        point_shadow_maps: &wgpu::TextureView,
+        shadow_sampler: &wgpu::Sampler,
+        environment: &Environment,
+    ) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Global uniform bind group".into(),
+            layout: &gpu.layouts.frame,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: globals_uniform,
+                        offset: 0,
+                        size: NonZero::new(size_of::<GlobalsUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lights.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(shadow_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(shadow_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(point_shadow_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&environment.irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&environment.specular.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&environment.brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn new(
+        gpu: &Gpu,
+        lights: &Lights,
+        shadows: &ShadowMaps,
+        environment: &Environment,
+    ) -> Self {
         let globals_uniform = gpu.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Globals uniform buffer"),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
@@ -23,52 +100,114 @@ impl Globals {
             mapped_at_creation: false,
         });
 
-        let globals_uniform_layout =
-            gpu.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: "Global uniform variables layout".into(),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
-
-        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: "Global uniform bind group".into(),
-            layout: &globals_uniform_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &globals_uniform,
-                    offset: 0,
-                    size: NonZero::new(size_of::<GlobalsUniform>() as u64),
-                }),
-            }],
-        });
+        let bind_group = Self::make_bind_group(
+            gpu,
+            &globals_uniform,
+            lights,
+            &shadows.view,
+            &shadows.point_view,
+            &shadows.sampler,
+            environment,
+        );
+        let shadow_pass_bind_group = Self::make_bind_group(
+            gpu,
+            &globals_uniform,
+            lights,
+            &shadows.placeholder,
+            &shadows.point_placeholder,
+            &shadows.sampler,
+            environment,
+        );
 
-        let begin = std::time::Instant::now();
+        let begin = Instant::now();
 
         Self {
             begin,
+            last_update: None,
+            uniform_data: GlobalsUniform {
+                time: 0.0,
+                delta_time: 0.0,
+                frame: 0,
+                _padding: 0,
+                resolution: Vec2::ZERO,
+                mouse: Vec2::ZERO,
+            },
             globals_uniform,
             bind_group,
+            shadow_pass_bind_group,
         }
     }
 
+    /// Seconds since startup.
+    pub fn time(&self) -> f32 {
+        self.uniform_data.time
+    }
+
+    /// Seconds since the previous frame, zero on the first one.
+    pub fn delta_time(&self) -> f32 {
+        self.uniform_data.delta_time
+    }
+
+    /// Index of the current frame, starting at zero.
+    pub fn frame(&self) -> u32 {
+        self.uniform_data.frame
+    }
+
+    /// Size of the render surface in pixels.
+    pub fn resolution(&self) -> Vec2 {
+        self.uniform_data.resolution
+    }
+
+    /// Cursor position in pixels, relative to the top left of the window.
+    pub fn mouse(&self) -> Vec2 {
+        self.uniform_data.mouse
+    }
+
+    pub fn set_mouse(&mut self, position: Vec2) {
+        self.uniform_data.mouse = position;
+    }
+
+    /// Rebuilds the bind groups around another environment.
+    pub fn set_environment(
+        &mut self,
+        gpu: &Gpu,
+        lights: &Lights,
+        shadows: &ShadowMaps,
+        environment: &Environment,
+    ) {
+        self.bind_group = Self::make_bind_group(
+            gpu,
+            &self.globals_uniform,
+            lights,
+            &shadows.view,
+            &shadows.point_view,
+            &shadows.sampler,
+            environment,
+        );
+        self.shadow_pass_bind_group = Self::make_bind_group(
+            gpu,
+            &self.globals_uniform,
+            lights,
+            &shadows.placeholder,
+            &shadows.point_placeholder,
+            &shadows.sampler,
+            environment,
+        );
+    }
+
     pub fn update_globals(&mut self, gpu: &Gpu) {
-        let uniform_data = GlobalsUniform {
-            time: std::time::Instant::now()
-                .duration_since(self.begin)
-                .as_secs_f32(),
-        };
+        let now = Instant::now();
+        let uniform_data = &mut self.uniform_data;
+
+        if let Some(last_update) = self.last_update {
+            uniform_data.frame = uniform_data.frame.wrapping_add(1);
+            uniform_data.delta_time = now.duration_since(last_update).as_secs_f32();
+        }
+        uniform_data.time = now.duration_since(self.begin).as_secs_f32();
+        uniform_data.resolution = Vec2::new(gpu.config.width as f32, gpu.config.height as f32);
+        self.last_update = Some(now);
 
         gpu.queue
-            .write_buffer(&self.globals_uniform, 0, bytemuck::bytes_of(&uniform_data));
+            .write_buffer(&self.globals_uniform, 0, bytemuck::bytes_of(uniform_data));
     }
 }
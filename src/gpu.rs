@@ -1,19 +1,28 @@
-use anyhow::Result;
-use std::cell::{RefCell, RefMut};
-use std::collections::HashMap;
-use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::{
+    layouts::BindGroupLayouts,
+    pipeline::{PipelineCache, RenderTargets},
+    render_graph::{RenderGraph, ResourceId, TextureDesc, TexturePool},
+};
+
 pub struct Gpu {
     window: Arc<Window>,
     surface: wgpu::Surface<'static>,
-    depth: wgpu::TextureView,
+    // Kept to query the surface again when the window changes
+    adapter: wgpu::Adapter,
+    sample_count: u32,
 
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
-    // TODO - replace refcell with mut reference to gpu
-    pub render_pipelines: RefCell<HashMap<String, wgpu::RenderPipeline>>,
+    pub pipelines: PipelineCache,
+    pub layouts: BindGroupLayouts,
 }
 
 impl Gpu {
@@ -28,16 +37,14 @@ impl Gpu {
         size: PhysicalSize<u32>,
     ) -> wgpu::SurfaceConfiguration {
         let capabilities = surface.get_capabilities(&adapter);
+        let surface_format = Self::surface_format(&capabilities);
 
-        let surface_format = capabilities
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(capabilities.formats[0]);
+        // Copying out is only needed for screenshots, where supported
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (capabilities.usages & wgpu::TextureUsages::COPY_SRC);
 
         wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -48,6 +55,15 @@ impl Gpu {
         }
     }
 
+    fn surface_format(capabilities: &wgpu::SurfaceCapabilities) -> wgpu::TextureFormat {
+        capabilities
+            .formats
+            .iter()
+            .find(|f| f.is_srgb())
+            .copied()
+            .unwrap_or(capabilities.formats[0])
+    }
+
     fn get_limits() -> wgpu::Limits {
         let mut limits = wgpu::Limits::defaults();
         limits.max_vertex_attributes = 5;
@@ -89,34 +105,8 @@ impl Gpu {
         Ok((device, queue))
     }
 
-    fn make_depth_texture(
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        format: &wgpu::TextureFormat,
-    ) -> (wgpu::Texture, wgpu::TextureView) {
-        let sz = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
-            depth_or_array_layers: 1,
-        };
-
-        let texture_desc = wgpu::TextureDescriptor {
-            label: "Depth Texture".into(),
-            dimension: wgpu::TextureDimension::D2,
-            size: sz,
-            mip_level_count: 1,
-            sample_count: 1,
-            format: format.clone(),
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        };
-
-        let texture = device.create_texture(&texture_desc);
-
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        (texture, view)
-    }
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
     pub async fn new(window: Window, size: PhysicalSize<u32>) -> Result<Self> {
         let window = Arc::new(window);
@@ -130,78 +120,262 @@ impl Gpu {
         let config = Self::get_config(&adapter, &surface, size);
         surface.configure(&device, &config);
 
-        let (_, depth) =
-            Self::make_depth_texture(&device, &config, &wgpu::TextureFormat::Depth24Plus);
+        let layouts = BindGroupLayouts::new(&device);
 
         Ok(Self {
             window,
             surface,
-            depth,
+            adapter,
+            sample_count: 1,
             device,
             queue,
             config,
-            render_pipelines: Default::default(),
+            pipelines: Default::default(),
+            layouts,
         })
     }
 
-    pub fn get_render_pipelines(&self) -> RefMut<'_, HashMap<String, wgpu::RenderPipeline>> {
-        self.render_pipelines.borrow_mut()
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn render_targets(&self) -> RenderTargets {
+        RenderTargets {
+            color_format: Some(Self::HDR_FORMAT),
+            depth_format: Some(Self::DEPTH_FORMAT),
+            sample_count: self.sample_count,
+        }
+    }
+
+    fn reconfigure(&mut self) {
+        self.surface.configure(&self.device, &self.config);
     }
 
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
         self.config.width = size.width;
         self.config.height = size.height;
+
+        // The window may have moved to a display supporting other formats
+        let capabilities = self.surface.get_capabilities(&self.adapter);
+        if capabilities.formats.contains(&self.config.format) {
+            self.reconfigure();
+        } else {
+            self.set_surface_format(Self::surface_format(&capabilities));
+        }
     }
 
-    pub fn render(&self, mut set_render_pass: impl FnMut(&mut wgpu::RenderPass)) -> Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn set_surface_format(&mut self, format: wgpu::TextureFormat) {
+        if self.config.format != format {
+            self.config.format = format;
+            self.reconfigure();
+            self.pipelines.invalidate();
+        }
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if self.sample_count != sample_count {
+            self.sample_count = sample_count;
+            self.pipelines.invalidate();
+        }
+    }
+
+    fn target_desc(&self, format: wgpu::TextureFormat, sample_count: u32) -> TextureDesc {
+        TextureDesc {
+            width: self.config.width,
+            height: self.config.height,
+            format,
+            sample_count,
+        }
+    }
+
+    /// Adds the opaque and transparent passes, rendering the scene into an
+    /// HDR texture which is returned. `reads` are textures sampled by the
+    /// scene, such as the shadow maps.
+    pub fn add_scene_passes<'a>(
+        &self,
+        graph: &mut RenderGraph<'a>,
+        reads: &[ResourceId],
+        clear_color: wgpu::Color,
+        opaque_pass: impl FnOnce(&mut wgpu::RenderPass) + 'a,
+        transparent_pass: impl FnOnce(&mut wgpu::RenderPass) + 'a,
+    ) -> ResourceId {
+        let hdr = graph.create_texture("HDR", self.target_desc(Self::HDR_FORMAT, 1));
+        let depth = graph.create_texture(
+            "Depth",
+            self.target_desc(Self::DEPTH_FORMAT, self.sample_count),
+        );
+        // With MSAA the passes render into a multisampled texture, and the
+        // last one resolves it into the HDR texture
+        let msaa = (self.sample_count > 1).then(|| {
+            graph.create_texture(
+                "Multisampled color",
+                self.target_desc(Self::HDR_FORMAT, self.sample_count),
+            )
+        });
+        let color = msaa.unwrap_or(hdr);
 
-        {
-            let bg_rgb = [0, 0, 0]
-                .map(|x| x as f64 / 255.0) // Normalize
-                .map(|x| x.powf(2.2)); // Convert to sRGB
-
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    depth_slice: None,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: bg_rgb[0],
-                            g: bg_rgb[1],
-                            b: bg_rgb[2],
-                            a: 1.0,
+        let opaque = reads
+            .iter()
+            .fold(graph.add_pass("Opaque"), |pass, id| pass.read(*id));
+        opaque.write(color).write(depth).execute(move |context| {
+            let mut render_pass = context
+                .encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Opaque Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: context.view(color),
+                        depth_slice: None,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(clear_color),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: context.view(depth),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
                         }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
+                        stencil_ops: None,
                     }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+
+            opaque_pass(&mut render_pass);
+        });
+
+        // Transparent geometry is tested against, but never written to the depth buffer
+        let transparent = reads
+            .iter()
+            .fold(graph.add_pass("Transparent"), |pass, id| pass.read(*id));
+        let transparent = match msaa {
+            Some(msaa) => transparent.read(msaa).write(msaa).write(hdr),
+            None => transparent.read(hdr).write(hdr),
+        };
+        transparent.read(depth).execute(move |context| {
+            let resolve_target = msaa.map(|_| context.view(hdr));
+            let mut render_pass = context
+                .encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparent Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: context.view(color),
+                        depth_slice: None,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: context.view(depth),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Discard,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+
+            transparent_pass(&mut render_pass);
+        });
 
-            set_render_pass(&mut render_pass);
+        hdr
+    }
+
+    // Reads the finished frame back and saves it as an image, stalling until
+    // the GPU is done with it
+    fn save_screenshot(&self, texture: &wgpu::Texture, path: &Path) -> Result<()> {
+        if !self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(anyhow!("The surface can't be copied from"));
         }
+        let bgra = match texture.format().remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            format => return Err(anyhow!("Unsupported surface format {format:?}")),
+        };
+
+        let (width, height) = (texture.width(), texture.height());
+        let padded_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: (padded_row * height) as u64,
+            mapped_at_creation: false,
+        });
 
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screenshot encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
         self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::Wait)?;
+
+        let pixels: Vec<u8> = slice
+            .get_mapped_range()
+            .chunks(padded_row as usize)
+            .flat_map(|row| row[..width as usize * 4].chunks(4))
+            .flat_map(|texel| {
+                if bgra {
+                    [texel[2], texel[1], texel[0], 255]
+                } else {
+                    [texel[0], texel[1], texel[2], 255]
+                }
+            })
+            .collect();
+        image::RgbaImage::from_raw(width, height, pixels)
+            .unwrap()
+            .save(path)
+            .map_err(|err| anyhow!("Failed to save screenshot {}: {err}", path.display()))
+    }
+
+    /// Builds this frame's render graph around the swapchain texture, then
+    /// executes and presents it, saving it to `screenshot` first if given.
+    pub fn render<'a>(
+        &self,
+        pool: &mut TexturePool,
+        screenshot: &Option<PathBuf>,
+        build: impl FnOnce(&mut RenderGraph<'a>, ResourceId),
+    ) -> Result<()> {
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut graph = RenderGraph::default();
+        let swapchain = graph.import_texture("Swapchain", &view);
+        build(&mut graph, swapchain);
+        graph.execute(self, pool)?;
+        if let Some(path) = screenshot {
+            match self.save_screenshot(&output.texture, path) {
+                Ok(()) => log::info!("Saved screenshot {}", path.display()),
+                Err(err) => log::error!("Failed to take screenshot: {err}"),
+            }
+        }
         output.present();
 
         self.window.request_redraw();
@@ -0,0 +1,579 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Result, anyhow};
+use glam::{Vec2, Vec3};
+use strum::EnumString;
+use winit::{
+    event::{ElementState, KeyEvent, MouseButton},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+/// A key or mouse button.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// Buttons held down, those pressed or released since the last frame, and
+/// how far the mouse and wheel moved.
+#[derive(Default)]
+pub struct InputState {
+    held: HashSet<Button>,
+    pressed: HashSet<Button>,
+    released: HashSet<Button>,
+    /// In pixels, or unaccelerated device units where the platform has them.
+    pub mouse_motion: Vec2,
+    /// In lines, up is positive.
+    pub wheel: f32,
+}
+
+impl InputState {
+    pub fn handle_key(&mut self, event: &KeyEvent) {
+        // Repeats of a held key aren't new presses
+        if let PhysicalKey::Code(code) = event.physical_key
+            && !event.repeat
+        {
+            self.handle_button(Button::Key(code), event.state);
+        }
+    }
+
+    pub fn handle_button(&mut self, button: Button, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                self.held.insert(button);
+                self.pressed.insert(button);
+            }
+            ElementState::Released => {
+                self.held.remove(&button);
+                self.released.insert(button);
+            }
+        }
+    }
+
+    pub fn is_held(&self, button: Button) -> bool {
+        self.held.contains(&button)
+    }
+
+    pub fn was_pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn was_released(&self, button: Button) -> bool {
+        self.released.contains(&button)
+    }
+
+    /// Releases every held button, for when the window loses focus and won't
+    /// see them come back up.
+    pub fn release_all(&mut self) {
+        self.released.extend(self.held.drain());
+    }
+
+    /// Forgets the presses, releases and motion, once the frame has handled them.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_motion = Vec2::ZERO;
+        self.wheel = 0.0;
+    }
+}
+
+/// What the user can do, named in snake case in bindings files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Boost,
+    /// Axes, usually bound to mouse motion and the wheel.
+    LookX,
+    LookY,
+    Zoom,
+    Rotate,
+    Pan,
+    Pick,
+    ToggleCamera,
+    /// Fits every model in view.
+    FrameAll,
+    /// Switches to the next of the demo's backgrounds.
+    CycleBackground,
+    /// Frees the cursor from looking around, and takes it back.
+    ReleaseCursor,
+    CaptureCursor,
+    /// Swaps the left and right mouse buttons, for left-handed mice.
+    SwapMouseButtons,
+    Screenshot,
+    /// Switches multisampling on and off.
+    ToggleMsaa,
+    /// Switches between perspective and orthographic projections.
+    ToggleProjection,
+    /// Simulation debugging: pausing, stepping once while paused, and
+    /// halving or doubling the time scale.
+    Pause,
+    Step,
+    SlowDown,
+    SpeedUp,
+    /// Drops the demo's rigid bodies again.
+    ResetBodies,
+    /// Switches to the next tonemapping operator.
+    CycleTonemap,
+    /// Switches between auto and manual exposure, and brightens or darkens
+    /// either by half a stop.
+    ToggleAutoExposure,
+    ExposureUp,
+    ExposureDown,
+}
+
+/// An input an action reads from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Source {
+    Button(Button),
+    MouseX,
+    MouseY,
+    Wheel,
+}
+
+/// Buttons count as 1 while held, axes as how far they moved this frame.
+/// Inverted bindings count negatively.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub source: Source,
+    pub inverted: bool,
+}
+
+impl Binding {
+    pub fn new(source: Source) -> Self {
+        Self {
+            source,
+            inverted: false,
+        }
+    }
+
+    pub fn inverted(mut self) -> Self {
+        self.inverted = !self.inverted;
+        self
+    }
+
+    fn value(&self, state: &InputState) -> f32 {
+        let value = match self.source {
+            Source::Button(button) => state.is_held(button) as i32 as f32,
+            Source::MouseX => state.mouse_motion.x,
+            Source::MouseY => state.mouse_motion.y,
+            Source::Wheel => state.wheel,
+        };
+
+        if self.inverted { -value } else { value }
+    }
+}
+
+// Keys are named after their `KeyCode` variant
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn parse_key(name: &str) -> Option<KeyCode> {
+            match name {
+                $(stringify!($key) => Some(KeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names! {
+    KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO,
+    KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ, Digit0, Digit1, Digit2,
+    Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9, Numpad0, Numpad1, Numpad2, Numpad3,
+    Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, NumpadAdd, NumpadSubtract,
+    NumpadEnter, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, ArrowUp, ArrowDown,
+    ArrowLeft, ArrowRight, Space, Tab, Enter, Escape, Backspace, Delete, Insert, Home, End,
+    PageUp, PageDown, ShiftLeft, ShiftRight, ControlLeft, ControlRight, AltLeft, AltRight,
+    CapsLock, PrintScreen, Backquote, Minus, Equal, BracketLeft, BracketRight, Backslash,
+    Semicolon, Quote, Comma, Period, Slash,
+}
+
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    /// Parses a key, `MouseLeft`, `MouseRight`, `MouseMiddle`, `MouseBack`,
+    /// `MouseForward`, `MouseX`, `MouseY` or `Wheel`, inverted by a leading `-`.
+    fn from_str(name: &str) -> Result<Self> {
+        if let Some(name) = name.strip_prefix('-') {
+            return Ok(name.parse::<Self>()?.inverted());
+        }
+
+        let source = match name {
+            "MouseLeft" => Source::Button(Button::Mouse(MouseButton::Left)),
+            "MouseRight" => Source::Button(Button::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Source::Button(Button::Mouse(MouseButton::Middle)),
+            "MouseBack" => Source::Button(Button::Mouse(MouseButton::Back)),
+            "MouseForward" => Source::Button(Button::Mouse(MouseButton::Forward)),
+            "MouseX" => Source::MouseX,
+            "MouseY" => Source::MouseY,
+            "Wheel" => Source::Wheel,
+            _ => Source::Button(Button::Key(
+                parse_key(name).ok_or_else(|| anyhow!("Unknown input {name}"))?,
+            )),
+        };
+
+        Ok(Self::new(source))
+    }
+}
+
+/// Bindings of every action, in the same format as bindings files.
+const DEFAULT_BINDINGS: &str = "
+move_forward = KeyW
+move_backward = KeyS
+move_left = KeyA
+move_right = KeyD
+move_up = Space
+move_down = KeyC
+boost = ShiftLeft, ShiftRight
+look_x = MouseX
+look_y = MouseY
+zoom = Wheel
+rotate = MouseLeft
+pan = MouseMiddle
+pick = MouseRight
+toggle_camera = Tab
+frame_all = KeyF
+cycle_background = KeyB
+release_cursor = Escape
+capture_cursor = MouseLeft
+swap_mouse_buttons = KeyL
+screenshot = F12
+toggle_msaa = KeyM
+toggle_projection = KeyO
+pause = KeyP
+step = Period
+slow_down = BracketLeft
+speed_up = BracketRight
+reset_bodies = KeyR
+cycle_tonemap = KeyT
+toggle_auto_exposure = KeyX
+exposure_up = Equal
+exposure_down = Minus
+";
+
+/// Which inputs drive each action. An action can have any number of
+/// bindings, and an input can drive any number of actions.
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = Self {
+            bindings: HashMap::new(),
+        };
+        map.parse(DEFAULT_BINDINGS).unwrap();
+        map
+    }
+}
+
+impl InputMap {
+    // Each line binds an action to a comma separated list of inputs, replacing
+    // its current bindings. Lines starting with `#` are comments.
+    fn parse(&mut self, source: &str) -> Result<()> {
+        for (line_idx, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| anyhow!("{message} at line {}", line_idx + 1);
+
+            let (action, inputs) = line
+                .split_once('=')
+                .ok_or_else(|| error("Expected action = inputs".into()))?;
+            let action = action.trim();
+            let action =
+                Action::from_str(action).map_err(|_| error(format!("Unknown action {action}")))?;
+            let bindings = inputs
+                .split(',')
+                .map(str::trim)
+                .filter(|input| !input.is_empty())
+                .map(|input| input.parse().map_err(|err| error(format!("{err}"))))
+                .collect::<Result<Vec<_>>>()?;
+            self.unbind(action);
+            for binding in bindings {
+                self.bind(action, binding);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads a bindings file over the default bindings. Actions it doesn't
+    /// mention keep their defaults, and those it lists without inputs are unbound.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read bindings {}: {err}", path.display()))?;
+        let mut map = Self::default();
+        map.parse(&source)
+            .map_err(|err| anyhow!("Failed to parse bindings {}: {err}", path.display()))?;
+
+        Ok(map)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds a binding, keeping the action's others. Bindings the action
+    /// already has aren't added again, so that axes don't count twice.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Replaces every binding of an action.
+    pub fn rebind(&mut self, action: Action, bindings: impl IntoIterator<Item = Binding>) {
+        self.bindings.insert(action, bindings.into_iter().collect());
+    }
+
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.remove(&action);
+    }
+
+    /// Moves every binding of either button to the other one.
+    pub fn swap_buttons(&mut self, a: Button, b: Button) {
+        let actions: Vec<_> = self.bindings.keys().copied().collect();
+        for action in actions {
+            let bindings: Vec<_> = self
+                .bindings(action)
+                .iter()
+                .map(|binding| {
+                    let source = match binding.source {
+                        Source::Button(button) if button == a => Source::Button(b),
+                        Source::Button(button) if button == b => Source::Button(a),
+                        source => source,
+                    };
+                    Binding { source, ..*binding }
+                })
+                .collect();
+            self.rebind(action, bindings);
+        }
+    }
+
+    /// Sum of every binding's value.
+    pub fn value(&self, action: Action, state: &InputState) -> f32 {
+        self.bindings(action)
+            .iter()
+            .map(|binding| binding.value(state))
+            .sum()
+    }
+
+    /// Whether any button bound to the action is held down.
+    pub fn is_held(&self, action: Action, state: &InputState) -> bool {
+        self.buttons(action).any(|button| state.is_held(button))
+    }
+
+    /// Whether any button bound to the action went down this frame.
+    pub fn was_pressed(&self, action: Action, state: &InputState) -> bool {
+        self.buttons(action).any(|button| state.was_pressed(button))
+    }
+
+    /// Whether any button bound to the action came back up this frame.
+    pub fn was_released(&self, action: Action, state: &InputState) -> bool {
+        self.buttons(action)
+            .any(|button| state.was_released(button))
+    }
+
+    fn buttons(&self, action: Action) -> impl Iterator<Item = Button> {
+        self.bindings(action)
+            .iter()
+            .filter_map(|binding| match binding.source {
+                Source::Button(button) => Some(button),
+                _ => None,
+            })
+    }
+
+    /// What the camera controllers should do this frame.
+    pub fn user_input(&self, state: &InputState) -> UserInput {
+        let held = |action| self.is_held(action, state);
+        let rotate = held(Action::Rotate);
+        let boost = held(Action::Boost);
+
+        UserInput {
+            move_forward: held(Action::MoveForward),
+            move_backward: held(Action::MoveBackward),
+            move_left: held(Action::MoveLeft),
+            move_right: held(Action::MoveRight),
+            move_up: held(Action::MoveUp),
+            move_down: held(Action::MoveDown),
+            boost,
+            yaw: self.value(Action::LookX, state),
+            pitch: self.value(Action::LookY, state),
+            // Boosting while rotating pans instead
+            rotate: rotate && !boost,
+            pan: held(Action::Pan) || (rotate && boost),
+            zoom: self.value(Action::Zoom, state),
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+pub struct UserInput {
+    pub move_forward: bool,
+    pub move_backward: bool,
+    pub move_left: bool,
+    pub move_right: bool,
+    pub move_up: bool,
+    pub move_down: bool,
+    /// Flies faster while held.
+    pub boost: bool,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Orbit controls: mouse motion turns around the pivot or pans it, and
+    /// each wheel step up zooms in, or speeds up flying.
+    pub rotate: bool,
+    pub pan: bool,
+    pub zoom: f32,
+}
+
+impl UserInput {
+    /// Unit direction to move in, relative to the camera.
+    pub fn direction(&self) -> Vec3 {
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+
+        Vec3::new(
+            axis(self.move_right, self.move_left),
+            axis(self.move_up, self.move_down),
+            axis(self.move_forward, self.move_backward),
+        )
+        .normalize_or_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> Binding {
+        Binding::new(Source::Button(Button::Key(code)))
+    }
+
+    fn mouse(button: MouseButton) -> Binding {
+        Binding::new(Source::Button(Button::Mouse(button)))
+    }
+
+    fn parse(source: &str) -> Result<InputMap> {
+        let mut map = InputMap::default();
+        map.parse(source)?;
+        Ok(map)
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let map = parse(
+            "# Arrows too
+            move_forward = KeyW, ArrowUp
+
+            look_y = -MouseY
+            zoom =",
+        )
+        .unwrap();
+
+        assert_eq!(
+            map.bindings(Action::MoveForward),
+            [key(KeyCode::KeyW), key(KeyCode::ArrowUp)]
+        );
+        assert_eq!(
+            map.bindings(Action::LookY),
+            [Binding::new(Source::MouseY).inverted()]
+        );
+        assert!(map.bindings(Action::Zoom).is_empty());
+        // Left out, so kept as by default
+        assert_eq!(map.bindings(Action::MoveBackward), [key(KeyCode::KeyS)]);
+    }
+
+    #[test]
+    fn unknown_names_fail() {
+        let err = parse("move_forward = KeyWW").err().unwrap();
+        assert!(
+            err.to_string().contains("Unknown input KeyWW at line 1"),
+            "{err}"
+        );
+
+        let err = parse("move_forward = KeyW\nfly = KeyF").err().unwrap();
+        assert!(
+            err.to_string().contains("Unknown action fly at line 2"),
+            "{err}"
+        );
+
+        let err = parse("move_forward KeyW").err().unwrap();
+        assert!(
+            err.to_string().contains("Expected action = inputs"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings_count_once() {
+        let map = parse("look_x = MouseX, MouseX, -MouseX\nboost = KeyQ\nboost = KeyE").unwrap();
+
+        assert_eq!(
+            map.bindings(Action::LookX),
+            [
+                Binding::new(Source::MouseX),
+                Binding::new(Source::MouseX).inverted()
+            ]
+        );
+        // Later lines replace earlier ones
+        assert_eq!(map.bindings(Action::Boost), [key(KeyCode::KeyE)]);
+    }
+
+    #[test]
+    fn picking_has_its_own_button() {
+        let map = InputMap::default();
+        for action in [Action::Rotate, Action::CaptureCursor] {
+            assert!(
+                map.bindings(Action::Pick)
+                    .iter()
+                    .all(|binding| !map.bindings(action).contains(binding)),
+                "{action:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn swapped_buttons_move_their_bindings() {
+        let mut map = InputMap::default();
+        map.swap_buttons(
+            Button::Mouse(MouseButton::Left),
+            Button::Mouse(MouseButton::Right),
+        );
+
+        assert_eq!(map.bindings(Action::Rotate), [mouse(MouseButton::Right)]);
+        assert_eq!(map.bindings(Action::Pick), [mouse(MouseButton::Left)]);
+        assert_eq!(map.bindings(Action::Pan), [mouse(MouseButton::Middle)]);
+    }
+
+    #[test]
+    fn presses_and_releases_last_a_frame() {
+        let map = InputMap::default();
+        let mut state = InputState::default();
+        let rotate = Button::Mouse(MouseButton::Left);
+
+        state.handle_button(rotate, ElementState::Pressed);
+        assert!(map.was_pressed(Action::Rotate, &state));
+        assert!(map.is_held(Action::Rotate, &state));
+        state.end_frame();
+        assert!(!map.was_pressed(Action::Rotate, &state));
+        assert!(map.is_held(Action::Rotate, &state));
+
+        state.release_all();
+        assert!(map.was_released(Action::Rotate, &state));
+        assert!(!map.is_held(Action::Rotate, &state));
+        state.end_frame();
+        assert!(!map.was_released(Action::Rotate, &state));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    camera::CameraUniform,
+    globals::GlobalsUniform,
+    light::LightsUniform,
+    model::ModelUniform,
+    uniform::{Uniform, UniformLayout},
+};
+
+/// Owns the bind group layouts shared by every standard pipeline: per frame
+/// globals, lights and environment, camera, per object uniforms and the standard material.
+pub struct BindGroupLayouts {
+    pub frame: wgpu::BindGroupLayout,
+    pub camera: wgpu::BindGroupLayout,
+    pub object: wgpu::BindGroupLayout,
+    pub material: wgpu::BindGroupLayout,
+}
+
+impl BindGroupLayouts {
+    pub const FRAME_GROUP: u32 = 0;
+    pub const CAMERA_GROUP: u32 = 1;
+    pub const OBJECT_GROUP: u32 = 2;
+    pub const MATERIAL_GROUP: u32 = 3;
+
+    fn get_uniform_layout(
+        device: &wgpu::Device,
+        label: &str,
+        visibility: wgpu::ShaderStages,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: label.into(),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    fn get_frame_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let uniform = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let environment_cube = wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            multisampled: false,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Frame bind group layout".into(),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: uniform,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: uniform,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::CubeArray,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Environment irradiance and prefiltered specular cube maps, BRDF LUT
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: environment_cube,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: environment_cube,
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn get_material_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Standard material bind group layout".into(),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let vertex_fragment = wgpu::ShaderStages::VERTEX_FRAGMENT;
+
+        Self {
+            frame: Self::get_frame_layout(device),
+            camera: Self::get_uniform_layout(device, "Camera uniform layout", vertex_fragment),
+            object: Self::get_uniform_layout(device, "Object uniform layout", vertex_fragment),
+            material: Self::get_material_layout(device),
+        }
+    }
+
+    /// Uniforms of the standard groups by group and binding, shared by every shader.
+    pub fn uniforms() -> [(u32, u32, UniformLayout); 4] {
+        [
+            (Self::FRAME_GROUP, 0, GlobalsUniform::layout()),
+            (Self::FRAME_GROUP, 1, LightsUniform::layout()),
+            (Self::CAMERA_GROUP, 0, CameraUniform::layout()),
+            (Self::OBJECT_GROUP, 0, ModelUniform::layout()),
+        ]
+    }
+
+    pub fn make_pipeline_layout(
+        &self,
+        device: &wgpu::Device,
+        material_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::PipelineLayout {
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: "Standard pipeline layout".into(),
+            bind_group_layouts: &[&self.frame, &self.camera, &self.object, material_layout],
+            push_constant_ranges: &[],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::ShaderKey;
+
+    #[test]
+    fn uniforms_match_shaders() {
+        let shaders = [
+            ShaderKey::builtin("simple"),
+            ShaderKey::builtin("shadow"),
+            ShaderKey::builtin("shadow").with_define("LINEAR_DEPTH"),
+            ShaderKey::builtin("shadow").with_define("ALPHA_TEST"),
+        ];
+        for shader in shaders {
+            let module = shader.load(false).unwrap().parse().unwrap();
+            for (group, binding, layout) in BindGroupLayouts::uniforms() {
+                layout.check(&module, group, binding).unwrap();
+            }
+        }
+    }
+}
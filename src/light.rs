@@ -0,0 +1,403 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::{
+    camera::Camera,
+    gpu::Gpu,
+    object::{DataStore, Object},
+    shadow::{CASCADE_COUNT, MAX_POINT_SHADOWS, MAX_SHADOW_VIEWS, ShadowView, ShadowViews},
+    uniform::uniform_struct,
+};
+
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Lights the whole scene from infinitely far away.
+    Directional,
+    /// Lights a cone, angles are measured from its axis in radians.
+    Spot {
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    /// Lights every direction around its position.
+    Point { range: f32 },
+}
+
+/// Lights shine along the +Z axis of their object, and cast shadows unless
+/// their object is set not to.
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+uniform_struct! {
+    struct LightData {
+        position: Vec3,
+        kind: u32,
+        direction: Vec3,
+        range: f32,
+        color: Vec3,
+        intensity: f32,
+        cos_inner: f32,
+        cos_outer: f32,
+        // Shadow map layer, or cube map index for point lights
+        shadow_view: i32,
+        _padding: f32,
+    }
+}
+
+uniform_struct! {
+    pub struct LightsUniform {
+        pub count: u32,
+        pub cascade_count: u32,
+        _padding: [u32; 2],
+        pub cascade_splits: Vec4,
+        lights: [LightData; MAX_LIGHTS],
+        pub shadow_matrices: [Mat4; MAX_SHADOW_VIEWS],
+    }
+}
+
+impl Light {
+    const DIRECTIONAL: u32 = 0;
+    const SPOT: u32 = 1;
+    const POINT: u32 = 2;
+
+    pub fn new_custom(
+        store: &mut DataStore,
+        kind: LightKind,
+        color: Vec3,
+        intensity: f32,
+    ) -> Object {
+        let light = Self {
+            kind,
+            color,
+            intensity,
+        };
+
+        Object::new(light, store)
+    }
+
+    pub fn new_directional(store: &mut DataStore, color: Vec3, intensity: f32) -> Object {
+        Self::new_custom(store, LightKind::Directional, color, intensity)
+    }
+
+    pub fn new_spot(
+        store: &mut DataStore,
+        color: Vec3,
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Object {
+        let kind = LightKind::Spot {
+            range,
+            inner_angle,
+            outer_angle,
+        };
+
+        Self::new_custom(store, kind, color, intensity)
+    }
+
+    pub fn new_point(store: &mut DataStore, color: Vec3, intensity: f32, range: f32) -> Object {
+        Self::new_custom(store, LightKind::Point { range }, color, intensity)
+    }
+
+    fn light_data(&self, xform: Mat4, shadow_view: i32) -> LightData {
+        let (kind, range, cos_inner, cos_outer) = match self.kind {
+            LightKind::Directional => (Self::DIRECTIONAL, 0.0, 0.0, 0.0),
+            LightKind::Spot {
+                range,
+                inner_angle,
+                outer_angle,
+            } => (Self::SPOT, range, inner_angle.cos(), outer_angle.cos()),
+            LightKind::Point { range } => (Self::POINT, range, 0.0, 0.0),
+        };
+
+        LightData {
+            position: xform.w_axis.truncate(),
+            kind,
+            direction: xform.transform_vector3(Vec3::Z).normalize(),
+            range,
+            color: self.color,
+            intensity: self.intensity,
+            cos_inner,
+            cos_outer,
+            shadow_view,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// GPU side list of the lights in the scene.
+pub struct Lights {
+    pub uniform_buffer: wgpu::Buffer,
+    point_shadow_budget: usize,
+}
+
+impl Lights {
+    pub fn new(gpu: &Gpu) -> Self {
+        let uniform_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lights uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<LightsUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            uniform_buffer,
+            point_shadow_budget: MAX_POINT_SHADOWS,
+        }
+    }
+
+    /// Limits how many point lights get shadows each frame, as each of them
+    /// renders the scene six times. The lights closest to the camera win.
+    pub fn set_point_shadow_budget(&mut self, budget: usize) {
+        self.point_shadow_budget = budget.min(MAX_POINT_SHADOWS);
+    }
+
+    // Indices of the point lights which get shadows this frame
+    fn point_shadow_casters(
+        lights: &[(&Light, Mat4, bool)],
+        camera_pos: Vec3,
+        budget: usize,
+    ) -> Vec<usize> {
+        let mut casters: Vec<_> = lights
+            .iter()
+            .enumerate()
+            .filter(|(_, (light, _, cast_shadows))| {
+                *cast_shadows && matches!(light.kind, LightKind::Point { .. })
+            })
+            .map(|(idx, (_, xform, _))| (idx, xform.w_axis.truncate().distance(camera_pos)))
+            .collect();
+        casters.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        casters
+            .into_iter()
+            .take(budget)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Uploads the lights and returns the shadow views they need this frame.
+    /// Lights whose shadows don't fit in the shadow maps anymore are rendered
+    /// without them.
+    pub fn update(
+        &self,
+        gpu: &Gpu,
+        lights: &[(&Light, Mat4, bool)],
+        camera: &Camera,
+        camera_xform: Mat4,
+        aspect: f32,
+    ) -> ShadowViews {
+        let (uniform_data, shadow_views) = Self::uniform_data(
+            lights,
+            camera_xform.w_axis.truncate(),
+            self.point_shadow_budget,
+            |direction| ShadowView::cascades(direction, camera, camera_xform, aspect),
+        );
+
+        gpu.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform_data));
+
+        shadow_views
+    }
+
+    // Directional lights are split into cascades by `cascades`, which is
+    // given their direction
+    fn uniform_data(
+        lights: &[(&Light, Mat4, bool)],
+        camera_pos: Vec3,
+        point_shadow_budget: usize,
+        cascades: impl Fn(Vec3) -> ([ShadowView; CASCADE_COUNT], Vec4),
+    ) -> (LightsUniform, ShadowViews) {
+        if lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "Only the first {MAX_LIGHTS} of {} lights are used",
+                lights.len()
+            );
+        }
+        let lights = &lights[..lights.len().min(MAX_LIGHTS)];
+        let point_shadow_casters =
+            Self::point_shadow_casters(lights, camera_pos, point_shadow_budget);
+
+        let mut uniform_data: LightsUniform = bytemuck::Zeroable::zeroed();
+        let mut shadow_views = ShadowViews::default();
+
+        for (idx, (light, xform, cast_shadows)) in lights.iter().enumerate() {
+            let position = xform.w_axis.truncate();
+            let direction = xform.transform_vector3(Vec3::Z).normalize();
+
+            let views = match light.kind {
+                _ if !cast_shadows => Vec::new(),
+                LightKind::Directional => {
+                    let (cascades, splits) = cascades(direction);
+                    uniform_data.cascade_count = CASCADE_COUNT as u32;
+                    uniform_data.cascade_splits = splits;
+                    cascades.to_vec()
+                }
+                LightKind::Spot {
+                    range, outer_angle, ..
+                } => vec![ShadowView::spot(position, direction, outer_angle, range)],
+                LightKind::Point { .. } => Vec::new(),
+            };
+
+            let shadow_view = match light.kind {
+                LightKind::Point { range } if point_shadow_casters.contains(&idx) => {
+                    shadow_views
+                        .point_views
+                        .push(ShadowView::point(position, range));
+                    shadow_views.point_views.len() as i32 - 1
+                }
+                _ if !views.is_empty()
+                    && shadow_views.views.len() + views.len() <= MAX_SHADOW_VIEWS =>
+                {
+                    let first = shadow_views.views.len();
+                    shadow_views.views.extend(views);
+                    first as i32
+                }
+                _ => -1,
+            };
+
+            uniform_data.lights[idx] = light.light_data(*xform, shadow_view);
+            uniform_data.count += 1;
+        }
+
+        for (matrix, view) in uniform_data
+            .shadow_matrices
+            .iter_mut()
+            .zip(&shadow_views.views)
+        {
+            *matrix = view.matrix();
+        }
+
+        (uniform_data, shadow_views)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(kind: LightKind) -> Light {
+        Light {
+            kind,
+            color: Vec3::ONE,
+            intensity: 1.0,
+        }
+    }
+
+    fn at(position: Vec3) -> Mat4 {
+        Mat4::from_translation(position)
+    }
+
+    // Cascades only need to be counted here
+    fn cascades(direction: Vec3) -> ([ShadowView; CASCADE_COUNT], Vec4) {
+        let view = ShadowView::spot(Vec3::ZERO, direction, 0.5, 10.0);
+        ([view; CASCADE_COUNT], Vec4::ONE)
+    }
+
+    #[test]
+    fn closest_point_lights_cast_shadows() {
+        let point = light(LightKind::Point { range: 10.0 });
+        let spot = light(LightKind::Spot {
+            range: 10.0,
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        });
+        let lights = [
+            (&point, at(Vec3::new(0.0, 0.0, 10.0)), true),
+            (&spot, at(Vec3::ZERO), true),
+            (&point, at(Vec3::ZERO), false),
+            (&point, at(Vec3::new(0.0, 2.0, 0.0)), true),
+            (&point, at(Vec3::new(-5.0, 0.0, 0.0)), true),
+        ];
+
+        let casters = Lights::point_shadow_casters(&lights, Vec3::ZERO, 2);
+        assert_eq!(casters, [3, 4]);
+        let casters = Lights::point_shadow_casters(&lights, Vec3::new(0.0, 0.0, 9.0), 2);
+        assert_eq!(casters, [0, 3]);
+        assert!(Lights::point_shadow_casters(&lights, Vec3::ZERO, 0).is_empty());
+    }
+
+    #[test]
+    fn shadows_past_the_budget_are_dropped() {
+        let point = light(LightKind::Point { range: 10.0 });
+        let lights = [
+            (&point, at(Vec3::new(0.0, 0.0, 10.0)), true),
+            (&point, at(Vec3::new(0.0, 0.0, 1.0)), true),
+            (&point, at(Vec3::new(0.0, 0.0, 5.0)), true),
+        ];
+
+        let (uniform_data, shadow_views) = Lights::uniform_data(&lights, Vec3::ZERO, 2, cascades);
+        let shadow_views_of: Vec<_> = uniform_data.lights[..3]
+            .iter()
+            .map(|light| light.shadow_view)
+            .collect();
+        assert_eq!(shadow_views_of, [-1, 0, 1]);
+        assert_eq!(shadow_views.point_views.len(), 2);
+        assert!(shadow_views.views.is_empty());
+    }
+
+    #[test]
+    fn shadows_past_the_shadow_map_are_dropped() {
+        let directional = light(LightKind::Directional);
+        let spot = light(LightKind::Spot {
+            range: 10.0,
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        });
+        let point = light(LightKind::Point { range: 10.0 });
+        // Two directional lights fill the shadow map with their cascades
+        let lights = [
+            (&spot, at(Vec3::ZERO), true),
+            (&directional, Mat4::IDENTITY, true),
+            (&directional, Mat4::IDENTITY, true),
+            (&spot, at(Vec3::ZERO), true),
+            (&point, at(Vec3::ZERO), true),
+        ];
+
+        let (uniform_data, shadow_views) =
+            Lights::uniform_data(&lights, Vec3::ZERO, MAX_POINT_SHADOWS, cascades);
+        let shadow_views_of: Vec<_> = uniform_data.lights[..5]
+            .iter()
+            .map(|light| light.shadow_view)
+            .collect();
+        // The second directional light doesn't fit after the spot light, but
+        // the second spot light still does, and point lights have their own maps
+        assert_eq!(shadow_views_of, [0, 1, -1, 5, 0]);
+        assert_eq!(shadow_views.views.len(), 1 + CASCADE_COUNT + 1);
+        assert!(shadow_views.views.len() <= MAX_SHADOW_VIEWS);
+        assert_eq!(uniform_data.count, 5);
+    }
+
+    #[test]
+    fn lights_set_not_to_cast_shadows_get_none() {
+        let directional = light(LightKind::Directional);
+        let spot = light(LightKind::Spot {
+            range: 10.0,
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        });
+        let point = light(LightKind::Point { range: 10.0 });
+        let lights = [
+            (&directional, Mat4::IDENTITY, false),
+            (&spot, at(Vec3::ZERO), false),
+            (&point, at(Vec3::ZERO), false),
+            (&spot, at(Vec3::ZERO), true),
+        ];
+
+        let (uniform_data, shadow_views) =
+            Lights::uniform_data(&lights, Vec3::ZERO, MAX_POINT_SHADOWS, cascades);
+        let shadow_views_of: Vec<_> = uniform_data.lights[..4]
+            .iter()
+            .map(|light| light.shadow_view)
+            .collect();
+        assert_eq!(shadow_views_of, [-1, -1, -1, 0]);
+        assert_eq!(uniform_data.cascade_count, 0);
+        assert_eq!(shadow_views.views.len(), 1);
+        assert!(shadow_views.point_views.is_empty());
+    }
+}
@@ -1,43 +1,96 @@
+mod background;
+mod bounds;
 mod camera;
+mod cubemap;
 mod data;
+mod environment;
+mod fly;
 mod globals;
 mod gpu;
+mod input;
+mod layouts;
+mod light;
 mod material;
 mod mesh;
 mod model;
 mod object;
+mod orbit;
 mod physics;
+mod pipeline;
+mod post;
+mod render_graph;
+mod render_queue;
 mod renderer;
 mod scene;
+mod shader;
+mod shader_material;
+mod shadow;
+mod timestep;
+mod tonemap;
+mod uniform;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 
+use background::Background;
 use camera::Camera;
-use glam::{Vec2, Vec3};
-use model::Model;
-use object::DataStore;
-use physics::UserInput;
+use cubemap::Cubemap;
+use environment::Environment;
+use fly::FlyController;
+use glam::{Vec2, Vec3, Vec4};
+use input::{Action, Button, InputMap, InputState, UserInput};
+use light::Light;
+use material::AlphaMode;
+use model::{Model, ObjOptions};
+use object::{DataStore, Object};
+use orbit::OrbitController;
+use physics::{Collider, RigidBody};
+use post::{Bloom, ColorGrading, FilmGrain, Fxaa, Lut3d, PostSettings, Vignette};
 use scene::Scene;
+use shader_material::{ShaderMaterial, ShaderMaterialDesc};
+use timestep::FixedTimestep;
+use tonemap::Exposure;
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
-    event::{DeviceEvent, KeyEvent, Modifiers, WindowEvent},
+    event::{DeviceEvent, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
     window::{CursorGrabMode, Window},
 };
 
-use crate::{gpu::Gpu, physics::PhysicsController, renderer::Renderer};
+use crate::{gpu::Gpu, renderer::Renderer};
+
+#[derive(Default, PartialEq)]
+enum CameraMode {
+    #[default]
+    Fly,
+    Orbit,
+}
 
 #[derive(Default)]
 struct App {
     data_store: DataStore,
     renderer: Option<Renderer>,
     scene: Option<Scene>,
-    physics: PhysicsController,
-    input_modifiers: Modifiers,
-    key_event: Option<KeyEvent>,
-    mouse_motion: Vec2,
+    fly: FlyController,
+    orbit: Option<OrbitController>,
+    camera_mode: CameraMode,
+    timestep: FixedTimestep,
+    // Never paused or scaled, so that the camera keeps moving in real time
+    camera_timestep: FixedTimestep,
+    input: InputState,
+    input_map: InputMap,
+    cursor_position: Vec2,
+    // Hidden and held in the window, for looking around
+    cursor_captured: bool,
+    last_click: Option<(Instant, Vec2)>,
+    // Turned with the time since startup
+    star: Option<Rc<ShaderMaterial>>,
+    // Bodies dropped in the demo, in the order of `FALLING_START`
+    falling: [usize; 3],
+    // Cycled through, the first being the scene's
+    backgrounds: Vec<Background>,
 }
 
 impl ApplicationHandler for App {
@@ -50,11 +103,86 @@ impl ApplicationHandler for App {
             .with_title("Quickrender");
 
         let window = event_loop.create_window(attrs).unwrap();
-        let _ = window.set_cursor_grab(CursorGrabMode::Confined);
-        window.set_cursor_visible(false);
-        let gpu = pollster::block_on(Gpu::new(window, size)).unwrap();
+        let mut gpu = pollster::block_on(Gpu::new(window, size)).unwrap();
+        gpu.pipelines
+            .set_hot_reload(std::env::var_os("QUICKRENDER_HOT_RELOAD").is_some());
+
+        let star = Rc::new(Self::star_material(&mut gpu).unwrap());
+        let star_model = Model::load_obj(
+            &gpu,
+            &mut self.data_store,
+            Path::new("src/res/models/suzanne/suzanne.obj"),
+        ).unwrap()
+            .with_translation(Vec3::new(-3.0, 0.0, 0.0));
+        for (object, _) in star_model.get_all() {
+            if let Some(model) = object
+                .get_data()
+                .try_as_model()
+                .and_then(|id| self.data_store.get_model_mut(id))
+            {
+                model.material = Box::new(star.clone());
+            }
+        }
+        self.star = Some(star);
 
-        let scene = Scene::new(vec![
+        let camera = Camera::new(&gpu, &mut self.data_store)
+            .with_translation(Vec3::new(0.0, 0.0, -6.0))
+            .with_look_at(Vec3::ZERO, Vec3::Y);
+        if let Some(camera) = camera
+            .get_data()
+            .try_as_camera()
+            .and_then(|id| self.data_store.get_camera(id))
+        {
+            camera.post = Self::post_settings(&gpu).unwrap();
+        }
+
+        let sky = Rc::new(
+            Cubemap::load_equirect(&mut gpu, Path::new("src/res/sky/sky.hdr"), Self::SKY_SIZE)
+                .unwrap(),
+        );
+        let environment = Rc::new(Environment::bake(&mut gpu, &sky).unwrap());
+        self.backgrounds = Self::backgrounds(&gpu, sky).unwrap();
+
+        // Dropped onto an invisible floor below the other models
+        let ground = Object::empty().with_translation(Vec3::new(0.0, -3.5, 0.0));
+        let falling = [
+            (
+                Self::FALLING_START[0],
+                Path::new("src/res/models/suzanne/suzanne.obj"),
+                RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 }).with_restitution(0.6),
+            ),
+            (
+                Self::FALLING_START[1],
+                Path::new("src/res/models/teapot/teapot.obj"),
+                RigidBody::new_dynamic(
+                    2.0,
+                    Collider::Box {
+                        half_extents: Vec3::new(1.6, 0.8, 1.0),
+                    },
+                )
+                .with_friction(0.8),
+            ),
+            (
+                Self::FALLING_START[2],
+                Path::new("src/res/models/sus/sus.obj"),
+                RigidBody::new_dynamic(
+                    1.0,
+                    Collider::Capsule {
+                        radius: 1.0,
+                        half_height: 0.4,
+                    },
+                )
+                .with_velocity(Vec3::new(-2.0, 0.0, 0.0)),
+            ),
+        ]
+        .map(|(start, path, body)| {
+            let object = Model::load_obj(&gpu, &mut self.data_store, path)
+                .unwrap()
+                .with_translation(start);
+            (object, body)
+        });
+
+        let mut scene = Scene::new(vec![
             /*
             Model::load_obj(
                 &gpu,
@@ -68,14 +196,60 @@ impl ApplicationHandler for App {
                 &Path::new("src/res/gltf/asteroids.glb")
             ).unwrap()
                 .with_rotation_x(-2.0 * std::f32::consts::PI / 4.0)
-                .with_scale(Vec3::ONE * 0.5),
-            Camera::new(&gpu, &mut self.data_store)
-                .with_rotation_y(std::f32::consts::PI)
-                .with_translation(Vec3::new(0.0, 0.0, 6.0)),
-        ]);
+                .with_scale(Vec3::ONE * 0.5)
+                // Casts shadows, but none fall on it
+                .with_receive_shadows(false),
+            // Untextured, so it gets the fallback star sprite, whose cutouts
+            // show the inside of the model
+            Model::load_obj_custom(
+                &gpu,
+                &mut self.data_store,
+                Path::new("src/res/models/suzanne/suzanne.obj"),
+                ObjOptions {
+                    double_sided: true,
+                    ..Default::default()
+                },
+            ).unwrap()
+                .with_translation(Vec3::new(3.0, 0.0, 0.0))
+                // Shadowed, but casts no shadow itself
+                .with_cast_shadows(false),
+            star_model,
+            ground.clone(),
+            falling[0].0.clone(),
+            falling[1].0.clone(),
+            falling[2].0.clone(),
+            Light::new_directional(&mut self.data_store, Vec3::ONE, 1.0)
+                .with_rotation_y(std::f32::consts::PI / 2.0)
+                .with_rotation_x(std::f32::consts::PI / 4.0),
+            // Over where the bodies land, pointing down
+            Light::new_spot(&mut self.data_store, Vec3::new(1.0, 0.9, 0.7), 6.0, 15.0, 0.3, 0.5)
+                .with_translation(Vec3::new(0.0, 5.0, 4.0))
+                .with_rotation_x(std::f32::consts::PI / 2.0),
+            // Only the closest of these casts shadows, see `POINT_SHADOW_BUDGET`
+            Light::new_point(&mut self.data_store, Vec3::new(1.0, 0.5, 0.2), 4.0, 8.0)
+                .with_translation(Vec3::new(-5.0, 1.0, -1.0)),
+            Light::new_point(&mut self.data_store, Vec3::new(0.2, 0.5, 1.0), 4.0, 8.0)
+                .with_translation(Vec3::new(5.0, 1.0, -1.0)),
+            camera,
+        ])
+        .with_background(self.backgrounds[0].clone())
+        .with_environment(environment);
+
+        scene.physics.add_body(
+            ground,
+            RigidBody::new_static(Collider::Box {
+                half_extents: Vec3::new(10.0, 0.5, 10.0),
+            }),
+        );
+        self.falling = falling.map(|(object, body)| scene.physics.add_body(object, body));
 
+        self.orbit = Some(OrbitController::new(Vec3::ZERO));
         self.scene = Some(scene);
-        self.renderer = Some(Renderer::new(gpu));
+        let mut renderer = Renderer::new(gpu);
+        renderer.set_point_shadow_budget(Self::POINT_SHADOW_BUDGET);
+        self.renderer = Some(renderer);
+        self.frame_all();
+        self.set_cursor_captured(self.camera_mode == CameraMode::Fly);
     }
 
     fn window_event(
@@ -96,19 +270,58 @@ impl ApplicationHandler for App {
             }
             WindowEvent::RedrawRequested => {
                 let user_input = self.handle_input();
+                let steps = self.timestep.advance();
+                let camera_steps = self.camera_timestep.advance();
 
                 if let Some(renderer) = &mut self.renderer
                     && let Some(scene) = &mut self.scene
                 {
-                    self.physics.update(scene, &mut self.data_store, user_input);
+                    for _ in 0..steps.count {
+                        scene.physics.step(steps.step);
+                    }
+                    scene.physics.sync(steps.alpha);
+
+                    if let Some(star) = &self.star {
+                        let angle = renderer.globals().time() * Self::STAR_SPIN_SPEED;
+                        star.set(renderer.gpu(), "angle", angle).unwrap();
+                    }
+
+                    match (&self.camera_mode, &mut self.orbit) {
+                        (CameraMode::Orbit, Some(orbit)) => orbit.update(
+                            scene,
+                            &mut self.data_store,
+                            renderer.globals().resolution(),
+                            user_input,
+                        ),
+                        _ => self.fly.update(scene, user_input, camera_steps),
+                    }
                     renderer.render(scene, &mut self.data_store).unwrap();
                 }
             }
-            WindowEvent::ModifiersChanged(modifiers) => {
-                self.input_modifiers = modifiers;
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Vec2::new(position.x as f32, position.y as f32);
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.set_mouse_position(self.cursor_position);
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.input.handle_button(Button::Mouse(button), state);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.input.wheel += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        position.y as f32 / Self::PIXELS_PER_LINE
+                    }
+                };
             }
             WindowEvent::KeyboardInput { event, .. } => {
-                self.key_event = Some(event);
+                self.input.handle_key(&event);
+            }
+            // Nothing comes back up or moves the camera while in another window
+            WindowEvent::Focused(false) => {
+                self.input.release_all();
+                self.set_cursor_captured(false);
             }
             _ => (),
         }
@@ -123,7 +336,7 @@ impl ApplicationHandler for App {
         match event {
             DeviceEvent::MouseMotion { delta } => {
                 let motion = Vec2::from([delta.0 as f32, delta.1 as f32]);
-                self.mouse_motion += motion;
+                self.input.mouse_motion += motion;
             }
             _ => {}
         }
@@ -131,35 +344,322 @@ impl ApplicationHandler for App {
 }
 
 impl App {
-    fn handle_input(&mut self) -> UserInput {
-        let mut input = UserInput::default();
-
-        if let Some(key_event) = &self.key_event {
-            if let PhysicalKey::Code(code) = key_event.physical_key {
-                match code {
-                    KeyCode::KeyW => input.move_forward = true,
-                    KeyCode::KeyA => input.move_left = true,
-                    KeyCode::KeyS => input.move_backward = true,
-                    KeyCode::KeyD => input.move_right = true,
-                    KeyCode::Space => input.move_up = true,
-                    KeyCode::KeyC => input.move_down = true,
-                    _ => {}
+    const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+    const DOUBLE_CLICK_DISTANCE: f32 = 4.0;
+    // Wheel movement counted as one step, for touchpads scrolling by pixels
+    const PIXELS_PER_LINE: f32 = 40.0;
+    // Radians per second
+    const STAR_SPIN_SPEED: f32 = 1.0;
+    // Point lights rendering shadows each frame, each costs six shadow passes
+    const POINT_SHADOW_BUDGET: usize = 1;
+    // Supported by every format the scene renders to
+    const MSAA_SAMPLE_COUNT: u32 = 4;
+    // Texels along each face of the sky cube map
+    const SKY_SIZE: u32 = 256;
+    const FALLING_START: [Vec3; 3] = [
+        Vec3::new(-3.0, 4.0, 4.0),
+        Vec3::new(0.0, 6.0, 4.0),
+        Vec3::new(3.0, 8.0, 4.0),
+    ];
+
+    // Double clicks in orbit mode move the pivot to the model under the cursor
+    fn handle_click(&mut self) {
+        let now = Instant::now();
+        let double_click = self.last_click.is_some_and(|(time, position)| {
+            now - time <= Self::DOUBLE_CLICK_TIME
+                && position.distance(self.cursor_position) <= Self::DOUBLE_CLICK_DISTANCE
+        });
+        self.last_click = (!double_click).then_some((now, self.cursor_position));
+
+        if double_click
+            && self.camera_mode == CameraMode::Orbit
+            && let Some(renderer) = &self.renderer
+            && let Some(scene) = &mut self.scene
+            && let Some(orbit) = &mut self.orbit
+        {
+            let viewport = renderer.globals().resolution();
+            let cursor = renderer.globals().mouse();
+            if let Some(pivot) = scene.pick(&mut self.data_store, viewport, cursor) {
+                orbit.pivot = pivot;
+            }
+        }
+    }
+
+    // Locking keeps the cursor in place, but isn't supported everywhere
+    fn set_cursor_captured(&mut self, captured: bool) {
+        self.cursor_captured = captured;
+
+        if let Some(renderer) = &self.renderer {
+            let window = renderer.window();
+            let result = if captured {
+                window
+                    .set_cursor_grab(CursorGrabMode::Locked)
+                    .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+            } else {
+                window.set_cursor_grab(CursorGrabMode::None)
+            };
+            if let Err(err) = result {
+                log::warn!("Failed to grab the cursor: {err}");
+            }
+            window.set_cursor_visible(!captured);
+        }
+    }
+
+    // Orbiting needs the cursor, flying captures it
+    fn toggle_camera_mode(&mut self) {
+        self.camera_mode = match self.camera_mode {
+            CameraMode::Fly => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Fly,
+        };
+        self.set_cursor_captured(self.camera_mode == CameraMode::Fly);
+    }
+
+    // The same sky loaded both ways, then a gradient and a solid color
+    fn backgrounds(gpu: &Gpu, sky: Rc<Cubemap>) -> anyhow::Result<Vec<Background>> {
+        let faces =
+            ["px", "nx", "py", "ny", "pz", "nz"].map(|face| format!("src/res/sky/{face}.png"));
+        let sky_faces = Cubemap::load_faces(gpu, faces.each_ref().map(Path::new))?;
+
+        Ok(vec![
+            Background::Cubemap(sky),
+            Background::Cubemap(Rc::new(sky_faces)),
+            Background::Gradient {
+                top: Vec3::new(0.25, 0.45, 0.85),
+                bottom: Vec3::new(0.25, 0.22, 0.2),
+            },
+            Background::Color(Vec3::new(0.02, 0.02, 0.03)),
+        ])
+    }
+
+    fn cycle_background(&mut self) {
+        if let Some(scene) = &mut self.scene
+            && !self.backgrounds.is_empty()
+        {
+            self.backgrounds.rotate_left(1);
+            scene.background = self.backgrounds[0].clone();
+        }
+    }
+
+    // Every post effect, graded warmer by a LUT
+    fn post_settings(gpu: &Gpu) -> anyhow::Result<PostSettings> {
+        let lut = Lut3d::load_cube(gpu, Path::new("src/res/luts/warm.cube"))?;
+
+        Ok(PostSettings::default()
+            .with_bloom(Bloom::default())
+            .with_color_grading(ColorGrading::new(Rc::new(lut)))
+            .with_vignette(Vignette::default())
+            .with_fxaa(Fxaa::default())
+            .with_film_grain(FilmGrain::default()))
+    }
+
+    // Drops the demo's bodies again from where they started
+    fn reset_bodies(&mut self) {
+        if let Some(scene) = &mut self.scene {
+            for (id, start) in self.falling.into_iter().zip(Self::FALLING_START) {
+                scene.physics.teleport(id, start);
+                if let Some(body) = scene.physics.get_body_mut(id) {
+                    body.velocity = Vec3::ZERO;
                 }
             }
+        }
+    }
+
+    // Fits every model in view, and orbits around their center
+    fn frame_all(&mut self) {
+        if let Some(renderer) = &self.renderer
+            && let Some(scene) = &mut self.scene
+        {
+            let size = renderer.window().inner_size();
+            scene.frame_all(&mut self.data_store, size.width as f32 / size.height as f32);
+
+            if let Some(orbit) = &mut self.orbit
+                && let Some(bounds) = scene.root.bounds(&self.data_store)
+            {
+                orbit.pivot = bounds.center();
+            }
+        }
+    }
+
+    // Keeps what's around the orbit pivot in view, also when flying
+    fn toggle_projection(&mut self) {
+        if let Some(renderer) = &self.renderer
+            && let Some(scene) = &mut self.scene
+            && let Some(orbit) = &self.orbit
+        {
+            let size = renderer.window().inner_size();
+            orbit.toggle_projection(
+                scene,
+                &mut self.data_store,
+                size.width as f32 / size.height as f32,
+            );
+        }
+    }
+
+    // Star sprite drawn by a shader loaded at runtime, rather than a builtin one
+    fn star_material(gpu: &mut Gpu) -> anyhow::Result<ShaderMaterial> {
+        let star = image::open("src/res/star.png")?.to_rgba8();
+        let desc = ShaderMaterialDesc::new(Path::new("src/shaders/star_shader.wgsl"))
+            .with_define("ALPHA_TEST")
+            .with_texture("star", star, true)
+            .with_param("tint", Vec4::new(4.0, 3.0, 1.0, 1.0))
+            .with_param("angle", 0.0)
+            .with_param("scale", 1.5)
+            .with_alpha_mode(AlphaMode::Mask(0.5))
+            .with_double_sided(true);
+
+        ShaderMaterial::new(gpu, desc)
+    }
+
+    // Screenshots are named after the time they're taken, in the working directory
+    fn take_screenshot(&mut self) {
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        if let Some(renderer) = &mut self.renderer {
+            renderer.take_screenshot(&PathBuf::from(format!("screenshot-{millis}.png")));
+        }
+    }
+
+    fn handle_input(&mut self) -> UserInput {
+        let mut input = self.input_map.user_input(&self.input);
+        let pressed = |action| self.input_map.was_pressed(action, &self.input);
+        let released = |action| self.input_map.was_released(action, &self.input);
+        let (toggle, frame_all, cycle_background, release, capture, pick, screenshot) = (
+            pressed(Action::ToggleCamera),
+            pressed(Action::FrameAll),
+            pressed(Action::CycleBackground),
+            pressed(Action::ReleaseCursor),
+            pressed(Action::CaptureCursor),
+            pressed(Action::Pick),
+            pressed(Action::Screenshot),
+        );
+        let (drag_start, drag_end, swap_mouse_buttons, toggle_projection) = (
+            pressed(Action::Rotate) || pressed(Action::Pan),
+            released(Action::Rotate) || released(Action::Pan),
+            pressed(Action::SwapMouseButtons),
+            pressed(Action::ToggleProjection),
+        );
+        let (pause, step, slow_down, speed_up, reset) = (
+            pressed(Action::Pause),
+            pressed(Action::Step),
+            pressed(Action::SlowDown),
+            pressed(Action::SpeedUp),
+            pressed(Action::ResetBodies),
+        );
+        let (cycle_tonemap, toggle_auto_exposure, exposure_up, exposure_down, toggle_msaa) = (
+            pressed(Action::CycleTonemap),
+            pressed(Action::ToggleAutoExposure),
+            pressed(Action::ExposureUp),
+            pressed(Action::ExposureDown),
+            pressed(Action::ToggleMsaa),
+        );
+        self.input.end_frame();
+
+        // Mouse motion is reported outside the window too, so a free cursor
+        // doesn't look around
+        let flying = self.camera_mode == CameraMode::Fly;
+        if flying && !self.cursor_captured {
+            input.yaw = 0.0;
+            input.pitch = 0.0;
+        }
 
-            self.key_event = None;
+        if toggle {
+            self.toggle_camera_mode();
+        } else if flying && self.cursor_captured && release {
+            self.set_cursor_captured(false);
+        } else if flying && !self.cursor_captured && capture {
+            self.set_cursor_captured(true);
+        } else if !flying && (drag_start || drag_end) {
+            // Orbit drags hold on to the cursor, so that they can go past
+            // the edges of the window
+            self.set_cursor_captured(drag_start && !drag_end);
+        }
+        if swap_mouse_buttons {
+            self.input_map.swap_buttons(
+                Button::Mouse(MouseButton::Left),
+                Button::Mouse(MouseButton::Right),
+            );
+            log::info!("Swapped mouse buttons");
+        }
+        if frame_all {
+            self.frame_all();
+        }
+        if toggle_projection {
+            self.toggle_projection();
+        }
+        if cycle_background {
+            self.cycle_background();
+        }
+        if pick {
+            self.handle_click();
+        }
+        if screenshot {
+            self.take_screenshot();
         }
 
-        input.yaw = self.mouse_motion[0];
-        input.pitch = self.mouse_motion[1];
-        self.mouse_motion = Vec2::ZERO;
+        let timestep = &mut self.timestep;
+        if pause {
+            timestep.paused = !timestep.paused;
+            log::info!("Simulation {}", if timestep.paused { "paused" } else { "resumed" });
+        }
+        if step && timestep.paused {
+            timestep.queue_step();
+        }
+        if slow_down || speed_up {
+            timestep.scale_time(if speed_up { 2.0 } else { 0.5 });
+            log::info!("Time scale {}", timestep.time_scale);
+        }
+        if reset {
+            self.reset_bodies();
+        }
+
+        if let Some(renderer) = &mut self.renderer {
+            if cycle_tonemap {
+                let operator = renderer.tonemap_operator().next();
+                renderer.set_tonemap_operator(operator);
+                log::info!("Tonemap operator {operator:?}");
+            }
+            let mut exposure = renderer.exposure();
+            if toggle_auto_exposure {
+                exposure = match exposure {
+                    Exposure::Auto { .. } => Exposure::Manual(0.0),
+                    Exposure::Manual(_) => Exposure::default(),
+                };
+            }
+            if exposure_up || exposure_down {
+                exposure = exposure.offset(if exposure_up { 0.5 } else { -0.5 });
+            }
+            if exposure != renderer.exposure() {
+                renderer.set_exposure(exposure);
+                log::info!("Exposure {exposure:?}");
+            }
+            if toggle_msaa {
+                let sample_count = match renderer.gpu().render_targets().sample_count {
+                    1 => Self::MSAA_SAMPLE_COUNT,
+                    _ => 1,
+                };
+                renderer.set_sample_count(sample_count);
+                log::info!("{sample_count} samples per pixel");
+            }
+        }
 
         input
     }
 }
 
 fn main() {
+    env_logger::init();
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Wait);
-    event_loop.run_app(&mut App::default()).unwrap();
+
+    let mut app = App::default();
+    if let Some(path) = std::env::var_os("QUICKRENDER_BINDINGS") {
+        match InputMap::load(Path::new(&path)) {
+            Ok(input_map) => app.input_map = input_map,
+            Err(err) => log::error!("{err}"),
+        }
+    }
+    event_loop.run_app(&mut app).unwrap();
 }
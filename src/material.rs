@@ -1,5 +1,17 @@
-use crate::{camera::Camera, data::Vertex, globals::Globals, gpu::Gpu, model::Model};
-use std::{default::Default, mem::size_of, path::Path};
+use crate::{
+    camera::Camera,
+    globals::Globals,
+    gpu::Gpu,
+    layouts::BindGroupLayouts,
+    model::Model,
+    pipeline::{PipelineDesc, PipelineKey, RenderTargets, VertexLayout},
+    render_queue::RenderState,
+    shader::ShaderKey,
+    shadow::ShadowMaps,
+    uniform::{Uniform, uniform_struct},
+};
+use glam::Vec4;
+use std::{default::Default, mem::size_of, num::NonZero, rc::Rc};
 use image::RgbaImage;
 use wgpu::{Extent3d, TexelCopyBufferLayout};
 
@@ -12,11 +24,98 @@ pub trait Material {
     ) -> GpuMaterial<'a>;
 }
 
+// Lets a material be shared between models and still be modified at runtime
+impl<M: Material> Material for Rc<M> {
+    fn as_gpu<'a>(
+        &'a self,
+        globals: &'a Globals,
+        camera: &'a Camera,
+        model: &'a Model,
+    ) -> GpuMaterial<'a> {
+        self.as_ref().as_gpu(globals, camera, model)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    // Fragments with alpha below the cutoff are discarded
+    Mask(f32),
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn blend_state(&self) -> wgpu::BlendState {
+        match self {
+            Self::Blend => wgpu::BlendState::ALPHA_BLENDING,
+            _ => wgpu::BlendState::REPLACE,
+        }
+    }
+
+    // Blended geometry is sorted back to front instead of relying on the depth buffer
+    pub fn depth_write(&self) -> bool {
+        *self != Self::Blend
+    }
+}
+
+impl From<gltf::Material<'_>> for AlphaMode {
+    fn from(material: gltf::Material) -> Self {
+        match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => Self::Opaque,
+            gltf::material::AlphaMode::Mask => Self::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+            gltf::material::AlphaMode::Blend => Self::Blend,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialParams {
+    pub base_color: Vec4,
+    pub alpha_mode: AlphaMode,
+    // Double sided materials are rendered without back face culling
+    pub double_sided: bool,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+uniform_struct! {
+    struct MaterialUniform {
+        pub base_color: Vec4,
+        pub alpha_cutoff: f32,
+        _padding: [u32; 3],
+    }
+}
+
+impl From<MaterialParams> for MaterialUniform {
+    fn from(params: MaterialParams) -> Self {
+        let alpha_cutoff = match params.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        };
+
+        Self {
+            base_color: params.base_color,
+            alpha_cutoff,
+            _padding: Default::default(),
+        }
+    }
+}
+
 pub struct SimpleMaterial {
-    pipeline: wgpu::RenderPipeline,
+    pipeline: PipelineDesc,
     texture: wgpu::Texture,
     sampler: wgpu::Sampler,
     bind_group: wgpu::BindGroup,
+    alpha_mode: AlphaMode,
 }
 
 impl Material for SimpleMaterial {
@@ -26,176 +125,146 @@ impl Material for SimpleMaterial {
         camera: &'a Camera,
         model: &'a Model,
     ) -> GpuMaterial<'a> {
-        GpuMaterial {
-            pipeline: &self.pipeline,
-            bind_groups: vec![
-                (0, &globals.bind_group),
-                (1, &camera.bind_group),
-                (2, &model.bind_group),
-                (3, &self.bind_group),
-            ],
-        }
+        GpuMaterial::standard(
+            &self.pipeline,
+            (globals, camera, model),
+            &self.bind_group,
+            self.alpha_mode,
+        )
     }
 }
 
 pub struct GpuMaterial<'a> {
-    pipeline: &'a wgpu::RenderPipeline,
+    pipeline: &'a PipelineDesc,
     bind_groups: Vec<(u32, &'a wgpu::BindGroup)>,
+    alpha_mode: AlphaMode,
 }
 
 impl<'a> GpuMaterial<'a> {
-    pub fn setup(&self, render_pass: &mut wgpu::RenderPass) {
-        render_pass.set_pipeline(self.pipeline);
+    pub fn new(
+        pipeline: &'a PipelineDesc,
+        bind_groups: Vec<(u32, &'a wgpu::BindGroup)>,
+        alpha_mode: AlphaMode,
+    ) -> Self {
+        Self {
+            pipeline,
+            bind_groups,
+            alpha_mode,
+        }
+    }
+
+    // Binds the frame, camera and object uniforms to their standard groups
+    pub fn standard(
+        pipeline: &'a PipelineDesc,
+        (globals, camera, model): (&'a Globals, &'a Camera, &'a Model),
+        material_bind_group: &'a wgpu::BindGroup,
+        alpha_mode: AlphaMode,
+    ) -> Self {
+        let bind_groups = vec![
+            (BindGroupLayouts::FRAME_GROUP, &globals.bind_group),
+            (BindGroupLayouts::CAMERA_GROUP, &camera.bind_group),
+            (BindGroupLayouts::OBJECT_GROUP, &model.bind_group),
+            (BindGroupLayouts::MATERIAL_GROUP, material_bind_group),
+        ];
+        Self::new(pipeline, bind_groups, alpha_mode)
+    }
+
+    pub fn pipeline_key(&self, targets: RenderTargets) -> PipelineKey {
+        PipelineKey {
+            desc: self.pipeline.clone(),
+            targets,
+        }
+    }
+
+    /// Depth only variant of the pipeline, used to render the material into shadow maps.
+    /// Point light shadows store the linear distance to the light instead.
+    /// Masked materials with the standard material layout are alpha tested,
+    /// any others cast solid shadows.
+    pub fn shadow_pipeline_key(
+        &self,
+        layouts: &BindGroupLayouts,
+        linear_depth: bool,
+    ) -> PipelineKey {
+        let (mut shader, depth_bias) = if linear_depth {
+            let shader = ShaderKey::builtin("shadow").with_define("LINEAR_DEPTH");
+            (shader, wgpu::DepthBiasState::default())
+        } else {
+            (ShaderKey::builtin("shadow"), ShadowMaps::DEPTH_BIAS)
+        };
+
+        let mut material_uniforms = Vec::new();
+        if matches!(self.alpha_mode, AlphaMode::Mask(_))
+            && self.pipeline.material_layout == layouts.material
+        {
+            shader = shader.with_define("ALPHA_TEST");
+            material_uniforms.push((3, MaterialUniform::layout()));
+        }
+
+        PipelineKey {
+            desc: PipelineDesc {
+                shader,
+                blend: None,
+                depth_write: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                depth_bias,
+                material_uniforms,
+                ..self.pipeline.clone()
+            },
+            targets: ShadowMaps::targets(),
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.alpha_mode == AlphaMode::Blend
+    }
+
+    pub fn material_bind_group(&self) -> Option<&'a wgpu::BindGroup> {
+        self.bind_groups
+            .iter()
+            .find(|(idx, _)| *idx == BindGroupLayouts::MATERIAL_GROUP)
+            .map(|(_, bind_group)| *bind_group)
+    }
+
+    pub fn setup<'s>(&self, render_pass: &mut wgpu::RenderPass, state: &mut RenderState<'s>)
+    where
+        'a: 's,
+    {
         for (idx, bind_group) in &self.bind_groups {
-            render_pass.set_bind_group(*idx, *bind_group, &[]);
+            state.set_bind_group(render_pass, *idx, bind_group);
         }
     }
 }
 
 impl SimpleMaterial {
-    fn get_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: "Simple material textures bind group layout".into(),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-        })
-    }
+    fn get_shader(params: MaterialParams, normal_map: bool) -> ShaderKey {
+        let mut shader = ShaderKey::builtin("simple");
 
-    fn get_pipeline_layout(
-        device: &wgpu::Device,
-        textures_group_layout: &wgpu::BindGroupLayout,
-    ) -> wgpu::PipelineLayout {
-        let simple_entries = [wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::VERTEX,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }];
-
-        let global_uniform_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: "Global uniform variables layout".into(),
-                entries: &simple_entries,
-            });
-
-        let camera_uniform_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: "Camera uniform variables layout".into(),
-                entries: &simple_entries,
-            });
-
-        let model_uniform_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: "Model uniform variables layout".into(),
-                entries: &simple_entries,
-            });
-
-        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: "Uniform buffer layout".into(),
-            bind_group_layouts: &[
-                &global_uniform_layout,
-                &camera_uniform_layout,
-                &model_uniform_layout,
-                &textures_group_layout,
-            ],
-            push_constant_ranges: &[],
-        })
+        if normal_map {
+            shader = shader.with_define("NORMAL_MAP");
+        }
+
+        match params.alpha_mode {
+            AlphaMode::Opaque => shader,
+            AlphaMode::Mask(_) => shader.with_define("ALPHA_TEST"),
+            AlphaMode::Blend => shader.with_define("ALPHA_BLEND"),
+        }
     }
 
-    fn make_pipeline(
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        pipeline_layout: &wgpu::PipelineLayout,
-    ) -> wgpu::RenderPipeline {
-        let shader_module = device.create_shader_module(wgpu::include_wgsl!("shaders/simple.wgsl"));
-
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Triangle render"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: Some("vs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: size_of::<Vertex>() as u64,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &wgpu::vertex_attr_array![
-                        0 => Float32x3,
-                        1 => Float32x3,
-                        2 => Float32x3,
-                        3 => Float32x3,
-                        4 => Float32x2
-                    ],
-                }],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None, //Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: Some("fs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                // TODO - grab this info from outside
-                format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0u64,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        })
+    fn get_pipeline_desc(gpu: &Gpu, params: MaterialParams, normal_map: bool) -> PipelineDesc {
+        PipelineDesc {
+            shader: Self::get_shader(params, normal_map),
+            vertex_layout: VertexLayout::Mesh,
+            material_layout: gpu.layouts.material.clone(),
+            blend: Some(params.alpha_mode.blend_state()),
+            cull_mode: (!params.double_sided).then_some(wgpu::Face::Back),
+            depth_write: params.alpha_mode.depth_write(),
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_bias: wgpu::DepthBiasState::default(),
+            material_uniforms: vec![(3, MaterialUniform::layout())],
+        }
     }
 
-    fn make_texture(
+    pub fn make_texture(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         texture_rgba: &RgbaImage,
@@ -240,7 +309,14 @@ impl SimpleMaterial {
         texture
     }
 
-    pub fn new(gpu: &Gpu, texture_rgba: &RgbaImage, normal_rgba: &RgbaImage) -> Self {
+    pub fn new(
+        gpu: &Gpu,
+        texture_rgba: &RgbaImage,
+        normal_rgba: Option<&RgbaImage>,
+        params: MaterialParams,
+    ) -> Self {
+        // Without a normal map a flat one is bound, so the layout stays the same
+        let flat_normal = RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
         let texture = Self::make_texture(
             &gpu.device,
             &gpu.queue,
@@ -250,20 +326,23 @@ impl SimpleMaterial {
         let normal_map = Self::make_texture(
             &gpu.device,
             &gpu.queue,
-            normal_rgba,
+            normal_rgba.unwrap_or(&flat_normal),
             wgpu::TextureFormat::Rgba8Unorm,
         );
-        let texture_bind_group_layout = Self::get_texture_bind_group_layout(&gpu.device);
-
-        let pipeline = gpu
-            .get_render_pipelines()
-            .entry("Simple texture pipeline".into())
-            .or_insert_with(|| {
-                let pipeline_layout =
-                    Self::get_pipeline_layout(&gpu.device, &texture_bind_group_layout);
-                Self::make_pipeline(&gpu.device, &gpu.config, &pipeline_layout)
-            })
-            .clone();
+        let pipeline = Self::get_pipeline_desc(gpu, params, normal_rgba.is_some());
+
+        let material_uniform = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simple material uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<MaterialUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        gpu.queue.write_buffer(
+            &material_uniform,
+            0,
+            bytemuck::bytes_of(&MaterialUniform::from(params)),
+        );
 
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let normal_view = normal_map.create_view(&wgpu::TextureViewDescriptor::default());
@@ -281,7 +360,7 @@ impl SimpleMaterial {
 
         let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: "Simple material texture data bind group".into(),
-            layout: &texture_bind_group_layout,
+            layout: &gpu.layouts.material,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -295,6 +374,14 @@ impl SimpleMaterial {
                     binding: 2,
                     resource: wgpu::BindingResource::Sampler(&sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &material_uniform,
+                        offset: 0,
+                        size: NonZero::new(size_of::<MaterialUniform>() as u64),
+                    }),
+                },
             ],
         });
 
@@ -303,6 +390,27 @@ impl SimpleMaterial {
             texture,
             sampler,
             bind_group,
+            alpha_mode: params.alpha_mode,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_shaders() {
+        let shaders = [
+            ShaderKey::builtin("simple"),
+            ShaderKey::builtin("simple").with_define("NORMAL_MAP").with_define("ALPHA_TEST"),
+            ShaderKey::builtin("simple").with_define("ALPHA_BLEND"),
+            ShaderKey::builtin("shadow").with_define("ALPHA_TEST"),
+            ShaderKey::builtin("shadow").with_define("ALPHA_TEST").with_define("LINEAR_DEPTH"),
+        ];
+        for shader in shaders {
+            let module = shader.load(false).unwrap().parse().unwrap();
+            MaterialUniform::layout().check(&module, BindGroupLayouts::MATERIAL_GROUP, 3).unwrap();
         }
     }
 }
@@ -1,10 +1,13 @@
-use crate::{Gpu, data::Vertex};
+use crate::{Gpu, bounds::Aabb, data::Vertex};
+use glam::Vec3;
 use std::mem::size_of;
 
 pub struct Mesh {
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     vtx_count: u32,
+    /// Local space bounds, `None` for an empty mesh.
+    pub bounds: Option<Aabb>,
 }
 
 impl Mesh {
@@ -31,17 +34,28 @@ impl Mesh {
     }
 
     pub fn new(gpu: &Gpu, vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
-        let vertex_buffer = Self::make_vertex_buffer(&gpu.device, &vertices);
-        gpu.queue
-            .write_buffer(&vertex_buffer, 0, &bytemuck::cast_slice(&vertices));
-        let index_buffer = Self::make_index_buffer(&gpu.device, &indices);
-        gpu.queue
-            .write_buffer(&index_buffer, 0, &bytemuck::cast_slice(&indices));
+        Self::from_device(&gpu.device, &gpu.queue, vertices, indices)
+    }
+
+    /// Same as `new`, without needing a window to render to.
+    pub fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    ) -> Self {
+        let vertex_buffer = Self::make_vertex_buffer(device, &vertices);
+        queue.write_buffer(&vertex_buffer, 0, &bytemuck::cast_slice(&vertices));
+        let index_buffer = Self::make_index_buffer(device, &indices);
+        queue.write_buffer(&index_buffer, 0, &bytemuck::cast_slice(&indices));
+
+        let bounds = Aabb::from_points(vertices.iter().map(|vertex| Vec3::from(vertex.pos)));
 
         Self {
             vertex_buffer,
             index_buffer,
             vtx_count: indices.len() as u32,
+            bounds,
         }
     }
 
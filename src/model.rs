@@ -1,29 +1,39 @@
 use std::path::Path;
 
 use glam::Mat4;
-use gltf::camera::{Perspective, Projection};
+use gltf::camera::Projection as GltfProjection;
 use gltf::mesh::util::{ReadNormals, ReadPositions};
 use image::RgbaImage;
 use tobj::LoadError;
 
-use bytemuck::NoUninit;
+use crate::uniform::uniform_struct;
 use std::num::NonZero;
 
-use crate::camera::Camera;
+use crate::camera::{Camera, Projection};
 use crate::object::DataStore;
 use crate::{
     data::Vertex,
     gpu::Gpu,
-    material::{Material, SimpleMaterial},
+    material::{AlphaMode, Material, MaterialParams, SimpleMaterial},
     mesh::Mesh,
     object::Object,
 };
 
-#[repr(C, packed)]
-#[derive(Copy, Clone, NoUninit)]
-struct ModelUniform {
-    pub model: Mat4,
-    pub normal: Mat4,
+uniform_struct! {
+    pub struct ModelUniform {
+        pub model: Mat4,
+        pub normal: Mat4,
+        pub receive_shadows: u32,
+        _padding: [u32; 3],
+    }
+}
+
+// OBJ files have no notion of double sided materials, so it has to be chosen on import
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ObjOptions {
+    pub double_sided: bool,
+    // Overrides the alpha mode guessed from the materials and textures
+    pub alpha_mode: Option<AlphaMode>,
 }
 
 // TODO - Generalize this to multiple materials
@@ -61,25 +71,9 @@ impl Model {
             mapped_at_creation: false,
         });
 
-        let model_uniform_layout =
-            gpu.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: "Model uniform variables layout".into(),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
-
         let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: "Model uniform bind group".into(),
-            layout: &model_uniform_layout,
+            layout: &gpu.layouts.object,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
@@ -98,12 +92,26 @@ impl Model {
         }
     }
 
-    fn parse_gltf_camera(gpu: &Gpu, store: &mut DataStore, perspective: Perspective) -> Option<Object> {
-        let fov = perspective.yfov();
-        let far = perspective.zfar().unwrap_or(Camera::DEFAULT_FAR);
-        let near = perspective.znear();
+    fn parse_gltf_camera(gpu: &Gpu, store: &mut DataStore, camera: gltf::Camera) -> Option<Object> {
+        let (projection, near, far) = match camera.projection() {
+            GltfProjection::Perspective(perspective) => (
+                Projection::Perspective {
+                    fov: perspective.yfov(),
+                },
+                perspective.znear(),
+                perspective.zfar().unwrap_or(Camera::DEFAULT_FAR),
+            ),
+            GltfProjection::Orthographic(orthographic) => (
+                Projection::Orthographic {
+                    xmag: orthographic.xmag(),
+                    ymag: orthographic.ymag(),
+                },
+                orthographic.znear(),
+                orthographic.zfar(),
+            ),
+        };
 
-        Some(Camera::new_custom(gpu, store, fov, near, far))
+        Some(Camera::new_custom(gpu, store, projection, near, far))
     }
 
     fn parse_gltf_mesh(
@@ -163,18 +171,30 @@ impl Model {
                 data.width, data.height, data.pixels.clone()
             )?;
 
-            let idx = primitive
-                .material()
-                .normal_texture()?
-                .texture()
-                .source()
-                .index();
-            let data = &images[idx];
-            let normal_rgba = RgbaImage::from_raw(
-                data.width, data.height, data.pixels.clone()
-            )?;
+            let normal_rgba = match primitive.material().normal_texture() {
+                Some(normal) => {
+                    let data = &images[normal.texture().source().index()];
+                    Some(RgbaImage::from_raw(data.width, data.height, data.pixels.clone())?)
+                }
+                None => None,
+            };
 
-            let material = Box::new(SimpleMaterial::new(&gpu, &texture_rgba, &normal_rgba));
+            let params = MaterialParams {
+                base_color: primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor()
+                    .into(),
+                alpha_mode: primitive.material().into(),
+                double_sided: primitive.material().double_sided(),
+            };
+
+            let material = Box::new(SimpleMaterial::new(
+                &gpu,
+                &texture_rgba,
+                normal_rgba.as_ref(),
+                params,
+            ));
             let mesh = Mesh::new(gpu, vertices, indices);
             let model = Self::new(gpu, mesh, material);
             let obj = Object::new(model, store);
@@ -195,10 +215,8 @@ impl Model {
             .flat_map(|child| Self::parse_node(gpu, store, child, buffers, images))
             .collect();
         
-        let obj = if let Some(camera) = node.camera()
-            && let Projection::Perspective(perspective) = camera.projection()
-        {
-            Self::parse_gltf_camera(gpu, store, perspective)
+        let obj = if let Some(camera) = node.camera() {
+            Self::parse_gltf_camera(gpu, store, camera)
         } else if let Some(mesh) = node.mesh() {
             Self::parse_gltf_mesh(gpu, store, mesh, buffers, images)
         } else {
@@ -228,6 +246,15 @@ impl Model {
     }
 
     pub fn load_obj(gpu: &Gpu, store: &mut DataStore, path: &Path) -> Result<Object, LoadError> {
+        Self::load_obj_custom(gpu, store, path, ObjOptions::default())
+    }
+
+    pub fn load_obj_custom(
+        gpu: &Gpu,
+        store: &mut DataStore,
+        path: &Path,
+        options: ObjOptions,
+    ) -> Result<Object, LoadError> {
         let (models, materials) = tobj::load_obj(&path, &tobj::GPU_LOAD_OPTIONS)?;
         let materials = materials.unwrap();
         let mut objs = Vec::<Model>::new();
@@ -261,20 +288,57 @@ impl Model {
                 && let Some(normal) = &materials[id].normal_texture
             {
                 if let Some("-bm") = normal.split_whitespace().next() {
-                    normal.splitn(3, " ").last().unwrap()
+                    Some(normal.splitn(3, " ").last().unwrap())
                 } else {
-                    normal
+                    Some(normal.as_str())
                 }
             } else {
-                "src/res/star.png"
+                None
             };
 
             let texture_bytes = std::fs::read(texture_path).unwrap();
             let texture_rgba = image::load_from_memory(&texture_bytes).unwrap().to_rgba8();
-            let normal_bytes = std::fs::read(normal_path).unwrap();
-            let normal_rgba = image::load_from_memory(&texture_bytes).unwrap().to_rgba8();
+            let normal_rgba = normal_path.map(|normal_path| {
+                let normal_bytes = std::fs::read(normal_path).unwrap();
+                image::load_from_memory(&normal_bytes).unwrap().to_rgba8()
+            });
+
+            let dissolve = model
+                .mesh
+                .material_id
+                .and_then(|id| materials[id].dissolve)
+                .unwrap_or(1.0);
+
+            // OBJ has no alpha modes, so partial dissolve is blended while
+            // dissolve maps and textures with transparent texels are cut out,
+            // such as the fallback star sprite
+            let alpha_mode = if let Some(alpha_mode) = options.alpha_mode {
+                alpha_mode
+            } else if dissolve < 1.0 {
+                AlphaMode::Blend
+            } else if model
+                .mesh
+                .material_id
+                .is_some_and(|id| materials[id].dissolve_texture.is_some())
+                || texture_rgba.pixels().any(|pixel| pixel[3] < u8::MAX)
+            {
+                AlphaMode::Mask(0.5)
+            } else {
+                AlphaMode::Opaque
+            };
+
+            let params = MaterialParams {
+                base_color: glam::Vec4::new(1.0, 1.0, 1.0, dissolve),
+                alpha_mode,
+                double_sided: options.double_sided,
+            };
 
-            let material = Box::new(SimpleMaterial::new(&gpu, &texture_rgba, &normal_rgba));
+            let material = Box::new(SimpleMaterial::new(
+                &gpu,
+                &texture_rgba,
+                normal_rgba.as_ref(),
+                params,
+            ));
 
             for point_idx in model.mesh.indices.chunks_exact(3) {
                 let (a, b, c) = Self::fill_tangents(
@@ -301,10 +365,12 @@ impl Model {
         Ok(result)
     }
 
-    pub fn update_model_uniform(&self, gpu: &Gpu, xform: glam::Mat4) {
+    pub fn update_model_uniform(&self, gpu: &Gpu, xform: glam::Mat4, receive_shadows: bool) {
         let uniform_data = ModelUniform {
             model: xform,
             normal: xform.inverse().transpose(),
+            receive_shadows: receive_shadows.into(),
+            _padding: Default::default(),
         };
 
         gpu.queue
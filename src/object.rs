@@ -6,13 +6,16 @@ use strum::EnumTryAs;
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
 
+use crate::bounds::Aabb;
 use crate::camera::Camera;
+use crate::light::Light;
 use crate::model::Model;
 
 #[derive(Default)]
 pub struct DataStore {
     models: Slab<Model>,
     cameras: Slab<Camera>,
+    lights: Slab<Light>,
 }
 
 impl DataStore {
@@ -26,13 +29,26 @@ impl DataStore {
         DataToken::Camera(id)
     }
 
-    pub fn get_model(&mut self, id: usize) -> Option<&mut Model> {
+    pub fn add_light(&mut self, light: Light) -> DataToken {
+        let id = self.lights.insert(light);
+        DataToken::Light(id)
+    }
+
+    pub fn get_model(&self, id: usize) -> Option<&Model> {
+        self.models.get(id)
+    }
+
+    pub fn get_model_mut(&mut self, id: usize) -> Option<&mut Model> {
         self.models.get_mut(id)
     }
 
     pub fn get_camera(&mut self, id: usize) -> Option<&mut Camera> {
         self.cameras.get_mut(id)
     }
+
+    pub fn get_light(&self, id: usize) -> Option<&Light> {
+        self.lights.get(id)
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumTryAs)]
@@ -40,11 +56,14 @@ pub enum DataToken {
     Empty,
     Model(usize),
     Camera(usize),
+    Light(usize),
 }
 
 struct ObjectInternal {
     xform: Mat4,
     data: DataToken,
+    cast_shadows: bool,
+    receive_shadows: bool,
     parent: Weak<RefCell<ObjectInternal>>,
     children: Vec<Object>,
 }
@@ -59,6 +78,12 @@ impl IntoData for Camera {
     }
 }
 
+impl IntoData for Light {
+    fn into_data(self, store: &mut DataStore) -> DataToken {
+        store.add_light(self)
+    }
+}
+
 impl IntoData for Model {
     fn into_data(self, store: &mut DataStore) -> DataToken {
         store.add_model(self)
@@ -73,6 +98,8 @@ impl Object {
         Self(Rc::new(RefCell::new(ObjectInternal {
             data: data.into_data(store),
             xform: Default::default(),
+            cast_shadows: true,
+            receive_shadows: true,
             parent: Weak::new(),
             children: Vec::new(),
         })))
@@ -82,29 +109,45 @@ impl Object {
         Self(Rc::new(RefCell::new(ObjectInternal {
             data: DataToken::Empty,
             xform: Default::default(),
+            cast_shadows: true,
+            receive_shadows: true,
             parent: Weak::new(),
             children: Vec::new(),
         })))
     }
 
     pub fn with_children(self, children: Vec<Object>) -> Self {
+        self.adopt(&children);
         self.0.borrow_mut().children = children;
         self
     }
 
     pub fn add_children(&mut self, children: Vec<Object>) {
+        self.adopt(&children);
         self.0.borrow_mut().children.extend(children);
     }
 
+    fn adopt(&self, children: &[Object]) {
+        for child in children {
+            child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+        }
+    }
+
+    /// Transform from the parent's space to world space, combining the
+    /// transforms of every ancestor.
     pub fn get_parent_xform(&self) -> Mat4 {
         self.0
             .borrow()
             .parent
             .upgrade()
-            .map(|parent| Object(parent).get_local_xform())
+            .map(|parent| Object(parent).get_world_xform())
             .unwrap_or_default()
     }
 
+    pub fn get_world_xform(&self) -> Mat4 {
+        self.get_parent_xform() * self.get_local_xform()
+    }
+
     pub fn get_local_xform(&self) -> Mat4 {
         self.0.borrow().xform
     }
@@ -117,7 +160,40 @@ impl Object {
         self.0.borrow().data
     }
 
+    pub fn casts_shadows(&self) -> bool {
+        self.0.borrow().cast_shadows
+    }
+
+    /// Also sets it on the children, so that it applies to whole loaded models.
+    pub fn set_cast_shadows(&mut self, cast_shadows: bool) {
+        for (obj, _) in self.get_all() {
+            obj.0.borrow_mut().cast_shadows = cast_shadows;
+        }
+    }
+
+    pub fn with_cast_shadows(mut self, cast_shadows: bool) -> Self {
+        self.set_cast_shadows(cast_shadows);
+        self
+    }
+
+    pub fn receives_shadows(&self) -> bool {
+        self.0.borrow().receive_shadows
+    }
+
+    /// Also sets it on the children, like `set_cast_shadows`.
+    pub fn set_receive_shadows(&mut self, receive_shadows: bool) {
+        for (obj, _) in self.get_all() {
+            obj.0.borrow_mut().receive_shadows = receive_shadows;
+        }
+    }
+
+    pub fn with_receive_shadows(mut self, receive_shadows: bool) -> Self {
+        self.set_receive_shadows(receive_shadows);
+        self
+    }
+
     pub fn add_child(&self, obj: Object) {
+        self.adopt(std::slice::from_ref(&obj));
         self.0.borrow_mut().children.push(obj);
     }
 
@@ -167,6 +243,42 @@ impl Object {
             .collect()
     }
 
+    /// World space bounds of the models in this object and its children,
+    /// `None` if there are none.
+    pub fn bounds(&self, store: &DataStore) -> Option<Aabb> {
+        self.get_all()
+            .into_iter()
+            .filter_map(|(obj, xform)| {
+                let id = obj.get_data().try_as_model()?;
+                let bounds = store.get_model(id)?.mesh.bounds?;
+                Some(bounds.transform(xform))
+            })
+            .reduce(Aabb::union)
+    }
+
+    /// Turns the object so that its +Z axis points at `target`, keeping its
+    /// position and scale. `target` and `up` are in the parent's space.
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        let (scale, _, position) = self.get_local_xform().to_scale_rotation_translation();
+        let Some(direction) = (target - position).try_normalize() else {
+            return;
+        };
+        // Looking straight along `up` leaves the roll undefined, any other axis does
+        let up = if direction.cross(up).length_squared() <= f32::EPSILON {
+            up.any_orthonormal_vector()
+        } else {
+            up
+        };
+
+        let rotation = Mat4::look_to_lh(Vec3::ZERO, direction, up).transpose();
+        self.set_xform(Mat4::from_translation(position) * rotation * Mat4::from_scale(scale));
+    }
+
+    pub fn with_look_at(mut self, target: Vec3, up: Vec3) -> Self {
+        self.look_at(target, up);
+        self
+    }
+
     pub fn translate(&mut self, translation: Vec3) {
         self.0.borrow_mut().xform *= Mat4::from_translation(translation);
     }
@@ -216,3 +328,41 @@ impl Object {
         self.0.borrow_mut().xform = Mat4::IDENTITY;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadow_flags_apply_to_children() {
+        let child = Object::empty();
+        let object = Object::empty()
+            .with_children(vec![Object::empty().with_children(vec![child.clone()])])
+            .with_cast_shadows(false);
+        assert!(!object.casts_shadows() && !child.casts_shadows());
+        assert!(object.receives_shadows() && child.receives_shadows());
+
+        let object = object.with_receive_shadows(false).with_cast_shadows(true);
+        assert!(object.casts_shadows() && child.casts_shadows());
+        assert!(!object.receives_shadows() && !child.receives_shadows());
+    }
+
+    #[test]
+    fn world_xforms_combine_every_ancestor() {
+        let child = Object::empty().with_translation(Vec3::new(0.0, 0.0, 1.0));
+        let parent = Object::empty()
+            .with_rotation_y(std::f32::consts::FRAC_PI_2)
+            .with_children(vec![child.clone()]);
+        let _root = Object::empty()
+            .with_translation(Vec3::new(1.0, 2.0, 3.0))
+            .with_children(vec![parent]);
+
+        let position = child.get_world_xform().transform_point3(Vec3::ZERO);
+        assert!(
+            position.abs_diff_eq(Vec3::new(2.0, 2.0, 3.0), 1e-5),
+            "{position}"
+        );
+        let parent_position = child.get_parent_xform().transform_point3(Vec3::ZERO);
+        assert!(parent_position.abs_diff_eq(Vec3::new(1.0, 2.0, 3.0), 1e-5));
+    }
+}
@@ -0,0 +1,116 @@
+use glam::{Mat4, Quat, Vec2, Vec3};
+
+use crate::{camera::Projection, input::UserInput, object::DataStore, scene::Scene};
+
+/// Turntable camera turning around a pivot, for inspecting a model.
+pub struct OrbitController {
+    pub pivot: Vec3,
+}
+
+impl OrbitController {
+    // Radians per unit of mouse motion
+    const ROTATE_SPEED: f32 = 0.005;
+    // Distance or orthographic size change per wheel step
+    const ZOOM_FACTOR: f32 = 1.1;
+    const MIN_DISTANCE: f32 = 0.01;
+    const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+    pub fn new(pivot: Vec3) -> Self {
+        Self { pivot }
+    }
+
+    /// Moves the active camera around the pivot. The camera keeps its
+    /// distance, but turns to face the pivot if it wasn't already. Zooming
+    /// scales orthographic projections, as the distance doesn't show.
+    /// Panning drags the scene along with the cursor, taking mouse motion
+    /// to be in pixels of the viewport.
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        store: &mut DataStore,
+        viewport: Vec2,
+        input: UserInput,
+    ) {
+        let Some(camera) = scene.get_camera_object() else {
+            return;
+        };
+        let xform = camera.get_local_xform();
+        let (scale, _, position) = xform.to_scale_rotation_translation();
+
+        let offset = self.pivot - position;
+        let distance = offset.length().max(Self::MIN_DISTANCE);
+        let forward = if offset.length_squared() > 0.0 {
+            offset / offset.length()
+        } else {
+            Vec3::Z
+        };
+        let mut yaw = forward.x.atan2(forward.z);
+        let mut pitch = (-forward.y).asin();
+
+        if input.pan {
+            // Moves the pivot to what was under the cursor, on the plane
+            // through the pivot facing the camera
+            if viewport.cmpgt(Vec2::ZERO).all()
+                && let Some(lens) = camera
+                    .get_data()
+                    .try_as_camera()
+                    .and_then(|id| store.get_camera(id))
+                && let Some(pixel) = lens.project(xform, viewport, self.pivot)
+            {
+                let motion = Vec2::new(input.yaw, input.pitch);
+                let ray = lens.unproject(xform, viewport, pixel.truncate() - motion);
+                let distance = (self.pivot - ray.origin).dot(forward) / ray.direction.dot(forward);
+                self.pivot = ray.at(distance);
+            }
+        } else if input.rotate {
+            yaw += input.yaw * Self::ROTATE_SPEED;
+            pitch += input.pitch * Self::ROTATE_SPEED;
+        }
+        let pitch = pitch.clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        let zoom = Self::ZOOM_FACTOR.powf(-input.zoom);
+        let projection = camera
+            .get_data()
+            .try_as_camera()
+            .and_then(|id| store.get_camera(id))
+            .map(|lens| &mut lens.projection);
+        let distance = match projection {
+            Some(Projection::Orthographic { xmag, ymag }) => {
+                *xmag *= zoom;
+                *ymag *= zoom;
+                distance
+            }
+            _ => (distance * zoom).max(Self::MIN_DISTANCE),
+        };
+
+        let rotation = Quat::from_rotation_y(yaw) * Quat::from_rotation_x(pitch);
+        let position = self.pivot - rotation * Vec3::Z * distance;
+        camera.set_xform(Mat4::from_scale_rotation_translation(
+            scale, rotation, position,
+        ));
+    }
+
+    /// Switches the active camera between perspective and orthographic
+    /// projections, keeping the pivot's surroundings the same size on screen.
+    pub fn toggle_projection(&self, scene: &mut Scene, store: &mut DataStore, aspect: f32) {
+        let Some(camera) = scene.get_camera_object() else {
+            return;
+        };
+        let Some(lens) = camera
+            .get_data()
+            .try_as_camera()
+            .and_then(|id| store.get_camera(id))
+        else {
+            return;
+        };
+        let (scale, rotation, position) = camera.get_local_xform().to_scale_rotation_translation();
+
+        let (projection, distance) = lens
+            .projection
+            .toggled(self.pivot.distance(position), aspect);
+        lens.projection = projection;
+        let position = self.pivot - rotation * Vec3::Z * distance;
+        camera.set_xform(Mat4::from_scale_rotation_translation(
+            scale, rotation, position,
+        ));
+    }
+}
@@ -1,76 +1,904 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Quat, Vec3};
+use slab::Slab;
 
-use crate::{object::DataStore, scene::Scene};
+use crate::{bounds::Aabb, object::Object};
 
-#[derive(Default, Copy, Clone)]
-pub struct UserInput {
-    pub move_forward: bool,
-    pub move_backward: bool,
-    pub move_left: bool,
-    pub move_right: bool,
-    pub move_up: bool,
-    pub move_down: bool,
-    pub yaw: f32,
-    pub pitch: f32,
+/// Shape a rigid body collides as, centered on its object and turned with
+/// it. Sizes are in world units, whatever the object's scale.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Collider {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_extents: Vec3,
+    },
+    /// Along the object's Y axis, `half_height` being the distance to the
+    /// centers of its end caps.
+    Capsule {
+        radius: f32,
+        half_height: f32,
+    },
 }
 
-impl UserInput {
-    pub fn direction(&self) -> Vec3 {
-        let mut direction = Vec3::ZERO;
+/// Physical properties of an object in a `PhysicsWorld`.
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    pub collider: Collider,
+    // Zero for static bodies, which nothing can move
+    inverse_mass: f32,
+    pub velocity: Vec3,
+    /// How much of its speed a body keeps when bouncing, from 0 to 1.
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl RigidBody {
+    const DEFAULT_RESTITUTION: f32 = 0.2;
+    const DEFAULT_FRICTION: f32 = 0.5;
+
+    fn new(collider: Collider, inverse_mass: f32) -> Self {
+        Self {
+            collider,
+            inverse_mass,
+            velocity: Vec3::ZERO,
+            restitution: Self::DEFAULT_RESTITUTION,
+            friction: Self::DEFAULT_FRICTION,
+        }
+    }
+
+    /// A body moved by gravity and collisions, `mass` being in kilograms.
+    pub fn new_dynamic(mass: f32, collider: Collider) -> Self {
+        Self::new(collider, 1.0 / mass)
+    }
+
+    /// A body that stays in place, such as the ground.
+    pub fn new_static(collider: Collider) -> Self {
+        Self::new(collider, 0.0)
+    }
+
+    pub fn with_velocity(mut self, velocity: Vec3) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn with_restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    pub fn with_friction(mut self, friction: f32) -> Self {
+        self.friction = friction;
+        self
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.inverse_mass == 0.0
+    }
+}
+
+// A collider placed in the world
+enum Shape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        axes: [Vec3; 3],
+        half_extents: Vec3,
+    },
+    Capsule {
+        start: Vec3,
+        end: Vec3,
+        radius: f32,
+    },
+}
+
+// Pushing `b` along the normal by the depth separates it from `a`
+struct Contact {
+    a: usize,
+    b: usize,
+    normal: Vec3,
+    depth: f32,
+}
+
+impl Shape {
+    const CAPSULE_BOX_ITERATIONS: usize = 4;
+
+    fn new(collider: Collider, position: Vec3, rotation: Quat) -> Self {
+        match collider {
+            Collider::Sphere { radius } => Self::Sphere {
+                center: position,
+                radius,
+            },
+            Collider::Box { half_extents } => Self::Box {
+                center: position,
+                axes: [rotation * Vec3::X, rotation * Vec3::Y, rotation * Vec3::Z],
+                half_extents,
+            },
+            Collider::Capsule {
+                radius,
+                half_height,
+            } => {
+                let axis = rotation * Vec3::Y * half_height;
+                Self::Capsule {
+                    start: position - axis,
+                    end: position + axis,
+                    radius,
+                }
+            }
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        match *self {
+            Self::Sphere { center, radius } => Aabb {
+                min: center - radius,
+                max: center + radius,
+            },
+            Self::Box {
+                center,
+                axes,
+                half_extents,
+            } => {
+                let extent = axes[0].abs() * half_extents.x
+                    + axes[1].abs() * half_extents.y
+                    + axes[2].abs() * half_extents.z;
+                Aabb {
+                    min: center - extent,
+                    max: center + extent,
+                }
+            }
+            Self::Capsule { start, end, radius } => Aabb {
+                min: start.min(end) - radius,
+                max: start.max(end) + radius,
+            },
+        }
+    }
+
+    // Normal pointing from this shape into the other one, and their overlap
+    fn collide(&self, other: &Self) -> Option<(Vec3, f32)> {
+        use Shape::*;
+
+        match (self, other) {
+            (
+                Sphere { center, radius },
+                Sphere {
+                    center: other_center,
+                    radius: other_radius,
+                },
+            ) => collide_spheres(*center, *radius, *other_center, *other_radius),
+            (
+                Sphere { center, radius },
+                Capsule {
+                    start,
+                    end,
+                    radius: other_radius,
+                },
+            ) => {
+                let closest = closest_on_segment(*start, *end, *center);
+                collide_spheres(*center, *radius, closest, *other_radius)
+            }
+            (
+                Capsule { start, end, radius },
+                Sphere {
+                    center,
+                    radius: other_radius,
+                },
+            ) => {
+                let closest = closest_on_segment(*start, *end, *center);
+                collide_spheres(closest, *radius, *center, *other_radius)
+            }
+            (
+                Capsule { start, end, radius },
+                Capsule {
+                    start: other_start,
+                    end: other_end,
+                    radius: other_radius,
+                },
+            ) => {
+                let (closest, other_closest) =
+                    closest_between_segments(*start, *end, *other_start, *other_end);
+                collide_spheres(closest, *radius, other_closest, *other_radius)
+            }
+            (Sphere { center, radius }, Box { .. }) => collide_sphere_box(*center, *radius, other),
+            (Capsule { start, end, radius }, Box { .. }) => {
+                // Converges on the point of the segment closest to the box
+                let mut closest = (*start + *end) / 2.0;
+                for _ in 0..Self::CAPSULE_BOX_ITERATIONS {
+                    let on_box = other.closest_box_point(closest);
+                    closest = closest_on_segment(*start, *end, on_box);
+                }
+                collide_sphere_box(closest, *radius, other)
+            }
+            (Box { .. }, Sphere { .. } | Capsule { .. }) => {
+                other.collide(self).map(|(normal, depth)| (-normal, depth))
+            }
+            (Box { .. }, Box { .. }) => collide_boxes(self, other),
+        }
+    }
+
+    fn closest_box_point(&self, point: Vec3) -> Vec3 {
+        let Self::Box {
+            center,
+            axes,
+            half_extents,
+        } = *self
+        else {
+            return point;
+        };
+        let offset = point - center;
+
+        (0..3).fold(center, |closest, axis| {
+            let distance = offset.dot(axes[axis]);
+            closest + axes[axis] * distance.clamp(-half_extents[axis], half_extents[axis])
+        })
+    }
+}
+
+fn closest_on_segment(start: Vec3, end: Vec3, point: Vec3) -> Vec3 {
+    let segment = end - start;
+    let length_squared = segment.length_squared();
+    if length_squared <= f32::EPSILON {
+        return start;
+    }
+
+    start + segment * ((point - start).dot(segment) / length_squared).clamp(0.0, 1.0)
+}
+
+// Closest points of two segments, from Real-Time Collision Detection 5.1.9
+fn closest_between_segments(p1: Vec3, q1: Vec3, p2: Vec3, q2: Vec3) -> (Vec3, Vec3) {
+    let (d1, d2, r) = (q1 - p1, q2 - p2, p1 - p2);
+    let (a, e, f) = (d1.length_squared(), d2.length_squared(), d2.dot(r));
+
+    let (s, t) = if a <= f32::EPSILON && e <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if a <= f32::EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denominator = a * e - b * b;
+            let s = if denominator > f32::EPSILON {
+                ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).clamp(0.0, 1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+fn collide_spheres(a: Vec3, radius_a: f32, b: Vec3, radius_b: f32) -> Option<(Vec3, f32)> {
+    let offset = b - a;
+    let distance = offset.length();
+    let depth = radius_a + radius_b - distance;
+    if depth <= 0.0 {
+        return None;
+    }
+    // Concentric spheres are pushed apart upwards
+    let normal = if distance > f32::EPSILON {
+        offset / distance
+    } else {
+        Vec3::Y
+    };
+
+    Some((normal, depth))
+}
+
+fn collide_sphere_box(center: Vec3, radius: f32, shape: &Shape) -> Option<(Vec3, f32)> {
+    let Shape::Box {
+        center: box_center,
+        axes,
+        half_extents,
+    } = *shape
+    else {
+        return None;
+    };
+
+    let closest = shape.closest_box_point(center);
+    let offset = closest - center;
+    let distance = offset.length();
+    if distance > f32::EPSILON {
+        return (distance < radius).then(|| (offset / distance, radius - distance));
+    }
+
+    // Inside the box, the nearest face pushes the sphere out
+    let local = center - box_center;
+    (0..3)
+        .map(|axis| {
+            let distance = local.dot(axes[axis]);
+            let normal = -axes[axis] * distance.signum();
+            (normal, radius + half_extents[axis] - distance.abs())
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+// Separating axis test, with the normal along the axis of least overlap
+fn collide_boxes(a: &Shape, b: &Shape) -> Option<(Vec3, f32)> {
+    let (
+        Shape::Box {
+            center: center_a,
+            axes: axes_a,
+            half_extents: half_a,
+        },
+        Shape::Box {
+            center: center_b,
+            axes: axes_b,
+            half_extents: half_b,
+        },
+    ) = (a, b)
+    else {
+        return None;
+    };
+
+    let offset = *center_b - *center_a;
+    let project = |axes: &[Vec3; 3], half_extents: &Vec3, axis: Vec3| {
+        (0..3)
+            .map(|idx| axes[idx].dot(axis).abs() * half_extents[idx])
+            .sum::<f32>()
+    };
+    let edges = axes_a
+        .iter()
+        .flat_map(|axis_a| axes_b.iter().map(|axis_b| axis_a.cross(*axis_b)));
+
+    let mut best: Option<(Vec3, f32)> = None;
+    for axis in axes_a.iter().chain(axes_b).copied().chain(edges) {
+        // Nearly parallel edges give no reliable axis, their faces are tested already
+        if axis.length_squared() < 1e-6 {
+            continue;
+        }
+        let axis = axis.normalize();
+        let distance = offset.dot(axis);
+        let depth = project(axes_a, half_a, axis) + project(axes_b, half_b, axis) - distance.abs();
+        if depth <= 0.0 {
+            return None;
+        }
+        if best.is_none_or(|(_, best_depth)| depth < best_depth) {
+            let normal = if distance < 0.0 { -axis } else { axis };
+            best = Some((normal, depth));
+        }
+    }
+
+    best
+}
+
+// A body along with where its object is, which the world owns while simulating
+struct Body {
+    object: Object,
+    body: RigidBody,
+    scale: Vec3,
+    rotation: Quat,
+    previous_position: Vec3,
+    position: Vec3,
+}
+
+/// Rigid bodies moved by gravity and pushed apart by collisions. Bodies keep
+/// the orientation of their objects, collisions don't spin them.
+///
+/// Positions are read from and written to the objects' local transforms, so
+/// objects with bodies should share a parent.
+pub struct PhysicsWorld {
+    pub gravity: Vec3,
+    bodies: Slab<Body>,
+}
 
-        if self.move_forward {
-            direction -= Vec3::Z;
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self {
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            bodies: Slab::new(),
         }
+    }
+}
+
+impl PhysicsWorld {
+    const SOLVER_ITERATIONS: usize = 8;
+    // Overlap left alone, so that resting contacts are still found next step
+    const PENETRATION_SLOP: f32 = 0.005;
+    // Fraction of the remaining overlap removed each step
+    const CORRECTION: f32 = 0.8;
+    // Slower impacts don't bounce, so that resting bodies settle
+    const RESTING_SPEED: f32 = 0.5;
+
+    /// Simulates an object, starting from where it is. Returns an id to
+    /// reach its body with.
+    pub fn add_body(&mut self, object: Object, body: RigidBody) -> usize {
+        let (scale, rotation, position) = object.get_local_xform().to_scale_rotation_translation();
+
+        self.bodies.insert(Body {
+            object,
+            body,
+            scale,
+            rotation,
+            previous_position: position,
+            position,
+        })
+    }
+
+    pub fn get_body_mut(&mut self, id: usize) -> Option<&mut RigidBody> {
+        self.bodies.get_mut(id).map(|body| &mut body.body)
+    }
+
+    /// Moves a body, without it moving through anything in between.
+    pub fn teleport(&mut self, id: usize, position: Vec3) {
+        if let Some(body) = self.bodies.get_mut(id) {
+            body.previous_position = position;
+            body.position = position;
+        }
+    }
+
+    // Sweep and prune along X, then exact tests for the boxes that overlap
+    fn find_contacts(&self) -> Vec<Contact> {
+        let mut shapes: Vec<_> = self
+            .bodies
+            .iter()
+            .map(|(id, body)| {
+                let shape = Shape::new(body.body.collider, body.position, body.rotation);
+                let bounds = shape.bounds();
+                (id, body.body.is_static(), shape, bounds)
+            })
+            .collect();
+        shapes.sort_by(|(.., a), (.., b)| a.min.x.total_cmp(&b.min.x));
+
+        let mut contacts = Vec::new();
+        for (idx, (a, a_static, shape_a, bounds_a)) in shapes.iter().enumerate() {
+            for (b, b_static, shape_b, bounds_b) in &shapes[idx + 1..] {
+                if bounds_b.min.x > bounds_a.max.x {
+                    break;
+                }
+                if (*a_static && *b_static) || !bounds_a.intersects(bounds_b) {
+                    continue;
+                }
+                if let Some((normal, depth)) = shape_a.collide(shape_b) {
+                    contacts.push(Contact {
+                        a: *a,
+                        b: *b,
+                        normal,
+                        depth,
+                    });
+                }
+            }
+        }
+
+        contacts
+    }
 
-        if self.move_backward {
-            direction += Vec3::Z;
+    // Impulse stopping the bodies from moving into each other, with Coulomb friction
+    fn resolve_velocity(&mut self, contact: &Contact) {
+        let Some((a, b)) = self.bodies.get2_mut(contact.a, contact.b) else {
+            return;
+        };
+        let (a, b) = (&mut a.body, &mut b.body);
+        let inverse_mass = a.inverse_mass + b.inverse_mass;
+        let relative = b.velocity - a.velocity;
+        let normal_speed = relative.dot(contact.normal);
+        if normal_speed >= 0.0 || inverse_mass == 0.0 {
+            return;
         }
 
-        if self.move_left {
-            direction += Vec3::X;
+        let restitution = if -normal_speed < Self::RESTING_SPEED {
+            0.0
+        } else {
+            a.restitution.max(b.restitution)
+        };
+        let normal_impulse = -(1.0 + restitution) * normal_speed / inverse_mass;
+        let mut impulse = contact.normal * normal_impulse;
+
+        let sliding = relative - contact.normal * normal_speed;
+        let sliding_speed = sliding.length();
+        if sliding_speed > f32::EPSILON {
+            let friction = (a.friction * b.friction).sqrt();
+            let friction_impulse = (sliding_speed / inverse_mass).min(friction * normal_impulse);
+            impulse -= sliding / sliding_speed * friction_impulse;
         }
 
-        if self.move_right {
-            direction -= Vec3::X;
+        a.velocity -= impulse * a.inverse_mass;
+        b.velocity += impulse * b.inverse_mass;
+    }
+
+    fn correct_position(&mut self, contact: &Contact) {
+        let Some((a, b)) = self.bodies.get2_mut(contact.a, contact.b) else {
+            return;
+        };
+        let inverse_mass = a.body.inverse_mass + b.body.inverse_mass;
+        if inverse_mass == 0.0 {
+            return;
         }
 
-        if self.move_up {
-            direction -= Vec3::Y;
+        let correction = (contact.depth - Self::PENETRATION_SLOP).max(0.0) * Self::CORRECTION
+            / inverse_mass
+            * contact.normal;
+        a.position -= correction * a.body.inverse_mass;
+        b.position += correction * b.body.inverse_mass;
+    }
+
+    /// Advances the simulation by `delta_time` seconds. Objects only move
+    /// once synced.
+    pub fn step(&mut self, delta_time: f32) {
+        for (_, body) in &mut self.bodies {
+            body.previous_position = body.position;
+            if !body.body.is_static() {
+                body.body.velocity += self.gravity * delta_time;
+            }
         }
 
-        if self.move_down {
-            direction += Vec3::Y;
+        let contacts = self.find_contacts();
+        for _ in 0..Self::SOLVER_ITERATIONS {
+            for contact in &contacts {
+                self.resolve_velocity(contact);
+            }
         }
 
-        if direction.element_sum() > 0.0 {
-            direction = direction.normalize();
+        for (_, body) in &mut self.bodies {
+            body.position += body.body.velocity * delta_time;
         }
+        for contact in &contacts {
+            self.correct_position(contact);
+        }
+    }
 
-        direction
+    /// Moves the objects to where their bodies are, `alpha` of the way from
+    /// the previous step to the last one.
+    pub fn sync(&mut self, alpha: f32) {
+        for (_, body) in &mut self.bodies {
+            let position = body.previous_position.lerp(body.position, alpha);
+            body.object.set_xform(Mat4::from_scale_rotation_translation(
+                body.scale,
+                body.rotation,
+                position,
+            ));
+        }
     }
 }
 
-#[derive(Default)]
-pub struct PhysicsController;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl PhysicsController {
-    pub fn update(&self, scene: &mut Scene, store: &mut DataStore, input: UserInput) {
-        if let Some(camera) = scene.get_camera_object() {
-            let (_, _, pos) = camera.get_local_xform().to_scale_rotation_translation();
+    const STEP: f32 = 1.0 / 60.0;
 
-            let id = camera.get_data().try_as_camera().unwrap();
-            let camera_inner = store.get_camera(id).unwrap();
-            camera_inner.yaw += input.yaw * 0.0025;
-            camera_inner.pitch += input.pitch * 0.0025;
+    fn sphere(center: Vec3, radius: f32) -> Shape {
+        Shape::new(Collider::Sphere { radius }, center, Quat::IDENTITY)
+    }
+
+    fn cube(center: Vec3, rotation: Quat) -> Shape {
+        Shape::new(
+            Collider::Box {
+                half_extents: Vec3::ONE,
+            },
+            center,
+            rotation,
+        )
+    }
+
+    fn capsule(center: Vec3, rotation: Quat) -> Shape {
+        Shape::new(
+            Collider::Capsule {
+                radius: 0.5,
+                half_height: 1.0,
+            },
+            center,
+            rotation,
+        )
+    }
+
+    fn assert_contact(contact: Option<(Vec3, f32)>, normal: Vec3, depth: f32) {
+        let (found_normal, found_depth) = contact.expect("shapes should touch");
+        assert!(
+            found_normal.abs_diff_eq(normal, 1e-4),
+            "normal {found_normal}, expected {normal}"
+        );
+        assert!(
+            (found_depth - depth).abs() < 1e-4,
+            "depth {found_depth}, expected {depth}"
+        );
+    }
+
+    // Two bodies with an empty object each, touching along the normal
+    fn world_with(a: RigidBody, b: RigidBody) -> (PhysicsWorld, Contact) {
+        let mut world = PhysicsWorld::default();
+        let a = world.add_body(Object::empty(), a);
+        let b = world.add_body(Object::empty(), b);
+        let contact = Contact {
+            a,
+            b,
+            normal: Vec3::Y,
+            depth: 0.1,
+        };
+        (world, contact)
+    }
 
-            let rotated = Mat4::from_translation(pos)
-                * Mat4::from_rotation_y(camera_inner.yaw)
-                * Mat4::from_rotation_x(camera_inner.pitch)
-                * camera.get_parent_xform();
+    fn ground() -> RigidBody {
+        RigidBody::new_static(Collider::Box {
+            half_extents: Vec3::new(10.0, 0.5, 10.0),
+        })
+    }
+
+    #[test]
+    fn boxes_separate_along_least_overlap() {
+        let a = cube(Vec3::ZERO, Quat::IDENTITY);
+        assert_contact(
+            a.collide(&cube(Vec3::new(1.5, 0.2, 0.0), Quat::IDENTITY)),
+            Vec3::X,
+            0.5,
+        );
+        assert_contact(
+            a.collide(&cube(Vec3::new(0.1, -1.8, 0.0), Quat::IDENTITY)),
+            -Vec3::Y,
+            0.2,
+        );
+        assert!(
+            a.collide(&cube(Vec3::new(2.1, 0.0, 0.0), Quat::IDENTITY))
+                .is_none()
+        );
+
+        // Turned 45 degrees around Y, its edge reaches sqrt(2) along X
+        let turned = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        assert_contact(
+            a.collide(&cube(Vec3::new(2.3, 0.0, 0.0), turned)),
+            Vec3::X,
+            std::f32::consts::SQRT_2 + 1.0 - 2.3,
+        );
+        assert!(a.collide(&cube(Vec3::new(2.5, 0.0, 0.0), turned)).is_none());
+    }
+
+    #[test]
+    fn sphere_touches_box() {
+        let cube = cube(Vec3::ZERO, Quat::IDENTITY);
+        assert_contact(
+            sphere(Vec3::new(0.0, 1.5, 0.0), 1.0).collide(&cube),
+            -Vec3::Y,
+            0.5,
+        );
+        // Near a corner the normal points at it
+        let corner = Vec3::ONE + Vec3::splat(0.3);
+        assert_contact(
+            sphere(corner, 1.0).collide(&cube),
+            -Vec3::ONE.normalize(),
+            1.0 - Vec3::splat(0.3).length(),
+        );
+        assert!(
+            sphere(Vec3::new(0.0, 2.1, 0.0), 1.0)
+                .collide(&cube)
+                .is_none()
+        );
+        // And the other way around
+        assert_contact(
+            cube.collide(&sphere(Vec3::new(0.0, 1.5, 0.0), 1.0)),
+            Vec3::Y,
+            0.5,
+        );
+    }
+
+    #[test]
+    fn sphere_inside_box_leaves_by_the_nearest_face() {
+        let cube = cube(Vec3::ZERO, Quat::IDENTITY);
+        assert_contact(
+            sphere(Vec3::new(0.0, 0.0, 0.8), 0.5).collide(&cube),
+            -Vec3::Z,
+            0.5 + 1.0 - 0.8,
+        );
+        assert_contact(
+            sphere(Vec3::new(-0.7, 0.1, 0.0), 0.5).collide(&cube),
+            Vec3::X,
+            0.5 + 1.0 - 0.7,
+        );
+    }
+
+    #[test]
+    fn closest_points_between_segments() {
+        // Crossing above each other
+        let (a, b) = closest_between_segments(
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+        );
+        assert!(a.abs_diff_eq(Vec3::ZERO, 1e-5));
+        assert!(b.abs_diff_eq(Vec3::Y, 1e-5));
+
+        // Closest at the end of one of them
+        let (a, b) = closest_between_segments(
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::new(3.0, -1.0, 0.0),
+            Vec3::new(3.0, 1.0, 0.0),
+        );
+        assert!(a.abs_diff_eq(Vec3::X, 1e-5));
+        assert!(b.abs_diff_eq(Vec3::new(3.0, 0.0, 0.0), 1e-5));
+
+        // Parallel, any pair of points across the gap will do
+        let (a, b) = closest_between_segments(
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::new(0.5, 2.0, 0.0),
+            Vec3::new(1.5, 2.0, 0.0),
+        );
+        assert!((b - a).abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+
+        // Degenerate into points
+        let (a, b) = closest_between_segments(Vec3::ZERO, Vec3::ZERO, Vec3::Y, Vec3::Y);
+        assert_eq!((a, b), (Vec3::ZERO, Vec3::Y));
+    }
+
+    #[test]
+    fn capsule_contacts() {
+        let upright = capsule(Vec3::ZERO, Quat::IDENTITY);
+        let lying = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+
+        // A sphere on the side and on top of an end cap
+        assert_contact(
+            upright.collide(&sphere(Vec3::new(1.0, 0.5, 0.0), 0.75)),
+            Vec3::X,
+            0.25,
+        );
+        assert_contact(
+            sphere(Vec3::new(0.0, 2.0, 0.0), 0.75).collide(&upright),
+            -Vec3::Y,
+            0.25,
+        );
+        // Crossed capsules, one lying across the top of the other
+        assert_contact(
+            upright.collide(&capsule(Vec3::new(0.0, 1.8, 0.0), lying)),
+            Vec3::Y,
+            0.2,
+        );
+        // Lying on a box, its whole length touches
+        assert_contact(
+            capsule(Vec3::new(0.3, 1.4, 0.0), lying).collide(&cube(Vec3::ZERO, Quat::IDENTITY)),
+            -Vec3::Y,
+            0.1,
+        );
+        assert!(
+            capsule(Vec3::new(0.0, 2.6, 0.0), Quat::IDENTITY)
+                .collide(&cube(Vec3::ZERO, Quat::IDENTITY))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn impacts_bounce_by_restitution() {
+        let (mut world, contact) = world_with(
+            ground(),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 })
+                .with_velocity(Vec3::new(0.0, -4.0, 0.0))
+                .with_restitution(0.5),
+        );
+        world.resolve_velocity(&contact);
+        let velocity = world.get_body_mut(contact.b).unwrap().velocity;
+        assert!(velocity.abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+
+        // Already separating, so left alone
+        world.resolve_velocity(&contact);
+        let velocity = world.get_body_mut(contact.b).unwrap().velocity;
+        assert!(velocity.abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn slow_impacts_do_not_bounce() {
+        let (mut world, contact) = world_with(
+            ground(),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 })
+                .with_velocity(Vec3::new(0.0, -0.2, 0.0))
+                .with_restitution(1.0),
+        );
+        world.resolve_velocity(&contact);
+        let velocity = world.get_body_mut(contact.b).unwrap().velocity;
+        assert!(velocity.abs_diff_eq(Vec3::ZERO, 1e-5));
+    }
+
+    #[test]
+    fn friction_slows_sliding() {
+        // Sliding too fast for friction to stop it
+        let (mut world, contact) = world_with(
+            ground().with_restitution(0.0).with_friction(1.0),
+            RigidBody::new_dynamic(2.0, Collider::Sphere { radius: 1.0 })
+                .with_velocity(Vec3::new(3.0, -1.0, 0.0))
+                .with_restitution(0.0)
+                .with_friction(0.25),
+        );
+        world.resolve_velocity(&contact);
+        // The normal impulse of 2 allows a friction impulse of 0.5 * 2
+        let velocity = world.get_body_mut(contact.b).unwrap().velocity;
+        assert!(velocity.abs_diff_eq(Vec3::new(2.5, 0.0, 0.0), 1e-5));
+
+        // Slow enough that friction stops it, without pushing it back
+        let (mut world, contact) = world_with(
+            ground(),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 })
+                .with_velocity(Vec3::new(0.1, -0.3, 0.0))
+                .with_friction(1.0),
+        );
+        world.resolve_velocity(&contact);
+        let velocity = world.get_body_mut(contact.b).unwrap().velocity;
+        assert!(velocity.abs_diff_eq(Vec3::ZERO, 1e-5));
+    }
+
+    #[test]
+    fn overlap_is_corrected_by_inverse_mass() {
+        let (mut world, mut contact) = world_with(
+            RigidBody::new_dynamic(3.0, Collider::Sphere { radius: 1.0 }),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 }),
+        );
+        contact.depth = 0.4 + PhysicsWorld::PENETRATION_SLOP;
+        world.correct_position(&contact);
+
+        let correction = 0.4 * PhysicsWorld::CORRECTION;
+        let a = world.bodies[contact.a].position;
+        let b = world.bodies[contact.b].position;
+        assert!(a.abs_diff_eq(Vec3::new(0.0, -correction / 4.0, 0.0), 1e-5));
+        assert!(b.abs_diff_eq(Vec3::new(0.0, correction * 3.0 / 4.0, 0.0), 1e-5));
+
+        // Static bodies stay where they are
+        let (mut world, contact) = world_with(
+            ground(),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 1.0 }),
+        );
+        world.correct_position(&contact);
+        assert_eq!(world.bodies[contact.a].position, Vec3::ZERO);
+        assert!(world.bodies[contact.b].position.y > 0.0);
+    }
+
+    #[test]
+    fn resting_box_settles_on_the_ground() {
+        let mut world = PhysicsWorld::default();
+        world.add_body(Object::empty(), ground());
+        let object = Object::empty().with_translation(Vec3::new(0.0, 1.5, 0.0));
+        let id = world.add_body(
+            object.clone(),
+            RigidBody::new_dynamic(
+                1.0,
+                Collider::Box {
+                    half_extents: Vec3::splat(0.5),
+                },
+            ),
+        );
+
+        for _ in 0..180 {
+            world.step(STEP);
+        }
+        world.sync(1.0);
+
+        // On top of the ground, whose top is at 0.5
+        let position = object.get_local_xform().w_axis.truncate();
+        assert!((position.y - 1.0).abs() < 0.02, "{position}");
+        assert!(position.x.abs() < 1e-5 && position.z.abs() < 1e-5);
+        assert!(world.get_body_mut(id).unwrap().velocity.length() < 0.2);
+    }
 
-            let translation_input = Mat4::from_translation(-input.direction() * 0.2);
+    #[test]
+    fn dropped_sphere_bounces() {
+        let mut world = PhysicsWorld::default();
+        world.add_body(Object::empty(), ground());
+        let id = world.add_body(
+            Object::empty().with_translation(Vec3::new(0.0, 3.0, 0.0)),
+            RigidBody::new_dynamic(1.0, Collider::Sphere { radius: 0.5 }).with_restitution(0.8),
+        );
 
-            camera.set_xform(rotated * translation_input);
+        let mut bounced = false;
+        let mut previous = 0.0;
+        for _ in 0..120 {
+            world.step(STEP);
+            let velocity = world.get_body_mut(id).unwrap().velocity.y;
+            bounced |= previous < -1.0 && velocity > 1.0;
+            previous = velocity;
         }
+        assert!(bounced);
+        // And never fell through
+        assert!(world.bodies[id].position.y > 0.5);
     }
 }
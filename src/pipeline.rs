@@ -0,0 +1,470 @@
+use anyhow::{Result, anyhow};
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use wgpu::naga;
+
+use crate::{data::Vertex, layouts::BindGroupLayouts, shader::ShaderKey, uniform::UniformLayout};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    Mesh,
+}
+
+impl VertexLayout {
+    const MESH_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x3,
+        3 => Float32x3,
+        4 => Float32x2
+    ];
+
+    fn buffers(&self) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        match self {
+            Self::Mesh => vec![wgpu::VertexBufferLayout {
+                array_stride: size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &Self::MESH_ATTRIBUTES,
+            }],
+        }
+    }
+}
+
+/// Describes the attachments of the render pass a pipeline will be used in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenderTargets {
+    // Depth only passes have no color attachment
+    pub color_format: Option<wgpu::TextureFormat>,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub sample_count: u32,
+}
+
+/// The part of a pipeline description owned by a material.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub shader: ShaderKey,
+    pub vertex_layout: VertexLayout,
+    pub material_layout: wgpu::BindGroupLayout,
+    pub blend: Option<wgpu::BlendState>,
+    pub cull_mode: Option<wgpu::Face>,
+    pub depth_write: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    pub depth_bias: wgpu::DepthBiasState,
+    // Uniform buffers of the material group by binding, checked against the shader
+    pub material_uniforms: Vec<(u32, UniformLayout)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub desc: PipelineDesc,
+    pub targets: RenderTargets,
+}
+
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: HashMap<ShaderKey, wgpu::ShaderModule>,
+    // Parsed shaders, used to check uniform layouts
+    modules: HashMap<ShaderKey, naga::Module>,
+    // Pipeline layouts only differ by their material bind group layout
+    layouts: HashMap<wgpu::BindGroupLayout, wgpu::PipelineLayout>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    // Failures are remembered, so that errors are only reported once
+    failed_shaders: HashSet<ShaderKey>,
+    failed_pipelines: HashSet<PipelineKey>,
+    // Bumped on every reload, for passes that keep their own pipelines
+    shader_generation: u64,
+
+    // Development mode: shaders are loaded from disk and polled for changes
+    hot_reload: bool,
+    // Every file a shader was assembled from, with its modification time
+    watched: HashMap<ShaderKey, Vec<(PathBuf, Option<SystemTime>)>>,
+    last_poll: Option<Instant>,
+}
+
+impl PipelineCache {
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+    // Models are authored right-handed but projected with a left-handed
+    // projection, which flips their winding order on screen
+    const FRONT_FACE: wgpu::FrontFace = wgpu::FrontFace::Cw;
+
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.hot_reload = enabled;
+    }
+
+    /// Recompiles shaders whose files changed on disk along with every
+    /// pipeline using them. If the new source or any of the pipelines fail to
+    /// build, the previous ones are kept and the error is reported.
+    pub fn poll_shaders(&mut self, device: &wgpu::Device) {
+        if !self.hot_reload
+            || self
+                .last_poll
+                .is_some_and(|last_poll| last_poll.elapsed() < Self::POLL_INTERVAL)
+        {
+            return;
+        }
+        self.last_poll = Some(Instant::now());
+
+        let changed: Vec<_> = self
+            .watched
+            .iter()
+            .filter(|(_, files)| {
+                files
+                    .iter()
+                    .any(|(path, modified)| Self::modified(path) != *modified)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in changed {
+            let reloaded = self
+                .compile_shader(device, &key)
+                .and_then(|(shader_module, module)| {
+                    let pipelines =
+                        self.rebuild_pipelines(device, &key, &shader_module, &module)?;
+                    Ok((shader_module, module, pipelines))
+                });
+            match reloaded {
+                Ok((shader_module, module, pipelines)) => {
+                    log::info!("Reloaded shader {}", key.path().display());
+                    self.shaders.insert(key.clone(), shader_module);
+                    self.modules.insert(key.clone(), module);
+                    self.failed_shaders.remove(&key);
+                    self.pipelines.extend(pipelines);
+                    self.failed_pipelines
+                        .retain(|pipeline| pipeline.desc.shader != key);
+                    self.shader_generation += 1;
+                }
+                Err(err) => log::error!("Keeping previous version of shader:\n{err}"),
+            }
+        }
+    }
+
+    // Builds every cached pipeline using a reloaded shader, so that they are
+    // either all replaced at once or all kept when any of them fails
+    fn rebuild_pipelines(
+        &self,
+        device: &wgpu::Device,
+        key: &ShaderKey,
+        shader_module: &wgpu::ShaderModule,
+        module: &naga::Module,
+    ) -> Result<Vec<(PipelineKey, wgpu::RenderPipeline)>> {
+        self.pipelines
+            .keys()
+            .filter(|pipeline_key| pipeline_key.desc.shader == *key)
+            .map(|pipeline_key| {
+                Self::check_uniforms(module, &pipeline_key.desc)?;
+                let pipeline_layout = &self.layouts[&pipeline_key.desc.material_layout];
+                let pipeline = Self::with_validation(device, || {
+                    Self::make_pipeline(
+                        device,
+                        pipeline_layout,
+                        shader_module,
+                        Self::has_fragment(module),
+                        pipeline_key,
+                    )
+                })?;
+                Ok((pipeline_key.clone(), pipeline))
+            })
+            .collect()
+    }
+
+    /// Drops every cached pipeline, so that they get rebuilt on next use.
+    /// Needed whenever the render targets change.
+    pub fn invalidate(&mut self) {
+        self.pipelines.clear();
+    }
+
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &BindGroupLayouts,
+        key: &PipelineKey,
+    ) -> Result<wgpu::RenderPipeline> {
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        } else if self.failed_pipelines.contains(key) {
+            return Err(anyhow!(
+                "Pipeline for {:?} failed to build",
+                key.desc.shader
+            ));
+        }
+
+        let shader_module = self.get_shader(device, &key.desc.shader)?;
+        let module = &self.modules[&key.desc.shader];
+        let has_fragment = Self::has_fragment(module);
+        Self::check_uniforms(module, &key.desc).inspect_err(|err| {
+            log::error!("{err}");
+            self.failed_pipelines.insert(key.clone());
+        })?;
+        let pipeline_layout = self
+            .layouts
+            .entry(key.desc.material_layout.clone())
+            .or_insert_with(|| layouts.make_pipeline_layout(device, &key.desc.material_layout))
+            .clone();
+        let pipeline = Self::with_validation(device, || {
+            Self::make_pipeline(device, &pipeline_layout, &shader_module, has_fragment, key)
+        })
+        .inspect_err(|err| {
+            log::error!("Failed to build pipeline for {:?}: {err}", key.desc.shader);
+            self.failed_pipelines.insert(key.clone());
+        })?;
+        self.pipelines.insert(key.clone(), pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    /// Compiles the shader, or returns the cached module. Also used by
+    /// passes that build their own pipelines.
+    pub fn get_shader(
+        &mut self,
+        device: &wgpu::Device,
+        key: &ShaderKey,
+    ) -> Result<wgpu::ShaderModule> {
+        if let Some(module) = self.shaders.get(key) {
+            return Ok(module.clone());
+        } else if self.failed_shaders.contains(key) {
+            return Err(anyhow!("Shader {} failed to compile", key.path().display()));
+        }
+
+        let (shader_module, module) = self.compile_shader(device, key).inspect_err(|err| {
+            log::error!("{err}");
+            self.failed_shaders.insert(key.clone());
+        })?;
+        self.shaders.insert(key.clone(), shader_module.clone());
+        self.modules.insert(key.clone(), module);
+
+        Ok(shader_module)
+    }
+
+    /// Changes whenever a shader is reloaded. Passes building their own
+    /// pipelines from `get_shader` rebuild them when it does.
+    pub fn shader_generation(&self) -> u64 {
+        self.shader_generation
+    }
+
+    /// The validated naga module of a shader compiled through the cache.
+    pub fn module(&self, key: &ShaderKey) -> Option<&naga::Module> {
+        self.modules.get(key)
+    }
+
+    fn check_uniforms(module: &naga::Module, desc: &PipelineDesc) -> Result<()> {
+        for (group, binding, layout) in BindGroupLayouts::uniforms() {
+            layout
+                .check(module, group, binding)
+                .map_err(|err| anyhow!("{}: {err}", desc.shader.path().display()))?;
+        }
+        for (binding, layout) in &desc.material_uniforms {
+            layout
+                .check(module, BindGroupLayouts::MATERIAL_GROUP, *binding)
+                .map_err(|err| anyhow!("{}: {err}", desc.shader.path().display()))?;
+        }
+
+        Ok(())
+    }
+
+    // Depth only shaders may leave out the fragment stage
+    fn has_fragment(module: &naga::Module) -> bool {
+        module
+            .entry_points
+            .iter()
+            .any(|entry_point| entry_point.name == "fs_main")
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).ok()?.modified().ok()
+    }
+
+    fn compile_shader(
+        &mut self,
+        device: &wgpu::Device,
+        key: &ShaderKey,
+    ) -> Result<(wgpu::ShaderModule, naga::Module)> {
+        let preprocessed = key.load(self.hot_reload);
+
+        if self.hot_reload {
+            let files = match &preprocessed {
+                Ok(preprocessed) => preprocessed.files.clone(),
+                Err(_) => vec![key.path()],
+            };
+            let files = files
+                .into_iter()
+                .map(|path| {
+                    let modified = Self::modified(&path);
+                    (path, modified)
+                })
+                .collect();
+            self.watched.insert(key.clone(), files);
+        }
+
+        let preprocessed = preprocessed?;
+        let module = preprocessed.parse()?;
+
+        let shader_module = Self::with_validation(device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&key.path().display().to_string()),
+                source: wgpu::ShaderSource::Wgsl(preprocessed.source.into()),
+            })
+        })?;
+
+        Ok((shader_module, module))
+    }
+
+    // Turns wgpu validation errors into a Result instead of the default panic
+    pub fn with_validation<T>(device: &wgpu::Device, make: impl FnOnce() -> T) -> Result<T> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let result = make();
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => Err(anyhow!("{error}")),
+            None => Ok(result),
+        }
+    }
+
+    fn make_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader_module: &wgpu::ShaderModule,
+        has_fragment: bool,
+        key: &PipelineKey,
+    ) -> wgpu::RenderPipeline {
+        let PipelineKey { desc, targets } = key;
+        let buffers = desc.vertex_layout.buffers();
+        let color_targets: Vec<_> = targets
+            .color_format
+            .map(|format| {
+                Some(wgpu::ColorTargetState {
+                    format,
+                    blend: desc.blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            })
+            .into_iter()
+            .collect();
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{:?} pipeline", desc.shader)),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader_module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &buffers,
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: Self::FRONT_FACE,
+                cull_mode: desc.cull_mode,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            fragment: has_fragment.then(|| wgpu::FragmentState {
+                module: shader_module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &color_targets,
+            }),
+            depth_stencil: targets.depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: desc.depth_write,
+                depth_compare: desc.depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: desc.depth_bias,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: targets.sample_count,
+                mask: !0u64,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::*;
+    use crate::{camera::Projection, object::Object};
+
+    fn winding(view_projection: Mat4, triangle: [Vec3; 3]) -> wgpu::FrontFace {
+        let [a, b, c] = triangle.map(|point| view_projection.project_point3(point).truncate());
+        if (b - a).perp_dot(c - a) > 0.0 {
+            wgpu::FrontFace::Ccw
+        } else {
+            wgpu::FrontFace::Cw
+        }
+    }
+
+    fn scene_pipeline(layouts: &BindGroupLayouts) -> PipelineKey {
+        PipelineKey {
+            desc: PipelineDesc {
+                shader: ShaderKey::builtin("simple"),
+                vertex_layout: VertexLayout::Mesh,
+                material_layout: layouts.material.clone(),
+                blend: None,
+                cull_mode: Some(wgpu::Face::Back),
+                depth_write: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                depth_bias: wgpu::DepthBiasState::default(),
+                material_uniforms: Vec::new(),
+            },
+            targets: RenderTargets {
+                color_format: Some(wgpu::TextureFormat::Rgba16Float),
+                depth_format: Some(wgpu::TextureFormat::Depth24Plus),
+                sample_count: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn new_targets_rebuild_pipelines() {
+        // Validates the pipelines, but never draws anything
+        let (device, _) = wgpu::Device::noop(&wgpu::DeviceDescriptor::default());
+        let layouts = BindGroupLayouts::new(&device);
+        let mut cache = PipelineCache::default();
+
+        let single_sampled = scene_pipeline(&layouts);
+        cache.get(&device, &layouts, &single_sampled).unwrap();
+        assert!(cache.pipelines.contains_key(&single_sampled));
+
+        let multisampled = PipelineKey {
+            targets: RenderTargets {
+                sample_count: 4,
+                ..single_sampled.targets
+            },
+            ..single_sampled.clone()
+        };
+        assert_ne!(multisampled, single_sampled);
+        assert!(!cache.pipelines.contains_key(&multisampled));
+
+        cache.invalidate();
+        cache.get(&device, &layouts, &multisampled).unwrap();
+        assert!(cache.pipelines.contains_key(&multisampled));
+        assert!(!cache.pipelines.contains_key(&single_sampled));
+        // Shaders don't depend on the targets, and are kept
+        assert!(cache.module(&single_sampled.desc.shader).is_some());
+    }
+
+    #[test]
+    fn gltf_front_faces_face_the_camera() {
+        // Counter-clockwise around its +Z normal, as glTF and OBJ define front faces
+        let triangle = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        let projection = Projection::Perspective { fov: 1.0 }.matrix(1.0, 0.1, 100.0);
+
+        // Single sided materials cull the triangle when seen from behind
+        for (position, front) in [(Vec3::Z * 5.0, true), (Vec3::NEG_Z * 5.0, false)] {
+            let xform = Object::empty()
+                .with_translation(position)
+                .with_look_at(Vec3::ZERO, Vec3::Y)
+                .get_local_xform();
+            let winding = winding(projection * xform.inverse(), triangle);
+            assert_eq!(winding == PipelineCache::FRONT_FACE, front);
+        }
+    }
+}
@@ -0,0 +1,958 @@
+use anyhow::{Result, anyhow};
+use glam::Vec2;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::{
+    gpu::Gpu,
+    pipeline::PipelineCache,
+    shader::ShaderKey,
+    tonemap::Tonemapper,
+    uniform::{Uniform, UniformLayout, uniform_struct},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    /// How much of the blurred scene is mixed into the image.
+    pub intensity: f32,
+    /// Blur radius of each upsample step, in UV units.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            intensity: 0.04,
+            radius: 0.005,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ColorGrading {
+    pub lut: Rc<Lut3d>,
+    /// Blend between the original and the graded color.
+    pub strength: f32,
+}
+
+impl ColorGrading {
+    pub fn new(lut: Rc<Lut3d>) -> Self {
+        Self { lut, strength: 1.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vignette {
+    /// Darkening in the corners, from 0 to 1.
+    pub intensity: f32,
+    /// How far the falloff reaches towards the center, from 0 to 1.
+    pub smoothness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.35,
+            smoothness: 0.6,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fxaa {
+    /// Longest blur along an edge, in pixels.
+    pub span_max: f32,
+    /// Lower values blur low contrast edges more.
+    pub reduce_mul: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilmGrain {
+    pub intensity: f32,
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        Self { intensity: 0.05 }
+    }
+}
+
+/// Post-processing effects of a camera, effects set to `None` are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct PostSettings {
+    pub bloom: Option<Bloom>,
+    pub color_grading: Option<ColorGrading>,
+    pub vignette: Option<Vignette>,
+    pub fxaa: Option<Fxaa>,
+    pub film_grain: Option<FilmGrain>,
+}
+
+impl PostSettings {
+    pub fn with_bloom(mut self, bloom: Bloom) -> Self {
+        self.bloom = Some(bloom);
+        self
+    }
+
+    pub fn with_color_grading(mut self, color_grading: ColorGrading) -> Self {
+        self.color_grading = Some(color_grading);
+        self
+    }
+
+    pub fn with_vignette(mut self, vignette: Vignette) -> Self {
+        self.vignette = Some(vignette);
+        self
+    }
+
+    pub fn with_fxaa(mut self, fxaa: Fxaa) -> Self {
+        self.fxaa = Some(fxaa);
+        self
+    }
+
+    pub fn with_film_grain(mut self, film_grain: FilmGrain) -> Self {
+        self.film_grain = Some(film_grain);
+        self
+    }
+
+    // Passes after tonemapping, in the order they're chained
+    fn ldr_passes(&self) -> Vec<PostPass> {
+        [
+            (self.color_grading.is_some(), PostPass::ColorGrading),
+            (self.vignette.is_some(), PostPass::Vignette),
+            (self.fxaa.is_some(), PostPass::Fxaa),
+            (self.film_grain.is_some(), PostPass::FilmGrain),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, pass)| enabled.then_some(pass))
+        .collect()
+    }
+}
+
+/// A 3D color lookup table for color grading.
+#[derive(Debug)]
+pub struct Lut3d {
+    view: wgpu::TextureView,
+    size: u32,
+}
+
+impl Lut3d {
+    fn parse_cube(source: &str) -> Result<(u32, Vec<[f32; 3]>)> {
+        let mut size: Option<u32> = None;
+        let mut data = Vec::new();
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| anyhow!("{message} at line {}", line_idx + 1);
+
+            let mut parts = line.split_whitespace();
+            let keyword = parts.next().unwrap_or_default();
+            let arguments: Vec<_> = parts.collect();
+            let floats = |values: &[&str]| {
+                values
+                    .iter()
+                    .map(|value| value.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+            };
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let value = arguments.first().and_then(|value| value.parse().ok());
+                    size = Some(value.ok_or_else(|| error("Malformed LUT_3D_SIZE"))?);
+                }
+                "LUT_1D_SIZE" => return Err(error("1D LUTs are not supported")),
+                "DOMAIN_MIN" | "DOMAIN_MAX" | "LUT_3D_INPUT_RANGE" => {
+                    let expected = match keyword {
+                        "DOMAIN_MIN" => vec![0.0; 3],
+                        "DOMAIN_MAX" => vec![1.0; 3],
+                        _ => vec![0.0, 1.0],
+                    };
+                    if floats(&arguments) != Some(expected) {
+                        return Err(error("Only the default 0 to 1 domain is supported"));
+                    }
+                }
+                _ => {
+                    let entry = [&[keyword], arguments.as_slice()].concat();
+                    let entry = floats(&entry)
+                        .and_then(|entry| <[f32; 3]>::try_from(entry).ok())
+                        .ok_or_else(|| error("Malformed LUT entry"))?;
+                    data.push(entry);
+                }
+            }
+        }
+
+        let size = size.ok_or_else(|| anyhow!("Missing LUT_3D_SIZE"))?;
+        if size < 2 || data.len() != size.pow(3) as usize {
+            return Err(anyhow!(
+                "Expected {} entries for a LUT of size {size}, found {}",
+                size.pow(3),
+                data.len()
+            ));
+        }
+
+        Ok((size, data))
+    }
+
+    /// Loads a LUT in the `.cube` format, as exported by most grading tools.
+    pub fn load_cube(gpu: &Gpu, path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read LUT {}: {err}", path.display()))?;
+        let (size, data) = Self::parse_cube(&source)
+            .map_err(|err| anyhow!("Failed to parse LUT {}: {err}", path.display()))?;
+
+        // Entries are listed with red changing fastest, which is the texel order
+        let texels: Vec<u8> = data
+            .iter()
+            .flat_map(|[r, g, b]| [*r, *g, *b, 1.0])
+            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect();
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+
+        let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: "Color grading LUT".into(),
+            dimension: wgpu::TextureDimension::D3,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        gpu.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &texels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size * 4),
+                rows_per_image: Some(size),
+            },
+            extent,
+        );
+
+        Ok(Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            size,
+        })
+    }
+}
+
+uniform_struct! {
+    struct BloomUniform {
+        filter_radius: f32,
+    }
+}
+
+uniform_struct! {
+    struct PostUniform {
+        texel_size: Vec2,
+        frame: u32,
+        lut_size: f32,
+        lut_strength: f32,
+        vignette_intensity: f32,
+        vignette_smoothness: f32,
+        grain_intensity: f32,
+        fxaa_span_max: f32,
+        fxaa_reduce_mul: f32,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum PostPass {
+    BloomDownsampleFirst,
+    BloomDownsample,
+    BloomUpsample,
+    BloomComposite,
+    ColorGrading,
+    Vignette,
+    Fxaa,
+    FilmGrain,
+}
+
+impl PostPass {
+    fn is_bloom(self) -> bool {
+        matches!(
+            self,
+            Self::BloomDownsampleFirst
+                | Self::BloomDownsample
+                | Self::BloomUpsample
+                | Self::BloomComposite
+        )
+    }
+
+    fn shader(self) -> ShaderKey {
+        ShaderKey::builtin(if self.is_bloom() { "bloom" } else { "post" })
+    }
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            Self::BloomDownsampleFirst => "fs_downsample_first",
+            Self::BloomDownsample => "fs_downsample",
+            Self::BloomUpsample | Self::BloomComposite => "fs_upsample",
+            Self::ColorGrading => "fs_color_grading",
+            Self::Vignette => "fs_vignette",
+            Self::Fxaa => "fs_fxaa",
+            Self::FilmGrain => "fs_film_grain",
+        }
+    }
+
+    fn blend(self) -> Option<wgpu::BlendState> {
+        let blend = |src_factor, dst_factor| {
+            let component = wgpu::BlendComponent {
+                src_factor,
+                dst_factor,
+                operation: wgpu::BlendOperation::Add,
+            };
+            Some(wgpu::BlendState {
+                color: component,
+                alpha: component,
+            })
+        };
+
+        match self {
+            Self::BloomUpsample => blend(wgpu::BlendFactor::One, wgpu::BlendFactor::One),
+            // Mixes the bloom in by the blend constant
+            Self::BloomComposite => blend(
+                wgpu::BlendFactor::Constant,
+                wgpu::BlendFactor::OneMinusConstant,
+            ),
+            _ => None,
+        }
+    }
+
+    fn uniform(self) -> UniformLayout {
+        if self.is_bloom() {
+            BloomUniform::layout()
+        } else {
+            PostUniform::layout()
+        }
+    }
+}
+
+/// Builds a pipeline drawing a fullscreen triangle with the shader's `vs_main`.
+/// `uniforms` are checked against the shader by group and binding.
+pub fn make_fullscreen_pipeline(
+    gpu: &mut Gpu,
+    shader: &ShaderKey,
+    entry_point: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    uniforms: &[(u32, u32, UniformLayout)],
+    target: wgpu::ColorTargetState,
+) -> Result<wgpu::RenderPipeline> {
+    let module = gpu.pipelines.get_shader(&gpu.device, shader)?;
+    if let Some(naga_module) = gpu.pipelines.module(shader) {
+        for (group, binding, layout) in uniforms {
+            layout.check(naga_module, *group, *binding)?;
+        }
+    }
+
+    let pipeline_layout = gpu
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(entry_point),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+    PipelineCache::with_validation(&gpu.device, || {
+        gpu.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("{shader:?} {entry_point} pipeline")),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &[Some(target)],
+                }),
+                multiview: None,
+                cache: None,
+            })
+    })
+}
+
+// Records a pass drawing a fullscreen triangle into `target`
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+    blend_constant: Option<f64>,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            depth_slice: None,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+
+    render_pass.set_pipeline(pipeline);
+    for (idx, bind_group) in bind_groups.iter().enumerate() {
+        render_pass.set_bind_group(idx as u32, *bind_group, &[]);
+    }
+    if let Some(constant) = blend_constant {
+        render_pass.set_blend_constant(wgpu::Color {
+            r: constant,
+            g: constant,
+            b: constant,
+            a: constant,
+        });
+    }
+    render_pass.draw(0..3, 0..1);
+}
+
+/// Runs the active camera's post-processing around the tonemapper: bloom on
+/// the HDR scene, then the remaining effects on the tonemapped image.
+pub struct PostStack {
+    layout: wgpu::BindGroupLayout,
+    lut_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bloom_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    // Failed builds are kept as None, so that they're only reported once
+    pipelines: HashMap<PostPass, Option<wgpu::RenderPipeline>>,
+    // Shader generation the pipelines above were built with
+    shader_generation: u64,
+    // Size and format the targets below were made for
+    size: (u32, u32, wgpu::TextureFormat),
+    // Ping-pong targets for the passes after tonemapping, each with a bind group reading it
+    targets: Vec<(wgpu::TextureView, wgpu::BindGroup)>,
+    // One view and bind group per bloom mip level
+    bloom_mips: Vec<(wgpu::TextureView, wgpu::BindGroup)>,
+    hdr_bind_group: Option<(wgpu::TextureView, wgpu::BindGroup)>,
+    lut_bind_group: Option<(wgpu::TextureView, wgpu::BindGroup)>,
+    // This frame's effects, minus the ones whose pipelines failed to build
+    bloom: Option<Bloom>,
+    passes: Vec<PostPass>,
+}
+
+impl PostStack {
+    const MAX_BLOOM_MIPS: u32 = 6;
+
+    fn make_layouts(device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroupLayout) {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Post-processing bind group layout".into(),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let lut_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Color grading LUT bind group layout".into(),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        (layout, lut_layout)
+    }
+
+    pub fn new(gpu: &Gpu) -> Self {
+        let (layout, lut_layout) = Self::make_layouts(&gpu.device);
+
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post-processing sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bloom_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<BloomUniform>() as u64,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post-processing uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<PostUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            layout,
+            lut_layout,
+            sampler,
+            bloom_buffer,
+            uniform_buffer,
+            pipelines: HashMap::new(),
+            shader_generation: 0,
+            size: (0, 0, gpu.config.format),
+            targets: Vec::new(),
+            bloom_mips: Vec::new(),
+            hdr_bind_group: None,
+            lut_bind_group: None,
+            bloom: None,
+            passes: Vec::new(),
+        }
+    }
+
+    fn make_bind_group(
+        &self,
+        device: &wgpu::Device,
+        view: &wgpu::TextureView,
+        uniform: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Post-processing bind group".into(),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn make_targets(&mut self, gpu: &Gpu) {
+        let (width, height) = (gpu.config.width, gpu.config.height);
+        let make_texture = |label, size: (u32, u32), mip_level_count, format| {
+            gpu.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                dimension: wgpu::TextureDimension::D2,
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+
+        self.targets = (0..2)
+            .map(|_| {
+                let texture = make_texture(
+                    "Post-processing target",
+                    (width, height),
+                    1,
+                    gpu.config.format,
+                );
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = self.make_bind_group(&gpu.device, &view, &self.uniform_buffer);
+                (view, bind_group)
+            })
+            .collect();
+
+        // The chain starts at half resolution and stops before going below a pixel
+        let bloom_size = ((width / 2).max(1), (height / 2).max(1));
+        let mip_count = (bloom_size.0.min(bloom_size.1).ilog2() + 1).min(Self::MAX_BLOOM_MIPS);
+        let texture = make_texture("Bloom texture", bloom_size, mip_count, Gpu::HDR_FORMAT);
+        self.bloom_mips = (0..mip_count)
+            .map(|mip| {
+                let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                });
+                let bind_group = self.make_bind_group(&gpu.device, &view, &self.bloom_buffer);
+                (view, bind_group)
+            })
+            .collect();
+
+        self.size = (width, height, gpu.config.format);
+    }
+
+    fn get_pipeline(&mut self, gpu: &mut Gpu, pass: PostPass) -> bool {
+        if !self.pipelines.contains_key(&pass) {
+            let format = if pass.is_bloom() {
+                Gpu::HDR_FORMAT
+            } else {
+                gpu.config.format
+            };
+            let mut bind_group_layouts = vec![&self.layout];
+            if pass == PostPass::ColorGrading {
+                bind_group_layouts.push(&self.lut_layout);
+            }
+
+            let pipeline = make_fullscreen_pipeline(
+                gpu,
+                &pass.shader(),
+                pass.entry_point(),
+                &bind_group_layouts,
+                &[(0, 2, pass.uniform())],
+                wgpu::ColorTargetState {
+                    format,
+                    blend: pass.blend(),
+                    write_mask: wgpu::ColorWrites::ALL,
+                },
+            )
+            .inspect_err(|err| log::error!("Failed to build {pass:?} pipeline: {err}"))
+            .ok();
+            self.pipelines.insert(pass, pipeline);
+        }
+
+        self.pipelines.get(&pass).is_some_and(Option::is_some)
+    }
+
+    /// Builds what this frame's effects need and uploads their parameters.
+    pub fn prepare(&mut self, gpu: &mut Gpu, settings: &PostSettings, frame: u32) {
+        if self.size != (gpu.config.width, gpu.config.height, gpu.config.format) {
+            if self.size.2 != gpu.config.format {
+                self.pipelines.clear();
+            }
+            self.make_targets(gpu);
+        }
+        if self.shader_generation != gpu.pipelines.shader_generation() {
+            self.shader_generation = gpu.pipelines.shader_generation();
+            self.pipelines.clear();
+        }
+
+        if let Some(color_grading) = &settings.color_grading {
+            let lut = &color_grading.lut.view;
+            if self
+                .lut_bind_group
+                .as_ref()
+                .is_none_or(|(view, _)| view != lut)
+            {
+                let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: "Color grading LUT bind group".into(),
+                    layout: &self.lut_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(lut),
+                    }],
+                });
+                self.lut_bind_group = Some((lut.clone(), bind_group));
+            }
+        }
+
+        let bloom_passes = [
+            PostPass::BloomDownsampleFirst,
+            PostPass::BloomDownsample,
+            PostPass::BloomUpsample,
+            PostPass::BloomComposite,
+        ];
+        let mut bloom_built = true;
+        if settings.bloom.is_some() {
+            for pass in bloom_passes {
+                bloom_built &= self.get_pipeline(gpu, pass);
+            }
+        }
+        self.bloom = settings.bloom.filter(|_| bloom_built);
+
+        let mut passes = settings.ldr_passes();
+        passes.retain(|pass| self.get_pipeline(gpu, *pass));
+        self.passes = passes;
+
+        if let Some(bloom) = self.bloom {
+            let uniform_data = BloomUniform {
+                filter_radius: bloom.radius,
+            };
+            gpu.queue
+                .write_buffer(&self.bloom_buffer, 0, bytemuck::bytes_of(&uniform_data));
+        }
+
+        let color_grading = settings.color_grading.as_ref();
+        let vignette = settings.vignette.unwrap_or_default();
+        let fxaa = settings.fxaa.unwrap_or_default();
+        let uniform_data = PostUniform {
+            texel_size: 1.0 / Vec2::new(gpu.config.width as f32, gpu.config.height as f32),
+            frame,
+            lut_size: color_grading.map_or(0.0, |grading| grading.lut.size as f32),
+            lut_strength: color_grading.map_or(0.0, |grading| grading.strength),
+            vignette_intensity: vignette.intensity,
+            vignette_smoothness: vignette.smoothness,
+            grain_intensity: settings.film_grain.unwrap_or_default().intensity,
+            fxaa_span_max: fxaa.span_max,
+            fxaa_reduce_mul: fxaa.reduce_mul,
+        };
+        gpu.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform_data));
+    }
+
+    fn pipeline(&self, pass: PostPass) -> &wgpu::RenderPipeline {
+        // Only passes with a pipeline are kept by `prepare`
+        self.pipelines[&pass].as_ref().unwrap()
+    }
+
+    fn render_bloom(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        hdr_bind_group: &wgpu::BindGroup,
+        bloom: Bloom,
+    ) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        for (idx, (target, _)) in self.bloom_mips.iter().enumerate() {
+            let (source, pass) = match idx {
+                0 => (hdr_bind_group, PostPass::BloomDownsampleFirst),
+                _ => (&self.bloom_mips[idx - 1].1, PostPass::BloomDownsample),
+            };
+            let pipeline = self.pipeline(pass);
+            fullscreen_pass(
+                encoder,
+                "Bloom Downsample Pass",
+                target,
+                clear,
+                pipeline,
+                &[source],
+                None,
+            );
+        }
+
+        // Each level adds the blurred smaller one on top of itself
+        for idx in (1..self.bloom_mips.len()).rev() {
+            let source = &self.bloom_mips[idx].1;
+            let target = &self.bloom_mips[idx - 1].0;
+            let pipeline = self.pipeline(PostPass::BloomUpsample);
+            fullscreen_pass(
+                encoder,
+                "Bloom Upsample Pass",
+                target,
+                wgpu::LoadOp::Load,
+                pipeline,
+                &[source],
+                None,
+            );
+        }
+
+        fullscreen_pass(
+            encoder,
+            "Bloom Composite Pass",
+            hdr,
+            wgpu::LoadOp::Load,
+            self.pipeline(PostPass::BloomComposite),
+            &[&self.bloom_mips[0].1],
+            Some(bloom.intensity as f64),
+        );
+    }
+
+    /// Records bloom into `hdr`, tonemapping and every other enabled effect,
+    /// the last one writing into `output`.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        tonemapper: &mut Tonemapper,
+    ) {
+        if self
+            .hdr_bind_group
+            .as_ref()
+            .is_none_or(|(view, _)| view != hdr)
+        {
+            let bind_group = self.make_bind_group(device, hdr, &self.bloom_buffer);
+            self.hdr_bind_group = Some((hdr.clone(), bind_group));
+        }
+
+        if let (Some(bloom), Some((_, hdr_bind_group))) = (self.bloom, &self.hdr_bind_group) {
+            self.render_bloom(encoder, hdr, hdr_bind_group, bloom);
+        }
+
+        let Some((first_target, _)) = self.targets.first().filter(|_| !self.passes.is_empty())
+        else {
+            tonemapper.render(device, encoder, hdr, output);
+            return;
+        };
+        tonemapper.render(device, encoder, hdr, first_target);
+
+        for (idx, pass) in self.passes.iter().enumerate() {
+            let source = &self.targets[idx % 2].1;
+            let target = if idx + 1 == self.passes.len() {
+                output
+            } else {
+                &self.targets[(idx + 1) % 2].0
+            };
+            let mut bind_groups = vec![source];
+            if let (PostPass::ColorGrading, Some((_, lut))) = (pass, &self.lut_bind_group) {
+                bind_groups.push(lut);
+            }
+
+            fullscreen_pass(
+                encoder,
+                &format!("{pass:?} Pass"),
+                target,
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                self.pipeline(*pass),
+                &bind_groups,
+                None,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniforms_match_shaders() {
+        let passes = [
+            PostPass::BloomDownsampleFirst,
+            PostPass::BloomDownsample,
+            PostPass::BloomUpsample,
+            PostPass::BloomComposite,
+            PostPass::ColorGrading,
+            PostPass::Vignette,
+            PostPass::Fxaa,
+            PostPass::FilmGrain,
+        ];
+        for pass in passes {
+            let module = pass.shader().load(false).unwrap().parse().unwrap();
+            assert!(
+                module
+                    .entry_points
+                    .iter()
+                    .any(|entry| entry.name == pass.entry_point())
+            );
+            pass.uniform().check(&module, 0, 2).unwrap();
+        }
+    }
+
+    // Identity LUT of size 2, listed red first
+    const IDENTITY: &str = "\
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn parse_cube_reads_entries() {
+        let source = format!("# Comment\nTITLE \"Identity\"\nLUT_3D_SIZE 2\n\n{IDENTITY}");
+        let (size, data) = Lut3d::parse_cube(&source).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(data.len(), 8);
+        assert_eq!(data[1], [1.0, 0.0, 0.0]);
+        assert_eq!(data[6], [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn parse_cube_checks_entry_count() {
+        let missing = IDENTITY.lines().skip(1).collect::<Vec<_>>().join("\n");
+        let error = Lut3d::parse_cube(&format!("LUT_3D_SIZE 2\n{missing}")).unwrap_err();
+        assert!(error.to_string().contains("Expected 8 entries"), "{error}");
+
+        let extra = format!("LUT_3D_SIZE 2\n{IDENTITY}0.5 0.5 0.5\n");
+        assert!(Lut3d::parse_cube(&extra).is_err());
+        assert!(Lut3d::parse_cube(IDENTITY).is_err());
+    }
+
+    #[test]
+    fn parse_cube_accepts_only_the_default_domain() {
+        let default =
+            format!("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 1.0 1.0 1.0\n{IDENTITY}");
+        assert!(Lut3d::parse_cube(&default).is_ok());
+
+        for domain in ["DOMAIN_MIN -1 0 0", "DOMAIN_MAX 2 2 2", "DOMAIN_MAX 1 1"] {
+            let source = format!("LUT_3D_SIZE 2\n{domain}\n{IDENTITY}");
+            let error = Lut3d::parse_cube(&source).unwrap_err();
+            assert!(error.to_string().contains("at line 2"), "{error}");
+        }
+    }
+
+    #[test]
+    fn parse_cube_rejects_1d_luts() {
+        let error = Lut3d::parse_cube("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").unwrap_err();
+        assert!(error.to_string().contains("1D LUTs"), "{error}");
+    }
+
+    #[test]
+    fn parse_cube_rejects_malformed_entries() {
+        let source = format!("LUT_3D_SIZE 2\n{}", IDENTITY.replace("1 0 1", "1 0"));
+        assert!(Lut3d::parse_cube(&source).is_err());
+        assert!(Lut3d::parse_cube("LUT_3D_SIZE two\n").is_err());
+    }
+
+    #[test]
+    fn demo_lut_parses() {
+        let source = std::fs::read_to_string("src/res/luts/warm.cube").unwrap();
+        let (size, data) = Lut3d::parse_cube(&source).unwrap();
+        assert_eq!(data.len(), size.pow(3) as usize);
+    }
+}
@@ -0,0 +1,509 @@
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::gpu::Gpu;
+
+/// Handle to a texture declared in a render graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+/// Describes a transient texture. Transient textures live for a single frame
+/// and are shared between resources with the same description whose
+/// lifetimes don't overlap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+}
+
+enum Resource {
+    Transient(TextureDesc),
+    // Created and kept alive outside the graph, such as the swapchain. Its
+    // view is kept with the other imported ones
+    Imported,
+}
+
+struct ResourceEntry {
+    name: &'static str,
+    resource: Resource,
+}
+
+type PassFn<'a> = Box<dyn FnOnce(&mut PassContext) + 'a>;
+
+struct Pass<'a> {
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    run: PassFn<'a>,
+}
+
+/// What a pass gets to record its commands.
+pub struct PassContext<'r> {
+    pub device: &'r wgpu::Device,
+    pub encoder: &'r mut wgpu::CommandEncoder,
+    views: &'r [Option<wgpu::TextureView>],
+}
+
+impl<'r> PassContext<'r> {
+    /// The texture behind a resource the pass declared.
+    pub fn view(&self, id: ResourceId) -> &'r wgpu::TextureView {
+        self.views[id.0]
+            .as_ref()
+            .expect("Render graph resource was not declared by this pass")
+    }
+}
+
+/// Declares the resources used by a pass, see `RenderGraph::add_pass`.
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn read(mut self, id: ResourceId) -> Self {
+        self.reads.push(id);
+        self
+    }
+
+    /// Passes that load the previous contents of a texture should also read it.
+    pub fn write(mut self, id: ResourceId) -> Self {
+        self.writes.push(id);
+        self
+    }
+
+    pub fn execute(self, run: impl FnOnce(&mut PassContext) + 'a) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            run: Box::new(run),
+        });
+    }
+}
+
+/// Transient textures kept from one frame to the next, so that a graph with
+/// the same shape doesn't allocate anything.
+#[derive(Default)]
+pub struct TexturePool {
+    textures: HashMap<TextureDesc, Vec<wgpu::TextureView>>,
+}
+
+impl TexturePool {
+    fn get(&mut self, gpu: &Gpu, desc: TextureDesc, slot: usize) -> wgpu::TextureView {
+        let textures = self.textures.entry(desc).or_default();
+        while textures.len() <= slot {
+            let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+                label: "Render graph texture".into(),
+                dimension: wgpu::TextureDimension::D2,
+                size: wgpu::Extent3d {
+                    width: desc.width,
+                    height: desc.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                format: desc.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            textures.push(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        }
+
+        textures[slot].clone()
+    }
+}
+
+/// A frame described as passes and the textures they read and write.
+///
+/// Passes writing the same texture run in the order they were added, and a
+/// pass reading a texture it doesn't write runs between the passes writing it
+/// that were added before it and those added after it. Passes contributing
+/// nothing to an imported texture are skipped.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    resources: Vec<ResourceEntry>,
+    imported: HashMap<ResourceId, wgpu::TextureView>,
+    passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn create_texture(&mut self, name: &'static str, desc: TextureDesc) -> ResourceId {
+        self.resources.push(ResourceEntry {
+            name,
+            resource: Resource::Transient(desc),
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn import_texture(&mut self, name: &'static str, view: &wgpu::TextureView) -> ResourceId {
+        self.resources.push(ResourceEntry {
+            name,
+            resource: Resource::Imported,
+        });
+        let id = ResourceId(self.resources.len() - 1);
+        self.imported.insert(id, view.clone());
+        id
+    }
+
+    pub fn add_pass(&mut self, name: &'static str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    fn writers(&self) -> Vec<Vec<usize>> {
+        let mut writers = vec![Vec::new(); self.resources.len()];
+        for (idx, pass) in self.passes.iter().enumerate() {
+            for id in &pass.writes {
+                writers[id.0].push(idx);
+            }
+        }
+        writers
+    }
+
+    // Passes which end up writing to an imported texture, directly or not
+    fn live_passes(&self, writers: &[Vec<usize>]) -> Vec<bool> {
+        let mut live = vec![false; self.passes.len()];
+        let mut pending: Vec<usize> = (0..self.passes.len())
+            .filter(|idx| {
+                self.passes[*idx]
+                    .writes
+                    .iter()
+                    .any(|id| matches!(self.resources[id.0].resource, Resource::Imported))
+            })
+            .collect();
+
+        while let Some(idx) = pending.pop() {
+            if live[idx] {
+                continue;
+            }
+            live[idx] = true;
+
+            // Only what was written before the pass can reach it
+            let pass = &self.passes[idx];
+            for id in pass.reads.iter().chain(&pass.writes) {
+                pending.extend(
+                    writers[id.0]
+                        .iter()
+                        .filter(|writer| **writer < idx && !live[**writer]),
+                );
+            }
+        }
+
+        live
+    }
+
+    // Execution order of the live passes, earlier passes first when unconstrained
+    fn order(&self, writers: &[Vec<usize>], live: &[bool]) -> Result<Vec<usize>> {
+        let mut dependencies = vec![HashSet::new(); self.passes.len()];
+        for (id, resource_writers) in writers.iter().enumerate() {
+            let resource_writers: Vec<_> = resource_writers
+                .iter()
+                .copied()
+                .filter(|writer| live[*writer])
+                .collect();
+
+            for pair in resource_writers.windows(2) {
+                dependencies[pair[1]].insert(pair[0]);
+            }
+            // Readers see what the writers added before them wrote, and not
+            // what the ones added after them overwrite it with
+            for (idx, pass) in self.passes.iter().enumerate() {
+                if live[idx]
+                    && pass.reads.contains(&ResourceId(id))
+                    && !resource_writers.contains(&idx)
+                {
+                    let next = resource_writers.partition_point(|writer| *writer < idx);
+                    if let Some(previous) = next.checked_sub(1) {
+                        dependencies[idx].insert(resource_writers[previous]);
+                    }
+                    if let Some(next) = resource_writers.get(next) {
+                        dependencies[*next].insert(idx);
+                    }
+                }
+            }
+        }
+
+        let mut order = Vec::new();
+        let mut scheduled = vec![false; self.passes.len()];
+        let mut ready: BTreeSet<usize> = BTreeSet::new();
+        loop {
+            ready.extend((0..self.passes.len()).filter(|idx| {
+                live[*idx]
+                    && !scheduled[*idx]
+                    && dependencies[*idx]
+                        .iter()
+                        .all(|dependency| scheduled[*dependency])
+            }));
+            let Some(idx) = ready.pop_first() else {
+                break;
+            };
+            scheduled[idx] = true;
+            order.push(idx);
+        }
+
+        if order.len() != live.iter().filter(|live| **live).count() {
+            let stuck: Vec<_> = (0..self.passes.len())
+                .filter(|idx| live[*idx] && !scheduled[*idx])
+                .map(|idx| self.passes[idx].name)
+                .collect();
+            return Err(anyhow!(
+                "Render graph passes depend on each other: {stuck:?}"
+            ));
+        }
+
+        Ok(order)
+    }
+
+    // Picks a pool slot for every transient texture, reusing the slots of
+    // textures with the same description that are no longer used
+    fn allocate(&self, order: &[usize]) -> Result<HashMap<usize, usize>> {
+        let mut lifetimes: Vec<(usize, usize, usize)> = Vec::new();
+        for (id, entry) in self.resources.iter().enumerate() {
+            let Resource::Transient(_) = entry.resource else {
+                continue;
+            };
+            let uses: Vec<_> = order
+                .iter()
+                .enumerate()
+                .filter(|(_, idx)| {
+                    let pass = &self.passes[**idx];
+                    pass.reads.contains(&ResourceId(id)) || pass.writes.contains(&ResourceId(id))
+                })
+                .map(|(position, _)| position)
+                .collect();
+            let (Some(first), Some(last)) = (uses.first(), uses.last()) else {
+                continue;
+            };
+            if !self.passes[order[*first]].writes.contains(&ResourceId(id)) {
+                return Err(anyhow!(
+                    "Render graph texture {} is read before being written",
+                    entry.name
+                ));
+            }
+            lifetimes.push((*first, *last, id));
+        }
+        lifetimes.sort();
+
+        // Last use of each slot, per description
+        let mut slots: HashMap<TextureDesc, Vec<usize>> = HashMap::new();
+        let mut allocation = HashMap::new();
+        for (first, last, id) in lifetimes {
+            let Resource::Transient(desc) = self.resources[id].resource else {
+                continue;
+            };
+            let desc_slots = slots.entry(desc).or_default();
+            let slot = match desc_slots.iter().position(|slot_last| *slot_last < first) {
+                Some(slot) => slot,
+                None => {
+                    desc_slots.push(0);
+                    desc_slots.len() - 1
+                }
+            };
+            desc_slots[slot] = last;
+            allocation.insert(id, slot);
+        }
+
+        Ok(allocation)
+    }
+
+    /// Records every live pass into one command encoder and submits it.
+    pub fn execute(self, gpu: &Gpu, pool: &mut TexturePool) -> Result<()> {
+        let writers = self.writers();
+        let live = self.live_passes(&writers);
+        let order = self.order(&writers, &live)?;
+        let allocation = self.allocate(&order)?;
+
+        let views: Vec<_> = self
+            .resources
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| match &entry.resource {
+                Resource::Transient(desc) => {
+                    allocation.get(&id).map(|slot| pool.get(gpu, *desc, *slot))
+                }
+                Resource::Imported => self.imported.get(&ResourceId(id)).cloned(),
+            })
+            .collect();
+
+        // Textures of descriptions that went unused are released
+        let used: HashSet<_> = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| allocation.contains_key(id))
+            .filter_map(|(_, entry)| match entry.resource {
+                Resource::Transient(desc) => Some(desc),
+                Resource::Imported => None,
+            })
+            .collect();
+        pool.textures.retain(|desc, _| used.contains(desc));
+
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Graph Encoder"),
+            });
+
+        let mut passes: Vec<_> = self.passes.into_iter().map(Some).collect();
+        for idx in order {
+            let Some(pass) = passes[idx].take() else {
+                continue;
+            };
+            // Passes only see the textures they declared
+            let pass_views: Vec<_> = views
+                .iter()
+                .enumerate()
+                .map(|(id, view)| {
+                    let declared = pass.reads.contains(&ResourceId(id))
+                        || pass.writes.contains(&ResourceId(id));
+                    view.clone().filter(|_| declared)
+                })
+                .collect();
+
+            let mut context = PassContext {
+                device: &gpu.device,
+                encoder: &mut encoder,
+                views: &pass_views,
+            };
+            context.encoder.push_debug_group(pass.name);
+            (pass.run)(&mut context);
+            context.encoder.pop_debug_group();
+        }
+
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESC: TextureDesc = TextureDesc {
+        width: 64,
+        height: 64,
+        format: wgpu::TextureFormat::Rgba16Float,
+        sample_count: 1,
+    };
+
+    // Imported textures without their views, which only matter when executing
+    fn import(graph: &mut RenderGraph, name: &'static str) -> ResourceId {
+        graph.resources.push(ResourceEntry {
+            name,
+            resource: Resource::Imported,
+        });
+        ResourceId(graph.resources.len() - 1)
+    }
+
+    fn pass(graph: &mut RenderGraph, reads: &[ResourceId], writes: &[ResourceId]) {
+        let mut builder = graph.add_pass("pass");
+        for id in reads {
+            builder = builder.read(*id);
+        }
+        for id in writes {
+            builder = builder.write(*id);
+        }
+        builder.execute(|_| {});
+    }
+
+    fn analyze(graph: &RenderGraph) -> (Vec<bool>, Vec<usize>) {
+        let writers = graph.writers();
+        let live = graph.live_passes(&writers);
+        let order = graph.order(&writers, &live).unwrap();
+        (live, order)
+    }
+
+    #[test]
+    fn readers_run_before_later_writers() {
+        let mut graph = RenderGraph::default();
+        let texture = graph.create_texture("texture", DESC);
+        let output = import(&mut graph, "output");
+        pass(&mut graph, &[], &[texture]);
+        pass(&mut graph, &[texture], &[output]);
+        pass(&mut graph, &[], &[texture]);
+        pass(&mut graph, &[texture, output], &[output]);
+
+        let (live, order) = analyze(&graph);
+        assert!(live.iter().all(|live| *live));
+        assert_eq!(order, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let mut graph = RenderGraph::default();
+        let a = graph.create_texture("a", DESC);
+        let b = graph.create_texture("b", DESC);
+        let output = import(&mut graph, "output");
+        pass(&mut graph, &[], &[b]);
+        pass(&mut graph, &[], &[a]);
+        pass(&mut graph, &[a, b], &[output]);
+
+        assert_eq!(analyze(&graph).1, [0, 1, 2]);
+    }
+
+    #[test]
+    fn unread_outputs_are_culled() {
+        let mut graph = RenderGraph::default();
+        let used = graph.create_texture("used", DESC);
+        let unused = graph.create_texture("unused", DESC);
+        let output = import(&mut graph, "output");
+        pass(&mut graph, &[], &[used, unused]);
+        pass(&mut graph, &[unused], &[unused]);
+        pass(&mut graph, &[used], &[output]);
+        // Written after its last reader
+        pass(&mut graph, &[], &[used]);
+
+        let (live, order) = analyze(&graph);
+        assert_eq!(live, [true, false, true, false]);
+        assert_eq!(order, [0, 2]);
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_slots() {
+        let mut graph = RenderGraph::default();
+        let first = graph.create_texture("first", DESC);
+        let second = graph.create_texture("second", DESC);
+        let third = graph.create_texture("third", DESC);
+        let other = graph.create_texture(
+            "other",
+            TextureDesc {
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                ..DESC
+            },
+        );
+        let output = import(&mut graph, "output");
+        pass(&mut graph, &[], &[first]);
+        pass(&mut graph, &[first], &[second]);
+        pass(&mut graph, &[second], &[third, other]);
+        pass(&mut graph, &[third, other], &[output]);
+
+        let (_, order) = analyze(&graph);
+        let allocation = graph.allocate(&order).unwrap();
+        // The first texture's slot is free again once the second is written
+        assert_eq!(allocation[&first.0], 0);
+        assert_eq!(allocation[&second.0], 1);
+        assert_eq!(allocation[&third.0], 0);
+        assert_eq!(allocation[&other.0], 0);
+    }
+
+    #[test]
+    fn reads_before_writes_fail() {
+        let mut graph = RenderGraph::default();
+        let texture = graph.create_texture("texture", DESC);
+        let output = import(&mut graph, "output");
+        pass(&mut graph, &[texture], &[output]);
+
+        let (_, order) = analyze(&graph);
+        assert!(graph.allocate(&order).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{material::GpuMaterial, mesh::Mesh};
+
+pub struct DrawItem<'a> {
+    pub pipeline: wgpu::RenderPipeline,
+    pub material: GpuMaterial<'a>,
+    pub mesh: &'a Mesh,
+    // View space depth of the object origin
+    pub depth: f32,
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub pipeline_changes: u32,
+    pub bind_group_changes: u32,
+}
+
+impl RenderStats {
+    pub fn state_changes(&self) -> u32 {
+        self.pipeline_changes + self.bind_group_changes
+    }
+}
+
+impl std::ops::AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.pipeline_changes += other.pipeline_changes;
+        self.bind_group_changes += other.bind_group_changes;
+    }
+}
+
+/// Tracks what is currently bound to a render pass so that redundant
+/// `set_pipeline`/`set_bind_group` calls can be skipped.
+#[derive(Default)]
+pub struct RenderState<'a> {
+    pipeline: Option<&'a wgpu::RenderPipeline>,
+    bind_groups: [Option<&'a wgpu::BindGroup>; 4],
+    pub stats: RenderStats,
+}
+
+impl<'a> RenderState<'a> {
+    pub fn set_pipeline(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        pipeline: &'a wgpu::RenderPipeline,
+    ) {
+        if self.pipeline != Some(pipeline) {
+            render_pass.set_pipeline(pipeline);
+            self.pipeline = Some(pipeline);
+            self.stats.pipeline_changes += 1;
+        }
+    }
+
+    pub fn set_bind_group(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        idx: u32,
+        bind_group: &'a wgpu::BindGroup,
+    ) {
+        let slot = &mut self.bind_groups[idx as usize];
+        if *slot != Some(bind_group) {
+            render_pass.set_bind_group(idx, bind_group, &[]);
+            *slot = Some(bind_group);
+            self.stats.bind_group_changes += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RenderQueue<'a> {
+    opaque: Vec<DrawItem<'a>>,
+    transparent: Vec<DrawItem<'a>>,
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn push(&mut self, item: DrawItem<'a>) {
+        if item.material.is_transparent() {
+            self.transparent.push(item);
+        } else {
+            self.opaque.push(item);
+        }
+    }
+
+    // Opaque draws are grouped by pipeline, then by material and finally
+    // sorted front to back to make the most of early depth testing.
+    pub fn sort(&mut self) {
+        self.opaque.sort_by(|a, b| {
+            a.pipeline
+                .cmp(&b.pipeline)
+                .then_with(|| {
+                    a.material
+                        .material_bind_group()
+                        .cmp(&b.material.material_bind_group())
+                })
+                .then_with(|| a.depth.total_cmp(&b.depth))
+        });
+
+        // Blending is order dependent, so transparent draws are always
+        // submitted back to front regardless of the state changes involved
+        self.transparent.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+    }
+
+    pub fn execute_opaque(&self, render_pass: &mut wgpu::RenderPass) -> RenderStats {
+        Self::execute(&self.opaque, render_pass)
+    }
+
+    pub fn execute_transparent(&self, render_pass: &mut wgpu::RenderPass) -> RenderStats {
+        Self::execute(&self.transparent, render_pass)
+    }
+
+    fn execute(items: &[DrawItem<'a>], render_pass: &mut wgpu::RenderPass) -> RenderStats {
+        let mut state = RenderState::default();
+
+        for item in items {
+            state.set_pipeline(render_pass, &item.pipeline);
+            item.material.setup(render_pass, &mut state);
+            item.mesh.set_render_pass(render_pass);
+            state.stats.draw_calls += 1;
+        }
+
+        state.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::Vertex,
+        layouts::BindGroupLayouts,
+        material::AlphaMode,
+        pipeline::{PipelineCache, PipelineDesc, PipelineKey, RenderTargets, VertexLayout},
+        shader::ShaderKey,
+    };
+
+    const TARGETS: RenderTargets = RenderTargets {
+        color_format: Some(wgpu::TextureFormat::Rgba16Float),
+        depth_format: Some(wgpu::TextureFormat::Depth24Plus),
+        sample_count: 1,
+    };
+
+    fn pipeline_desc(layouts: &BindGroupLayouts, cull_mode: Option<wgpu::Face>) -> PipelineDesc {
+        PipelineDesc {
+            shader: ShaderKey::builtin("simple"),
+            vertex_layout: VertexLayout::Mesh,
+            material_layout: layouts.material.clone(),
+            blend: None,
+            cull_mode,
+            depth_write: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_bias: wgpu::DepthBiasState::default(),
+            material_uniforms: Vec::new(),
+        }
+    }
+
+    // Records the draws into a pass on a noop device, which never runs them
+    fn execute(
+        device: &wgpu::Device,
+        items: impl FnOnce(&mut wgpu::RenderPass) -> RenderStats,
+    ) -> RenderStats {
+        let target = |format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: None,
+                    size: wgpu::Extent3d::default(),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let color = target(TARGETS.color_format.unwrap());
+        let depth = target(TARGETS.depth_format.unwrap());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations::default(),
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth,
+                depth_ops: Some(wgpu::Operations::default()),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        items(&mut render_pass)
+    }
+
+    #[test]
+    fn draws_are_sorted_to_skip_state_changes() {
+        let (device, queue) = wgpu::Device::noop(&wgpu::DeviceDescriptor::default());
+        let layouts = BindGroupLayouts::new(&device);
+        let mut cache = PipelineCache::default();
+
+        let descs =
+            [None, Some(wgpu::Face::Back)].map(|cull_mode| pipeline_desc(&layouts, cull_mode));
+        let pipelines = descs.clone().map(|desc| {
+            let key = PipelineKey {
+                desc,
+                targets: TARGETS,
+            };
+            cache.get(&device, &layouts, &key).unwrap()
+        });
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[],
+        });
+        let bind_groups = [(); 2].map(|_| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &empty_layout,
+                entries: &[],
+            })
+        });
+        let mesh = Mesh::from_device(&device, &queue, vec![Vertex::default(); 3], vec![0, 1, 2]);
+
+        // Every pipeline and material combination, interleaved and far to near
+        let mut queue = RenderQueue::default();
+        for idx in 0..8 {
+            queue.push(DrawItem {
+                pipeline: pipelines[idx % 2].clone(),
+                material: GpuMaterial::new(
+                    &descs[idx % 2],
+                    vec![(BindGroupLayouts::MATERIAL_GROUP, &bind_groups[idx / 2 % 2])],
+                    AlphaMode::Opaque,
+                ),
+                mesh: &mesh,
+                depth: 10.0 - idx as f32,
+            });
+        }
+        queue.sort();
+
+        for (a, b) in queue.opaque.iter().zip(&queue.opaque[1..]) {
+            let a_key = (&a.pipeline, a.material.material_bind_group());
+            let b_key = (&b.pipeline, b.material.material_bind_group());
+            assert!(a_key <= b_key);
+            if a_key == b_key {
+                assert!(a.depth < b.depth, "{} {}", a.depth, b.depth);
+            }
+        }
+
+        let stats = execute(&device, |render_pass| queue.execute_opaque(render_pass));
+        assert_eq!(stats.draw_calls, 8);
+        assert_eq!(stats.pipeline_changes, 2);
+        // Both materials are bound once under each pipeline
+        assert_eq!(stats.bind_group_changes, 4);
+    }
+
+    #[test]
+    fn transparent_draws_are_sorted_back_to_front() {
+        let (device, queue) = wgpu::Device::noop(&wgpu::DeviceDescriptor::default());
+        let layouts = BindGroupLayouts::new(&device);
+        let mut cache = PipelineCache::default();
+
+        let desc = pipeline_desc(&layouts, None);
+        let key = PipelineKey {
+            desc: desc.clone(),
+            targets: TARGETS,
+        };
+        let pipeline = cache.get(&device, &layouts, &key).unwrap();
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[],
+        });
+        let bind_groups = [(); 2].map(|_| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &empty_layout,
+                entries: &[],
+            })
+        });
+        let mesh = Mesh::from_device(&device, &queue, vec![Vertex::default(); 3], vec![0, 1, 2]);
+
+        let mut queue = RenderQueue::default();
+        for depth in [3.0, 1.0, 4.0, 2.0] {
+            queue.push(DrawItem {
+                pipeline: pipeline.clone(),
+                material: GpuMaterial::new(
+                    &desc,
+                    vec![(
+                        BindGroupLayouts::MATERIAL_GROUP,
+                        &bind_groups[depth as usize % 2],
+                    )],
+                    AlphaMode::Blend,
+                ),
+                mesh: &mesh,
+                depth,
+            });
+        }
+        queue.sort();
+
+        assert!(queue.opaque.is_empty());
+        let depths: Vec<_> = queue.transparent.iter().map(|item| item.depth).collect();
+        assert_eq!(depths, [4.0, 3.0, 2.0, 1.0]);
+
+        // Order wins over state changes: the materials alternate in depth
+        let stats = execute(&device, |render_pass| {
+            queue.execute_transparent(render_pass)
+        });
+        assert_eq!(stats.draw_calls, 4);
+        assert_eq!(stats.pipeline_changes, 1);
+        assert_eq!(stats.bind_group_changes, 4);
+    }
+
+    #[test]
+    fn redundant_state_changes_are_skipped() {
+        // The queue has to outlive the encoder
+        let (device, _queue) = wgpu::Device::noop(&wgpu::DeviceDescriptor::default());
+        let layouts = BindGroupLayouts::new(&device);
+        let mut cache = PipelineCache::default();
+        let key = PipelineKey {
+            desc: pipeline_desc(&layouts, None),
+            targets: TARGETS,
+        };
+        let pipeline = cache.get(&device, &layouts, &key).unwrap();
+        let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &empty_layout,
+            entries: &[],
+        });
+
+        let stats = execute(&device, |render_pass| {
+            let mut state = RenderState::default();
+            for _ in 0..3 {
+                state.set_pipeline(render_pass, &pipeline);
+                state.set_bind_group(render_pass, 0, &bind_group);
+                state.set_bind_group(render_pass, 1, &bind_group);
+            }
+            state.stats
+        });
+        assert_eq!(stats.pipeline_changes, 1);
+        // Each group is tracked on its own
+        assert_eq!(stats.bind_group_changes, 2);
+        assert_eq!(stats.state_changes(), 3);
+    }
+}
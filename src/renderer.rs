@@ -1,55 +1,293 @@
 use crate::{
+    background::BackgroundRenderer,
+    environment::Environment,
     globals::Globals,
     gpu::Gpu,
+    light::Lights,
     object::{DataStore, DataToken},
+    post::PostStack,
+    render_graph::TexturePool,
+    render_queue::{DrawItem, RenderQueue, RenderStats},
     scene::Scene,
+    shadow::{ShadowCaster, ShadowMaps},
+    tonemap::{Exposure, TonemapOperator, Tonemapper},
 };
 
 use anyhow::Result;
-use winit::dpi::PhysicalSize;
+use glam::{Mat4, Vec2, Vec3};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use winit::{dpi::PhysicalSize, window::Window};
 
 pub struct Renderer {
     gpu: Gpu,
     globals: Globals,
+    lights: Lights,
+    shadows: ShadowMaps,
+    background: BackgroundRenderer,
+    // Lights scenes without an environment of their own
+    default_environment: Environment,
+    environment: Option<Rc<Environment>>,
+    tonemapper: Tonemapper,
+    post: PostStack,
+    textures: TexturePool,
+    // Saved after the next frame
+    screenshot: Option<PathBuf>,
 }
 
 impl Renderer {
-    pub fn render(&mut self, scene: &mut Scene, store: &mut DataStore) -> Result<()> {
+    const DEFAULT_AMBIENT: f32 = 0.1;
+
+    pub fn render(&mut self, scene: &mut Scene, store: &mut DataStore) -> Result<RenderStats> {
         self.globals.update_globals(&self.gpu);
-        self.gpu.render(|render_pass| {
-            for (obj, xform) in scene.root.get_all() {
-                match obj.get_data() {
-                    DataToken::Model(id) => {
-                        // TODO - refactor this unwrap and clone mess
-                        let token = scene.get_camera_object().unwrap().get_data();
-                        let camera = store
-                            .get_camera(token.try_as_camera().unwrap())
-                            .unwrap()
-                            .clone();
-                        let model = store.get_model(id).unwrap();
-                        model.update_model_uniform(&self.gpu, xform);
-                        model
-                            .material
-                            .as_gpu(&self.globals, &camera, model)
-                            .setup(render_pass);
-                        model.mesh.set_render_pass(render_pass);
-                    }
-                    DataToken::Camera(id) => {
-                        let camera = store.get_camera(id).unwrap();
-                        camera.update_camera_uniform(&self.gpu, xform, 640.0 / 480.0);
-                    }
-                    _ => {}
+        self.update_environment(scene);
+        self.gpu.pipelines.poll_shaders(&self.gpu.device);
+
+        let objects = scene.root.get_all();
+        let active_camera = scene
+            .get_camera_object()
+            .and_then(|camera| camera.get_data().try_as_camera());
+
+        // Cameras have to be updated before any model gets queued,
+        // so that depth sorting uses this frame's view matrix
+        let aspect = self.gpu.config.width as f32 / self.gpu.config.height as f32;
+        let mut camera_xform = Mat4::IDENTITY;
+        for (obj, xform) in &objects {
+            if let DataToken::Camera(id) = obj.get_data() {
+                let camera = store.get_camera(id).unwrap();
+                camera.update_camera_uniform(&self.gpu, *xform, aspect);
+                if Some(id) == active_camera {
+                    camera_xform = *xform;
                 }
             }
-        })
+        }
+        let view = camera_xform.inverse();
+
+        let Some(camera_id) = active_camera else {
+            return Ok(RenderStats::default());
+        };
+        let camera = store.get_camera(camera_id).unwrap().clone();
+
+        let lights: Vec<_> = objects
+            .iter()
+            .filter_map(|(obj, xform)| match obj.get_data() {
+                DataToken::Light(id) => {
+                    Some((store.get_light(id).unwrap(), *xform, obj.casts_shadows()))
+                }
+                _ => None,
+            })
+            .collect();
+        let shadow_views = self
+            .lights
+            .update(&self.gpu, &lights, &camera, camera_xform, aspect);
+        self.shadows.update(&self.gpu, &shadow_views);
+
+        let targets = self.gpu.render_targets();
+        let mut queue = RenderQueue::default();
+        let mut shadow_casters = Vec::new();
+        for (obj, xform) in &objects {
+            if let DataToken::Model(id) = obj.get_data() {
+                let model = store.get_model(id).unwrap();
+                model.update_model_uniform(&self.gpu, *xform, obj.receives_shadows());
+                let material = model.material.as_gpu(&self.globals, &camera, model);
+
+                // Transparent objects don't cast shadows
+                if obj.casts_shadows()
+                    && !material.is_transparent()
+                    && let Some(material_bind_group) = material.material_bind_group()
+                    && let Ok(pipeline) = self.gpu.pipelines.get(
+                        &self.gpu.device,
+                        &self.gpu.layouts,
+                        &material.shadow_pipeline_key(&self.gpu.layouts, false),
+                    )
+                    && let Ok(point_pipeline) = self.gpu.pipelines.get(
+                        &self.gpu.device,
+                        &self.gpu.layouts,
+                        &material.shadow_pipeline_key(&self.gpu.layouts, true),
+                    )
+                {
+                    shadow_casters.push(ShadowCaster {
+                        pipeline,
+                        point_pipeline,
+                        object: &model.bind_group,
+                        material: material_bind_group,
+                        mesh: &model.mesh,
+                    });
+                }
+
+                // Pipelines that fail to build have already reported why
+                let Ok(pipeline) = self.gpu.pipelines.get(
+                    &self.gpu.device,
+                    &self.gpu.layouts,
+                    &material.pipeline_key(targets),
+                ) else {
+                    continue;
+                };
+                queue.push(DrawItem {
+                    pipeline,
+                    material,
+                    mesh: &model.mesh,
+                    depth: view.transform_point3(xform.w_axis.truncate()).z,
+                });
+            }
+        }
+        queue.sort();
+        shadow_casters.sort_by(|a, b| {
+            a.pipeline
+                .cmp(&b.pipeline)
+                .then_with(|| a.material.cmp(b.material))
+        });
+
+        self.background.prepare(&mut self.gpu, &scene.background);
+        self.tonemapper
+            .prepare(&mut self.gpu, self.globals.delta_time());
+        self.post
+            .prepare(&mut self.gpu, &camera.post, self.globals.frame());
+
+        let mut shadow_stats = RenderStats::default();
+        let mut opaque_stats = RenderStats::default();
+        let mut transparent_stats = RenderStats::default();
+        let (gpu, background, post, tonemapper) = (
+            &self.gpu,
+            &self.background,
+            &mut self.post,
+            &mut self.tonemapper,
+        );
+        let screenshot = self.screenshot.take();
+        gpu.render(&mut self.textures, &screenshot, |graph, swapchain| {
+            let shadow_maps = self.shadows.add_pass(
+                graph,
+                &self.globals.shadow_pass_bind_group,
+                shadow_casters,
+                &mut shadow_stats,
+            );
+            let hdr = gpu.add_scene_passes(
+                graph,
+                &shadow_maps,
+                scene.background.clear_color(),
+                |render_pass| {
+                    opaque_stats = queue.execute_opaque(render_pass);
+                    background.draw(render_pass, &camera.bind_group);
+                },
+                |render_pass| transparent_stats = queue.execute_transparent(render_pass),
+            );
+            graph
+                .add_pass("Post-processing")
+                .read(hdr)
+                .write(hdr)
+                .write(swapchain)
+                .execute(move |context| {
+                    let (hdr, output) = (context.view(hdr), context.view(swapchain));
+                    post.render(context.device, context.encoder, hdr, output, tonemapper);
+                });
+        })?;
+        let mut stats = shadow_stats;
+        stats += opaque_stats;
+        stats += transparent_stats;
+
+        log::debug!(
+            "{} draw calls, {} state changes ({} pipelines, {} bind groups)",
+            stats.draw_calls,
+            stats.state_changes(),
+            stats.pipeline_changes,
+            stats.bind_group_changes,
+        );
+
+        Ok(stats)
+    }
+
+    // Rebinds the lighting when the scene's environment changes
+    fn update_environment(&mut self, scene: &Scene) {
+        let unchanged = match (&self.environment, &scene.environment) {
+            (Some(bound), Some(environment)) => Rc::ptr_eq(bound, environment),
+            (bound, environment) => bound.is_none() && environment.is_none(),
+        };
+        if unchanged {
+            return;
+        }
+
+        self.environment = scene.environment.clone();
+        let environment = self
+            .environment
+            .as_deref()
+            .unwrap_or(&self.default_environment);
+        self.globals
+            .set_environment(&self.gpu, &self.lights, &self.shadows, environment);
     }
 
     pub fn new(gpu: Gpu) -> Self {
-        let globals = Globals::new(&gpu);
-        Self { gpu, globals }
+        let lights = Lights::new(&gpu);
+        let shadows = ShadowMaps::new(&gpu);
+        let default_environment = Environment::ambient(&gpu, Vec3::splat(Self::DEFAULT_AMBIENT));
+        let globals = Globals::new(&gpu, &lights, &shadows, &default_environment);
+        let background = BackgroundRenderer::new(&gpu);
+        let tonemapper = Tonemapper::new(&gpu);
+        let post = PostStack::new(&gpu);
+
+        Self {
+            gpu,
+            globals,
+            lights,
+            shadows,
+            background,
+            default_environment,
+            environment: None,
+            tonemapper,
+            post,
+            textures: TexturePool::default(),
+            screenshot: None,
+        }
     }
 
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         self.gpu.resize(size);
     }
+
+    pub fn window(&self) -> &Window {
+        self.gpu.window()
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    /// Rebuilds the scene's pipelines for the new sample count.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.gpu.set_sample_count(sample_count);
+    }
+
+    pub fn set_point_shadow_budget(&mut self, budget: usize) {
+        self.lights.set_point_shadow_budget(budget);
+    }
+
+    pub fn tonemap_operator(&self) -> TonemapOperator {
+        self.tonemapper.operator()
+    }
+
+    pub fn set_tonemap_operator(&mut self, operator: TonemapOperator) {
+        self.tonemapper.set_operator(operator);
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.tonemapper.exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        self.tonemapper.set_exposure(exposure);
+    }
+
+    /// Saves the next frame to an image, in a format picked by its extension.
+    pub fn take_screenshot(&mut self, path: &Path) {
+        self.screenshot = Some(path.to_path_buf());
+    }
+
+    pub fn set_mouse_position(&mut self, position: Vec2) {
+        self.globals.set_mouse(position);
+    }
 }
@@ -1,7 +1,21 @@
-use crate::object::{DataToken, Object};
+use glam::{Mat4, Vec2, Vec3};
+use std::rc::Rc;
+
+use crate::{
+    background::Background,
+    bounds::Aabb,
+    environment::Environment,
+    object::{DataStore, DataToken, Object},
+    physics::PhysicsWorld,
+};
 
 pub struct Scene {
     pub root: Object,
+    pub background: Background,
+    /// Ambient lighting, a dim flat ambient light when unset.
+    pub environment: Option<Rc<Environment>>,
+    /// Rigid bodies attached to the scene's objects.
+    pub physics: PhysicsWorld,
     camera: Option<Object>,
 }
 
@@ -17,7 +31,23 @@ impl Scene {
 
         let root = Object::empty().with_children(objs);
 
-        Self { root, camera }
+        Self {
+            root,
+            background: Background::default(),
+            environment: None,
+            physics: PhysicsWorld::default(),
+            camera,
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_environment(mut self, environment: Rc<Environment>) -> Self {
+        self.environment = Some(environment);
+        self
     }
 
     pub fn set_camera(&mut self, camera: Object) {
@@ -27,4 +57,64 @@ impl Scene {
     pub fn get_camera_object(&mut self) -> Option<&mut Object> {
         self.camera.as_mut()
     }
+
+    /// Moves the active camera along its view direction until it sees all of
+    /// `bounds`, keeping its rotation. `bounds` are in world space, also when
+    /// the camera is parented to another object.
+    pub fn frame_bounds(&mut self, store: &mut DataStore, bounds: Aabb, aspect: f32) {
+        let Some(object) = self.camera.as_mut() else {
+            return;
+        };
+        let Some(camera) = object
+            .get_data()
+            .try_as_camera()
+            .and_then(|id| store.get_camera(id))
+        else {
+            return;
+        };
+
+        let distance = camera.frame(bounds, aspect);
+        let parent_xform = object.get_parent_xform();
+        let (scale, rotation, _) =
+            (parent_xform * object.get_local_xform()).to_scale_rotation_translation();
+        let position = bounds.center() - rotation * Vec3::Z * distance;
+        object.set_xform(
+            parent_xform.inverse()
+                * Mat4::from_scale_rotation_translation(scale, rotation, position),
+        );
+    }
+
+    /// Frames the active camera around an object and its children.
+    pub fn frame_object(&mut self, store: &mut DataStore, object: &Object, aspect: f32) {
+        if let Some(bounds) = object.bounds(store) {
+            self.frame_bounds(store, bounds, aspect);
+        }
+    }
+
+    /// Frames the active camera around every model in the scene.
+    pub fn frame_all(&mut self, store: &mut DataStore, aspect: f32) {
+        let root = self.root.clone();
+        self.frame_object(store, &root, aspect);
+    }
+
+    /// Closest point under a pixel of the viewport on the bounds of a model,
+    /// as seen through the active camera.
+    pub fn pick(&mut self, store: &mut DataStore, viewport: Vec2, pixel: Vec2) -> Option<Vec3> {
+        let object = self.camera.as_ref()?;
+        let camera = store.get_camera(object.get_data().try_as_camera()?)?;
+        let ray = camera.unproject(object.get_world_xform(), viewport, pixel);
+
+        self.root
+            .get_all()
+            .into_iter()
+            .filter_map(|(obj, xform)| {
+                let bounds = store
+                    .get_model(obj.get_data().try_as_model()?)?
+                    .mesh
+                    .bounds?;
+                bounds.transform(xform).intersect(&ray)
+            })
+            .min_by(f32::total_cmp)
+            .map(|distance| ray.at(distance))
+    }
 }
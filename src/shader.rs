@@ -0,0 +1,393 @@
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use wgpu::naga;
+
+// Sources embedded into the binary, relative to `src/shaders`
+const BUILTIN_SOURCES: &[(&str, &str)] = &[
+    ("simple.wgsl", include_str!("shaders/simple.wgsl")),
+    ("shadow.wgsl", include_str!("shaders/shadow.wgsl")),
+    ("tonemap.wgsl", include_str!("shaders/tonemap.wgsl")),
+    ("exposure.wgsl", include_str!("shaders/exposure.wgsl")),
+    ("bloom.wgsl", include_str!("shaders/bloom.wgsl")),
+    ("post.wgsl", include_str!("shaders/post.wgsl")),
+    ("background.wgsl", include_str!("shaders/background.wgsl")),
+    ("cubemap.wgsl", include_str!("shaders/cubemap.wgsl")),
+    ("ibl.wgsl", include_str!("shaders/ibl.wgsl")),
+    (
+        "include/common.wgsl",
+        include_str!("shaders/include/common.wgsl"),
+    ),
+    (
+        "include/camera.wgsl",
+        include_str!("shaders/include/camera.wgsl"),
+    ),
+    (
+        "include/cube.wgsl",
+        include_str!("shaders/include/cube.wgsl"),
+    ),
+    (
+        "include/lighting.wgsl",
+        include_str!("shaders/include/lighting.wgsl"),
+    ),
+    (
+        "include/shadows.wgsl",
+        include_str!("shaders/include/shadows.wgsl"),
+    ),
+    (
+        "include/fullscreen.wgsl",
+        include_str!("shaders/include/fullscreen.wgsl"),
+    ),
+    (
+        "include/exposure.wgsl",
+        include_str!("shaders/include/exposure.wgsl"),
+    ),
+    (
+        "include/material.wgsl",
+        include_str!("shaders/include/material.wgsl"),
+    ),
+];
+
+fn shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderFile {
+    Builtin(&'static str),
+    // WGSL loaded from disk at pipeline creation time
+    Path(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub file: ShaderFile,
+    // Preprocessor flags the shader variant is compiled with
+    pub defines: BTreeSet<String>,
+}
+
+impl ShaderKey {
+    pub fn builtin(name: &'static str) -> Self {
+        Self {
+            file: ShaderFile::Builtin(name),
+            defines: BTreeSet::new(),
+        }
+    }
+
+    pub fn file(path: &Path) -> Self {
+        Self {
+            file: ShaderFile::Path(path.into()),
+            defines: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_define(mut self, define: &str) -> Self {
+        self.defines.insert(define.into());
+        self
+    }
+
+    /// Location of the shader on disk. Builtin shaders point back into the
+    /// source tree, so that they can be edited while the program is running.
+    pub fn path(&self) -> PathBuf {
+        match &self.file {
+            ShaderFile::Builtin(name) => shader_dir().join(format!("{name}.wgsl")),
+            ShaderFile::Path(path) => path.clone(),
+        }
+    }
+
+    /// Loads and preprocesses the shader. Builtin shaders and includes are
+    /// only read from disk in development mode.
+    pub fn load(&self, from_disk: bool) -> Result<Preprocessed> {
+        let load = |path: &Path| {
+            let builtin = path
+                .strip_prefix(shader_dir())
+                .ok()
+                .and_then(|relative| BUILTIN_SOURCES.iter().find(|(name, _)| relative == *name));
+
+            match builtin {
+                Some((_, source)) if !from_disk => Ok(source.to_string()),
+                _ => std::fs::read_to_string(path)
+                    .map_err(|err| anyhow!("Failed to read shader {}: {err}", path.display())),
+            }
+        };
+
+        let mut preprocessor = Preprocessor {
+            defines: self.defines.iter().cloned().collect(),
+            load: &load,
+            output: Preprocessed::default(),
+        };
+        preprocessor.process_file(&self.path())?;
+
+        Ok(preprocessor.output)
+    }
+}
+
+/// Preprocessed WGSL along with the files it was assembled from.
+#[derive(Default)]
+pub struct Preprocessed {
+    pub source: String,
+    pub files: Vec<PathBuf>,
+    // File index and line number each output line came from
+    lines: Vec<(usize, usize)>,
+}
+
+impl Preprocessed {
+    fn original_location(&self, location: Option<naga::SourceLocation>) -> String {
+        location
+            .and_then(|location| self.lines.get(location.line_number as usize - 1))
+            .map(|(file, line)| format!("\n  = at {}:{line}", self.files[*file].display()))
+            .unwrap_or_default()
+    }
+
+    /// Parses and validates the WGSL, reporting errors as naga diagnostics
+    /// pointing at the file and line they come from.
+    pub fn parse(&self) -> Result<naga::Module> {
+        let source = &self.source;
+        let path = format!("{} (preprocessed)", self.files[0].display());
+
+        let module = naga::front::wgsl::parse_str(source).map_err(|err| {
+            let location = self.original_location(err.location(source));
+            anyhow!("{}{location}", err.emit_to_string_with_path(source, &path))
+        })?;
+
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|err| {
+            let location = self.original_location(err.location(source));
+            anyhow!("{}{location}", err.emit_to_string_with_path(source, &path))
+        })?;
+
+        Ok(module)
+    }
+}
+
+/// Expands `#include "file"` (relative to the including file) and
+/// `#include <file>` (from the builtin `src/shaders/include` directory),
+/// and evaluates `#define`, `#undef`, `#ifdef`, `#ifndef`, `#else` and `#endif`.
+/// Every file is included at most once.
+struct Preprocessor<'a> {
+    defines: HashSet<String>,
+    load: &'a dyn Fn(&Path) -> Result<String>,
+    output: Preprocessed,
+}
+
+impl Preprocessor<'_> {
+    fn resolve_include(path: &Path, argument: &str) -> Option<PathBuf> {
+        if let Some(name) = argument
+            .strip_prefix('"')
+            .and_then(|arg| arg.strip_suffix('"'))
+        {
+            return Some(path.parent()?.join(name));
+        }
+
+        argument
+            .strip_prefix('<')
+            .and_then(|arg| arg.strip_suffix('>'))
+            .map(|name| shader_dir().join("include").join(name))
+    }
+
+    fn process_file(&mut self, path: &Path) -> Result<()> {
+        if self.output.files.iter().any(|file| file == path) {
+            return Ok(());
+        }
+
+        let file_idx = self.output.files.len();
+        self.output.files.push(path.into());
+        let source = (self.load)(path)?;
+
+        // Each entry tells if the lines of that conditional block are emitted,
+        // and if its #else was reached already
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), line_idx + 1);
+            let active = conditions.iter().all(|(condition, _)| *condition);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.output.source.push_str(line);
+                    self.output.source.push('\n');
+                    self.output.lines.push((file_idx, line_idx + 1));
+                }
+                continue;
+            };
+
+            let mut parts = directive.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let argument = parts.next();
+
+            match (name, argument) {
+                ("ifdef", Some(define)) => conditions.push((self.defines.contains(define), false)),
+                ("ifndef", Some(define)) => {
+                    conditions.push((!self.defines.contains(define), false))
+                }
+                ("else", None) => {
+                    let (condition, had_else) = conditions
+                        .last_mut()
+                        .ok_or_else(|| anyhow!("#else without #ifdef at {}", location()))?;
+                    if *had_else {
+                        return Err(anyhow!("Second #else in the same block at {}", location()));
+                    }
+                    *condition = !*condition;
+                    *had_else = true;
+                }
+                ("endif", None) => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| anyhow!("#endif without #ifdef at {}", location()))?;
+                }
+                _ if !active => {}
+                ("define", Some(define)) => {
+                    self.defines.insert(define.into());
+                }
+                ("undef", Some(define)) => {
+                    self.defines.remove(define);
+                }
+                ("include", Some(argument)) => {
+                    let include = Self::resolve_include(path, argument)
+                        .ok_or_else(|| anyhow!("Malformed #include at {}", location()))?;
+                    self.process_file(&include)?;
+                }
+                _ => return Err(anyhow!("Unknown directive #{directive} at {}", location())),
+            }
+        }
+
+        if !conditions.is_empty() {
+            return Err(anyhow!("Unterminated #ifdef in {}", path.display()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Preprocesses the first of `files`, reading every file from them rather than disk,
+    // paths being relative to the shader directory
+    fn preprocess(files: &[(&str, &str)], defines: &[&str]) -> Result<Preprocessed> {
+        let load = |path: &Path| {
+            files
+                .iter()
+                .find(|(name, _)| shader_dir().join(name) == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| anyhow!("No shader {}", path.display()))
+        };
+        let mut preprocessor = Preprocessor {
+            defines: defines.iter().map(|define| define.to_string()).collect(),
+            load: &load,
+            output: Preprocessed::default(),
+        };
+        preprocessor.process_file(&shader_dir().join(files[0].0))?;
+
+        Ok(preprocessor.output)
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let output = preprocess(
+            &[
+                (
+                    "main.wgsl",
+                    "#include <common.wgsl>\n#include \"lib.wgsl\"\n#include \"lib.wgsl\"\nmain",
+                ),
+                ("lib.wgsl", "#include <common.wgsl>\nlib"),
+                ("include/common.wgsl", "common"),
+            ],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(output.source, "common\nlib\nmain\n");
+        assert_eq!(output.files.len(), 3);
+    }
+
+    #[test]
+    fn quoted_includes_are_relative_and_angled_ones_builtin() {
+        let output = preprocess(
+            &[
+                (
+                    "materials/main.wgsl",
+                    "#include \"util.wgsl\"\n#include <util.wgsl>",
+                ),
+                ("materials/util.wgsl", "material util"),
+                ("include/util.wgsl", "builtin util"),
+            ],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(output.source, "material util\nbuiltin util\n");
+        assert!(preprocess(&[("main.wgsl", "#include util.wgsl")], &[]).is_err());
+    }
+
+    #[test]
+    fn nested_conditionals_in_inactive_blocks() {
+        let source = "#ifdef A\n#define C\n#ifdef B\nab\n#else\na\n#endif\n#else\nnone\n#endif\n#ifdef C\nc\n#endif";
+        let files = [("main.wgsl", source)];
+
+        assert_eq!(preprocess(&files, &[]).unwrap().source, "none\n");
+        assert_eq!(preprocess(&files, &["B"]).unwrap().source, "none\n");
+        assert_eq!(preprocess(&files, &["A"]).unwrap().source, "a\nc\n");
+        assert_eq!(preprocess(&files, &["A", "B"]).unwrap().source, "ab\nc\n");
+    }
+
+    #[test]
+    fn undef_removes_defines() {
+        let source = "#undef A\n#define B\n#undef B\n#ifdef A\na\n#endif\n#ifndef B\nno b\n#endif";
+
+        let output = preprocess(&[("main.wgsl", source)], &["A"]).unwrap();
+        assert_eq!(output.source, "no b\n");
+    }
+
+    #[test]
+    fn malformed_conditionals_fail() {
+        for source in [
+            "#ifdef A\n#else\n#else\n#endif",
+            "#ifdef A\n#ifdef B\n#else\n#else\n#endif\n#endif",
+            "#else",
+            "#endif",
+            "#ifdef A",
+        ] {
+            assert!(
+                preprocess(&[("main.wgsl", source)], &[]).is_err(),
+                "{source}"
+            );
+        }
+
+        let Err(err) = preprocess(&[("main.wgsl", "#ifdef A\n#else\n#else\n#endif")], &[]) else {
+            panic!("A second #else was accepted");
+        };
+        assert!(err.to_string().contains("main.wgsl:3"), "{err}");
+    }
+
+    #[test]
+    fn lines_map_back_to_their_file() {
+        let output = preprocess(
+            &[
+                ("main.wgsl", "first\n#include \"lib.wgsl\"\nlast"),
+                ("lib.wgsl", "#ifdef A\nskipped\n#endif\nlib"),
+            ],
+            &[],
+        )
+        .unwrap();
+        let location = |line_number| {
+            output.original_location(Some(naga::SourceLocation {
+                line_number,
+                line_position: 1,
+                offset: 0,
+                length: 0,
+            }))
+        };
+
+        assert!(location(1).ends_with("main.wgsl:1"));
+        assert!(location(2).ends_with("lib.wgsl:4"));
+        assert!(location(3).ends_with("main.wgsl:3"));
+        assert_eq!(location(4), "");
+        assert_eq!(output.original_location(None), "");
+    }
+}
@@ -0,0 +1,482 @@
+use anyhow::{Result, anyhow};
+use glam::{Vec2, Vec3, Vec4};
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+use wgpu::naga;
+
+use crate::{
+    camera::Camera,
+    globals::Globals,
+    gpu::Gpu,
+    layouts::BindGroupLayouts,
+    material::{AlphaMode, GpuMaterial, Material, SimpleMaterial},
+    model::Model,
+    pipeline::{PipelineDesc, VertexLayout},
+    shader::ShaderKey,
+    uniform::{FieldLayout, UniformField, UniformLayout},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    F32(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+}
+
+impl ParamValue {
+    // Size and alignment of the value inside a WGSL uniform struct
+    fn size(&self) -> u64 {
+        match self {
+            Self::F32(_) => 4,
+            Self::Vec2(_) => 8,
+            Self::Vec3(_) => 12,
+            Self::Vec4(_) => 16,
+        }
+    }
+
+    fn align(&self) -> u64 {
+        match self {
+            Self::F32(_) => 4,
+            Self::Vec2(_) => 8,
+            Self::Vec3(_) | Self::Vec4(_) => 16,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Self::F32(value) => bytemuck::bytes_of(value).to_vec(),
+            Self::Vec2(value) => bytemuck::bytes_of(value).to_vec(),
+            Self::Vec3(value) => bytemuck::bytes_of(value).to_vec(),
+            Self::Vec4(value) => bytemuck::bytes_of(value).to_vec(),
+        }
+    }
+
+    fn same_type(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl From<f32> for ParamValue {
+    fn from(value: f32) -> Self {
+        Self::F32(value)
+    }
+}
+
+impl From<Vec2> for ParamValue {
+    fn from(value: Vec2) -> Self {
+        Self::Vec2(value)
+    }
+}
+
+impl From<Vec3> for ParamValue {
+    fn from(value: Vec3) -> Self {
+        Self::Vec3(value)
+    }
+}
+
+impl From<Vec4> for ParamValue {
+    fn from(value: Vec4) -> Self {
+        Self::Vec4(value)
+    }
+}
+
+pub struct TextureSlot {
+    pub name: String,
+    pub image: RgbaImage,
+    // Color textures are sRGB encoded, data textures such as normal maps are not
+    pub srgb: bool,
+}
+
+/// Declares the resources of a [`ShaderMaterial`].
+///
+/// The shader sees the material in bind group 3: the parameters as a uniform
+/// struct at binding 0 with members in declaration order, a filtering sampler
+/// at binding 1 and the textures at bindings 2 and up, in declaration order.
+/// Groups 0 to 2 hold the standard globals, camera and model uniforms,
+/// declared by `#include <common.wgsl>`.
+pub struct ShaderMaterialDesc {
+    pub shader: PathBuf,
+    // Preprocessor flags the shader is compiled with
+    pub defines: Vec<String>,
+    pub textures: Vec<TextureSlot>,
+    pub params: Vec<(String, ParamValue)>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+impl ShaderMaterialDesc {
+    pub fn new(shader: &Path) -> Self {
+        Self {
+            shader: shader.into(),
+            defines: Vec::new(),
+            textures: Vec::new(),
+            params: Vec::new(),
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+
+    pub fn with_define(mut self, define: &str) -> Self {
+        self.defines.push(define.into());
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str, image: RgbaImage, srgb: bool) -> Self {
+        self.textures.push(TextureSlot {
+            name: name.into(),
+            image,
+            srgb,
+        });
+        self
+    }
+
+    pub fn with_param(mut self, name: &str, value: impl Into<ParamValue>) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+}
+
+struct Param {
+    name: String,
+    offset: u64,
+    default: ParamValue,
+}
+
+/// A material backed by a user supplied WGSL shader.
+pub struct ShaderMaterial {
+    pipeline: PipelineDesc,
+    params: Vec<Param>,
+    params_uniform: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    alpha_mode: AlphaMode,
+}
+
+impl Material for ShaderMaterial {
+    fn as_gpu<'a>(
+        &'a self,
+        globals: &'a Globals,
+        camera: &'a Camera,
+        model: &'a Model,
+    ) -> GpuMaterial<'a> {
+        GpuMaterial::standard(
+            &self.pipeline,
+            (globals, camera, model),
+            &self.bind_group,
+            self.alpha_mode,
+        )
+    }
+}
+
+impl ShaderMaterial {
+    // Lays the parameters out following WGSL uniform alignment rules
+    fn layout_params(params: &[(String, ParamValue)]) -> (Vec<Param>, u64) {
+        let mut offset = 0u64;
+        let params = params
+            .iter()
+            .map(|(name, value)| {
+                offset = offset.next_multiple_of(value.align());
+                let param = Param {
+                    name: name.clone(),
+                    offset,
+                    default: *value,
+                };
+                offset += value.size();
+                param
+            })
+            .collect();
+
+        // Uniform structs are padded to 16 bytes, and empty ones are not allowed
+        (params, offset.next_multiple_of(16).max(16))
+    }
+
+    fn params_uniform_layout(params: &[Param], size: u64) -> UniformLayout {
+        UniformLayout {
+            name: "ShaderMaterial parameters".into(),
+            fields: params
+                .iter()
+                .map(|param| UniformField {
+                    name: param.name.clone(),
+                    offset: param.offset as u32,
+                    size: param.default.size() as u32,
+                    layout: FieldLayout::Plain,
+                })
+                .collect(),
+            size: size as u32,
+        }
+    }
+
+    fn get_bind_group_layout(device: &wgpu::Device, texture_count: u32) -> wgpu::BindGroupLayout {
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        entries.extend((0..texture_count).map(|idx| wgpu::BindGroupLayoutEntry {
+            binding: 2 + idx,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }));
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Shader material bind group layout".into(),
+            entries: &entries,
+        })
+    }
+
+    // Texture slots are bound in declaration order, which has to match the shader
+    fn check_textures(module: &naga::Module, textures: &[TextureSlot]) -> Result<()> {
+        for (idx, slot) in textures.iter().enumerate() {
+            let binding = naga::ResourceBinding {
+                group: BindGroupLayouts::MATERIAL_GROUP,
+                binding: 2 + idx as u32,
+            };
+            let name = module
+                .global_variables
+                .iter()
+                .find(|(_, variable)| variable.binding == Some(binding))
+                .and_then(|(_, variable)| variable.name.as_deref());
+
+            if let Some(name) = name
+                && name != slot.name
+            {
+                return Err(anyhow!(
+                    "Texture `{}` is at @binding({}), where the shader declares `{name}`",
+                    slot.name,
+                    binding.binding
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the shader and checks the parameters and textures against it,
+    /// so that mistakes are reported here rather than when first drawn.
+    pub fn new(gpu: &mut Gpu, desc: ShaderMaterialDesc) -> Result<Self> {
+        let material_layout = Self::get_bind_group_layout(&gpu.device, desc.textures.len() as u32);
+
+        let (params, params_size) = Self::layout_params(&desc.params);
+        let params_layout = Self::params_uniform_layout(&params, params_size);
+        let shader = desc
+            .defines
+            .iter()
+            .fold(ShaderKey::file(&desc.shader), |shader, define| {
+                shader.with_define(define)
+            });
+
+        gpu.pipelines.get_shader(&gpu.device, &shader)?;
+        if let Some(module) = gpu.pipelines.module(&shader) {
+            params_layout
+                .check(module, BindGroupLayouts::MATERIAL_GROUP, 0)
+                .and_then(|()| Self::check_textures(module, &desc.textures))
+                .map_err(|err| anyhow!("{}: {err}", desc.shader.display()))?;
+        }
+
+        let pipeline = PipelineDesc {
+            shader,
+            vertex_layout: VertexLayout::Mesh,
+            material_layout: material_layout.clone(),
+            blend: Some(desc.alpha_mode.blend_state()),
+            cull_mode: (!desc.double_sided).then_some(wgpu::Face::Back),
+            depth_write: desc.alpha_mode.depth_write(),
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_bias: wgpu::DepthBiasState::default(),
+            material_uniforms: vec![(0, params_layout)],
+        };
+
+        let params_uniform = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shader material parameters buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: params_size,
+            mapped_at_creation: false,
+        });
+
+        for param in &params {
+            gpu.queue
+                .write_buffer(&params_uniform, param.offset, &param.default.bytes());
+        }
+
+        let texture_views: Vec<_> = desc
+            .textures
+            .iter()
+            .map(|slot| {
+                let format = match slot.srgb {
+                    true => wgpu::TextureFormat::Rgba8UnormSrgb,
+                    false => wgpu::TextureFormat::Rgba8Unorm,
+                };
+                SimpleMaterial::make_texture(&gpu.device, &gpu.queue, &slot.image, format)
+                    .create_view(&wgpu::TextureViewDescriptor::default())
+            })
+            .collect();
+
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: "Shader material sampler".into(),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ];
+
+        entries.extend(
+            texture_views
+                .iter()
+                .enumerate()
+                .map(|(idx, view)| wgpu::BindGroupEntry {
+                    binding: 2 + idx as u32,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }),
+        );
+
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Shader material bind group".into(),
+            layout: &material_layout,
+            entries: &entries,
+        });
+
+        Ok(Self {
+            pipeline,
+            params,
+            params_uniform,
+            bind_group,
+            alpha_mode: desc.alpha_mode,
+        })
+    }
+
+    /// Sets a parameter, which has to be of the type it was declared with.
+    pub fn set(&self, gpu: &Gpu, name: &str, value: impl Into<ParamValue>) -> Result<()> {
+        let value = value.into();
+        let param = self
+            .params
+            .iter()
+            .find(|param| param.name == name)
+            .ok_or_else(|| anyhow!("Shader material has no parameter named {name}"))?;
+
+        if !param.default.same_type(&value) {
+            return Err(anyhow!(
+                "Parameter {name} is declared as {:?}, got {value:?}",
+                param.default
+            ));
+        }
+
+        gpu.queue
+            .write_buffer(&self.params_uniform, param.offset, &value.bytes());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(params: &[(&str, ParamValue)]) -> (Vec<Param>, u64) {
+        let params: Vec<_> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        ShaderMaterial::layout_params(&params)
+    }
+
+    fn slot(name: &str) -> TextureSlot {
+        TextureSlot {
+            name: name.into(),
+            image: RgbaImage::new(1, 1),
+            srgb: true,
+        }
+    }
+
+    #[test]
+    fn params_follow_wgsl_alignment() {
+        let (params, size) = layout(&[
+            ("a", 1.0.into()),
+            ("b", Vec3::ONE.into()),
+            ("c", 1.0.into()),
+            ("d", Vec2::ONE.into()),
+            ("e", Vec4::ONE.into()),
+        ]);
+        let offsets: Vec<_> = params.iter().map(|param| param.offset).collect();
+        assert_eq!(offsets, [0, 16, 28, 32, 48]);
+        assert_eq!(size, 64);
+
+        let module = naga::front::wgsl::parse_str(
+            "struct Params { a: f32, b: vec3f, c: f32, d: vec2f, e: vec4f };
+            @group(3) @binding(0) var<uniform> uParams: Params;
+            @fragment fn fs_main() -> @location(0) vec4f { return uParams.e; }",
+        )
+        .unwrap();
+        ShaderMaterial::params_uniform_layout(&params, size)
+            .check(&module, BindGroupLayouts::MATERIAL_GROUP, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn params_are_padded_to_16_bytes() {
+        assert_eq!(layout(&[]).1, 16);
+        assert_eq!(layout(&[("a", 1.0.into())]).1, 16);
+        assert_eq!(layout(&[("a", Vec4::ONE.into()), ("b", 1.0.into())]).1, 32);
+    }
+
+    #[test]
+    fn star_shader_matches_its_material() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders/star_shader.wgsl");
+        let shader = ShaderKey::file(&path).with_define("ALPHA_TEST");
+        let module = shader.load(false).unwrap().parse().unwrap();
+
+        let (params, size) = layout(&[
+            ("tint", Vec4::ONE.into()),
+            ("angle", 0.0.into()),
+            ("scale", 1.0.into()),
+        ]);
+        ShaderMaterial::params_uniform_layout(&params, size)
+            .check(&module, BindGroupLayouts::MATERIAL_GROUP, 0)
+            .unwrap();
+
+        ShaderMaterial::check_textures(&module, &[slot("star")]).unwrap();
+        let err = ShaderMaterial::check_textures(&module, &[slot("pattern")]).unwrap_err();
+        assert!(err.to_string().contains("declares `star`"), "{err}");
+    }
+}
@@ -0,0 +1,54 @@
+// Scene background, drawn at the far plane after the opaque geometry
+// Variants (a vertical gradient when not set):
+//   BACKGROUND_CUBEMAP - environment cube map
+
+#include <camera.wgsl>
+
+struct BackgroundUniform {
+    top: vec3f,
+    bottom: vec3f,
+};
+
+@group(0) @binding(0) var<uniform> uCamera: CameraUniform;
+@group(1) @binding(0) var<uniform> uBackground: BackgroundUniform;
+@group(1) @binding(1) var environment: texture_cube<f32>;
+@group(1) @binding(2) var environment_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    // World space view direction, not normalized so that it interpolates linearly
+    @location(0) direction: vec3f,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> VertexOutput {
+    let uv = vec2f(f32((idx << 1u) & 2u), f32(idx & 2u));
+    let ndc = uv * vec2f(2.0, -2.0) + vec2f(-1.0, 1.0);
+
+    // Orthographic views have no field of view, their background is shown as
+    // through a perspective one of 90 degrees vertically
+    let projection = uCamera.projection;
+    let orthographic = projection[3][3] == 1.0;
+    let scale = select(
+        vec2f(1.0 / projection[0][0], 1.0 / projection[1][1]),
+        vec2f(projection[1][1] / projection[0][0], 1.0),
+        orthographic
+    );
+    let view_direction = vec3f(ndc * scale, 1.0);
+    // The inverse of the view rotation is its transpose
+    let view_rotation = mat3x3f(uCamera.view[0].xyz, uCamera.view[1].xyz, uCamera.view[2].xyz);
+    return VertexOutput(vec4f(ndc, 1.0, 1.0), transpose(view_rotation) * view_direction);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let direction = normalize(in.direction);
+
+#ifdef BACKGROUND_CUBEMAP
+    let color = textureSampleLevel(environment, environment_sampler, direction, 0.0).rgb;
+#else
+    let color = mix(uBackground.bottom, uBackground.top, direction.y * 0.5 + 0.5);
+#endif
+
+    return vec4f(color, 1.0);
+}
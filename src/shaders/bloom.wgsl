@@ -0,0 +1,87 @@
+// Physically based bloom: the HDR scene is downsampled along a mip chain with a
+// 13 tap filter, then upsampled back with a tent filter, adding every level up
+// Entry points:
+//   fs_downsample_first - reads the scene, Karis average against fireflies
+//   fs_downsample       - reads the previous mip
+//   fs_upsample         - reads the next smaller mip, blended additively
+
+#include <fullscreen.wgsl>
+#include <exposure.wgsl>
+
+struct BloomUniform {
+    // Upsample filter radius in UV units
+    filter_radius: f32,
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> uBloom: BloomUniform;
+
+fn tap(uv: vec2f, offset: vec2f) -> vec3f {
+    return textureSampleLevel(source, source_sampler, uv + offset, 0.0).rgb;
+}
+
+// Average of a 2x2 block of taps, weighted down when bright
+fn karis_group(a: vec3f, b: vec3f, c: vec3f, d: vec3f, weight: f32) -> vec4f {
+    let average = (a + b + c + d) * 0.25;
+    let w = weight / (1.0 + luminance(average));
+    return vec4f(average * w, w);
+}
+
+@fragment
+fn fs_downsample_first(in: FullscreenOutput) -> @location(0) vec4f {
+    let t = 1.0 / vec2f(textureDimensions(source));
+    let a = tap(in.uv, t * vec2f(-2.0, 2.0));
+    let b = tap(in.uv, t * vec2f(0.0, 2.0));
+    let c = tap(in.uv, t * vec2f(2.0, 2.0));
+    let d = tap(in.uv, t * vec2f(-2.0, 0.0));
+    let e = tap(in.uv, vec2f(0.0));
+    let f = tap(in.uv, t * vec2f(2.0, 0.0));
+    let g = tap(in.uv, t * vec2f(-2.0, -2.0));
+    let h = tap(in.uv, t * vec2f(0.0, -2.0));
+    let i = tap(in.uv, t * vec2f(2.0, -2.0));
+    let j = tap(in.uv, t * vec2f(-1.0, 1.0));
+    let k = tap(in.uv, t * vec2f(1.0, 1.0));
+    let l = tap(in.uv, t * vec2f(-1.0, -1.0));
+    let m = tap(in.uv, t * vec2f(1.0, -1.0));
+
+    let sum = karis_group(a, b, d, e, 0.125)
+        + karis_group(b, c, e, f, 0.125)
+        + karis_group(d, e, g, h, 0.125)
+        + karis_group(e, f, h, i, 0.125)
+        + karis_group(j, k, l, m, 0.5);
+    return vec4f(sum.rgb / sum.w, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4f {
+    let t = 1.0 / vec2f(textureDimensions(source));
+    let a = tap(in.uv, t * vec2f(-2.0, 2.0));
+    let b = tap(in.uv, t * vec2f(0.0, 2.0));
+    let c = tap(in.uv, t * vec2f(2.0, 2.0));
+    let d = tap(in.uv, t * vec2f(-2.0, 0.0));
+    let e = tap(in.uv, vec2f(0.0));
+    let f = tap(in.uv, t * vec2f(2.0, 0.0));
+    let g = tap(in.uv, t * vec2f(-2.0, -2.0));
+    let h = tap(in.uv, t * vec2f(0.0, -2.0));
+    let i = tap(in.uv, t * vec2f(2.0, -2.0));
+    let j = tap(in.uv, t * vec2f(-1.0, 1.0));
+    let k = tap(in.uv, t * vec2f(1.0, 1.0));
+    let l = tap(in.uv, t * vec2f(-1.0, -1.0));
+    let m = tap(in.uv, t * vec2f(1.0, -1.0));
+
+    let color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+    return vec4f(color, 1.0);
+}
+
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4f {
+    let r = uBloom.filter_radius;
+    let color = tap(in.uv, vec2f(0.0)) * 4.0
+        + (tap(in.uv, vec2f(0.0, r)) + tap(in.uv, vec2f(-r, 0.0))
+            + tap(in.uv, vec2f(r, 0.0)) + tap(in.uv, vec2f(0.0, -r))) * 2.0
+        + tap(in.uv, vec2f(-r, r)) + tap(in.uv, vec2f(r, r))
+        + tap(in.uv, vec2f(-r, -r)) + tap(in.uv, vec2f(r, -r));
+    return vec4f(color / 16.0, 1.0);
+}
@@ -0,0 +1,41 @@
+// Cube map generation
+// Entry points:
+//   equirect_to_cube - projects an equirectangular panorama onto the six faces,
+//                      one invocation per texel with the face index in z
+
+#include <cube.wgsl>
+
+@group(0) @binding(0) var equirect: texture_2d<f32>;
+@group(0) @binding(1) var cube: texture_storage_2d_array<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+
+// Wraps horizontally and clamps vertically
+fn load_equirect(coord: vec2i) -> vec3f {
+    let size = vec2i(textureDimensions(equirect));
+    let wrapped = vec2i((coord.x % size.x + size.x) % size.x, clamp(coord.y, 0, size.y - 1));
+    return textureLoad(equirect, wrapped, 0).rgb;
+}
+
+// Bilinear filtering by hand, float32 textures aren't filterable everywhere
+fn sample_equirect(uv: vec2f) -> vec3f {
+    let texel = uv * vec2f(textureDimensions(equirect)) - 0.5;
+    let base = vec2i(floor(texel));
+    let f = fract(texel);
+    let top = mix(load_equirect(base), load_equirect(base + vec2i(1, 0)), f.x);
+    let bottom = mix(load_equirect(base + vec2i(0, 1)), load_equirect(base + vec2i(1, 1)), f.x);
+    return mix(top, bottom, f.y);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(cube);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let direction = cube_direction(id.z, (vec2f(id.xy) + 0.5) / vec2f(size));
+    // +Z is at the center of the panorama, +Y at the top
+    let uv = vec2f(0.5 + atan2(direction.x, direction.z) / (2.0 * PI), acos(direction.y) / PI);
+    textureStore(cube, id.xy, id.z, vec4f(sample_equirect(uv), 1.0));
+}
@@ -0,0 +1,89 @@
+// Automatic exposure from a histogram of the scene's log luminance
+// Entry points:
+//   build_histogram - bins every pixel of the HDR target, one 16x16 tile per workgroup
+//   average         - single workgroup turning the histogram into the adapted exposure,
+//                     then clearing it for the next frame
+
+#include <exposure.wgsl>
+
+const BIN_COUNT: u32 = 256u;
+// Exposure which maps the average luminance to middle gray
+const KEY_VALUE: f32 = 0.18;
+
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Fraction of the way towards the target luminance covered this frame
+    adaptation: f32,
+    // Extra exposure on top of the automatic one, as a multiplier
+    compensation: f32,
+    pixel_count: u32,
+};
+
+@group(0) @binding(0) var hdr: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, BIN_COUNT>;
+@group(0) @binding(2) var<uniform> uParams: ExposureParams;
+@group(0) @binding(3) var<storage, read_write> exposure: Exposure;
+
+var<workgroup> local_bins: array<atomic<u32>, BIN_COUNT>;
+var<workgroup> weighted_bins: array<f32, BIN_COUNT>;
+
+// Bin 0 is reserved for black pixels, which are left out of the average
+fn luminance_bin(color: vec3f) -> u32 {
+    let lum = luminance(color);
+    if lum < 1e-5 {
+        return 0u;
+    }
+
+    let log_lum = saturate((log2(lum) - uParams.min_log_luminance) / uParams.log_luminance_range);
+    return u32(log_lum * f32(BIN_COUNT - 2u) + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3u,
+    @builtin(local_invocation_index) idx: u32,
+) {
+    atomicStore(&local_bins[idx], 0u);
+    workgroupBarrier();
+
+    if all(id.xy < textureDimensions(hdr)) {
+        let color = textureLoad(hdr, id.xy, 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[idx], atomicLoad(&local_bins[idx]));
+}
+
+@compute @workgroup_size(256)
+fn average(@builtin(local_invocation_index) idx: u32) {
+    let count = atomicLoad(&histogram[idx]);
+    weighted_bins[idx] = f32(count) * f32(idx);
+    atomicStore(&histogram[idx], 0u);
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride >>= 1u) {
+        if idx < stride {
+            weighted_bins[idx] += weighted_bins[idx + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if idx == 0u {
+        // `count` is the number of black pixels here
+        let lit_pixels = max(f32(uParams.pixel_count) - f32(count), 1.0);
+        let average_bin = weighted_bins[0] / lit_pixels;
+        let log_lum = (average_bin - 1.0) / f32(BIN_COUNT - 2u) * uParams.log_luminance_range
+            + uParams.min_log_luminance;
+        let target_lum = exp2(log_lum);
+
+        // Snap to the target the first time around instead of fading in from black
+        var lum = target_lum;
+        if exposure.luminance > 0.0 {
+            lum = mix(exposure.luminance, target_lum, uParams.adaptation);
+        }
+        exposure.luminance = lum;
+        exposure.exposure = KEY_VALUE / lum * uParams.compensation;
+    }
+}
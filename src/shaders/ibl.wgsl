@@ -0,0 +1,129 @@
+// Image based lighting, precomputed once per environment map
+// Entry points:
+//   irradiance - cosine weighted integral of the environment over each hemisphere
+//   prefilter  - environment convolved with the GGX lobe of one roughness, per mip level
+//   brdf_lut   - split sum scale and bias applied to F0, by N.V and roughness
+
+#include <cube.wgsl>
+
+struct PrefilterUniform {
+    roughness: f32,
+};
+
+@group(0) @binding(0) var environment: texture_cube<f32>;
+@group(0) @binding(1) var environment_sampler: sampler;
+@group(0) @binding(2) var output: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3) var<uniform> uPrefilter: PrefilterUniform;
+@group(0) @binding(4) var lut: texture_storage_2d<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+// Angle between irradiance samples, in radians
+const IRRADIANCE_SAMPLE_DELTA: f32 = 0.025;
+const GGX_SAMPLE_COUNT: u32 = 1024u;
+
+// Orthonormal basis with the normal as its Z axis
+fn tangent_basis(normal: vec3f) -> mat3x3f {
+    let up = select(vec3f(0.0, 0.0, 1.0), vec3f(1.0, 0.0, 0.0), abs(normal.z) > 0.999);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3f(tangent, bitangent, normal);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2f {
+    return vec2f(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around +Z, distributed like the GGX lobe
+fn importance_sample_ggx(xi: vec2f, roughness: f32) -> vec3f {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+fn geometry_schlick_ggx(n_dot: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot / (n_dot * (1.0 - k) + k);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(output);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let basis = tangent_basis(cube_direction(id.z, (vec2f(id.xy) + 0.5) / vec2f(size)));
+    var sum = vec3f(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += IRRADIANCE_SAMPLE_DELTA) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += IRRADIANCE_SAMPLE_DELTA) {
+            let direction = vec3f(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(environment, environment_sampler, basis * direction, 0.0).rgb;
+            // Weighted by the cosine, and by the solid angle shrinking towards the pole
+            sum += radiance * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    // Scaled so that a uniform environment comes out unchanged
+    textureStore(output, id.xy, id.z, vec4f(PI * sum / count, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(output);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    // The view and reflected directions are assumed to be the normal
+    let normal = cube_direction(id.z, (vec2f(id.xy) + 0.5) / vec2f(size));
+    let basis = tangent_basis(normal);
+    var sum = vec3f(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < GGX_SAMPLE_COUNT; i++) {
+        let half_dir = basis * importance_sample_ggx(hammersley(i, GGX_SAMPLE_COUNT), uPrefilter.roughness);
+        let to_light = reflect(-normal, half_dir);
+        let n_dot_l = dot(normal, to_light);
+        if n_dot_l > 0.0 {
+            sum += textureSampleLevel(environment, environment_sampler, to_light, 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(output, id.xy, id.z, vec4f(sum / weight, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3u) {
+    let size = textureDimensions(lut);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let uv = (vec2f(id.xy) + 0.5) / vec2f(size);
+    let n_dot_v = uv.x;
+    let roughness = uv.y;
+    let view_direction = vec3f(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < GGX_SAMPLE_COUNT; i++) {
+        let half_dir = importance_sample_ggx(hammersley(i, GGX_SAMPLE_COUNT), roughness);
+        let to_light = reflect(-view_direction, half_dir);
+        let n_dot_l = saturate(to_light.z);
+        let n_dot_h = saturate(half_dir.z);
+        let v_dot_h = saturate(dot(view_direction, half_dir));
+        if n_dot_l > 0.0 {
+            let geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    textureStore(lut, id.xy, vec4f(scale, bias, 0.0, 0.0) / f32(GGX_SAMPLE_COUNT));
+}
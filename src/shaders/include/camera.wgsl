@@ -0,0 +1,8 @@
+// Camera uniform, shared with passes outside the standard pipelines
+
+struct CameraUniform {
+    projection: mat4x4f,
+    view: mat4x4f,
+    camera_pos: vec3f,
+    far: f32,
+}
@@ -0,0 +1,62 @@
+// Uniforms and vertex input shared by every standard pipeline
+
+#include "camera.wgsl"
+
+struct GlobalsUniform {
+    // Seconds since startup and since the previous frame
+    time: f32,
+    delta_time: f32,
+    frame: u32,
+    // Viewport size and cursor position, in pixels
+    resolution: vec2f,
+    mouse: vec2f,
+}
+
+struct ModelUniform {
+    model: mat4x4f,
+    normal: mat4x4f,
+    receive_shadows: u32,
+}
+
+const MAX_LIGHTS: u32 = 8u;
+const MAX_SHADOW_VIEWS: u32 = 8u;
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+const LIGHT_POINT: u32 = 2u;
+
+struct Light {
+    position: vec3f,
+    kind: u32,
+    direction: vec3f,
+    range: f32,
+    color: vec3f,
+    intensity: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+    // First shadow map layer of the light, or its shadow cube map for point
+    // lights. Negative when it casts no shadows
+    shadow_view: i32,
+}
+
+struct LightsUniform {
+    count: u32,
+    cascade_count: u32,
+    // View space distance at which each directional shadow cascade ends
+    cascade_splits: vec4f,
+    lights: array<Light, MAX_LIGHTS>,
+    shadow_matrices: array<mat4x4f, MAX_SHADOW_VIEWS>,
+}
+
+@group(0) @binding(0) var<uniform> uGlobals: GlobalsUniform;
+@group(0) @binding(1) var<uniform> uLights: LightsUniform;
+@group(1) @binding(0) var<uniform> uCamera: CameraUniform;
+@group(2) @binding(0) var<uniform> uModel: ModelUniform;
+
+struct VertexInput {
+    @location(0) pos: vec3f,
+    @location(1) tangent: vec3f,
+    @location(2) bitangent: vec3f,
+    @location(3) normal: vec3f,
+    @location(4) uv: vec2f,
+};
@@ -0,0 +1,15 @@
+// Cube map face addressing, for compute shaders writing cube maps
+
+// Direction through a point of a cube face, with faces in +X, -X, +Y, -Y, +Z, -Z
+// order and UVs starting at the top left
+fn cube_direction(face: u32, uv: vec2f) -> vec3f {
+    let p = uv * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3f(1.0, -p.y, -p.x)); }
+        case 1u: { return normalize(vec3f(-1.0, -p.y, p.x)); }
+        case 2u: { return normalize(vec3f(p.x, 1.0, p.y)); }
+        case 3u: { return normalize(vec3f(p.x, -1.0, -p.y)); }
+        case 4u: { return normalize(vec3f(p.x, -p.y, 1.0)); }
+        default: { return normalize(vec3f(-p.x, -p.y, -1.0)); }
+    }
+}
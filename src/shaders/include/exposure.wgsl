@@ -0,0 +1,14 @@
+// Exposure applied to the HDR scene before tonemapping
+
+struct Exposure {
+    // Adapted average scene luminance, zero until auto exposure first runs
+    luminance: f32,
+    exposure: f32,
+    _padding: vec2f,
+};
+
+const LUMA: vec3f = vec3f(0.2126, 0.7152, 0.0722);
+
+fn luminance(color: vec3f) -> f32 {
+    return dot(color, LUMA);
+}
@@ -0,0 +1,14 @@
+// Vertex stage covering the screen with a single triangle, drawn with 3 vertices
+// and no vertex buffers
+
+struct FullscreenOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) uv: vec2f,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) idx: u32) -> FullscreenOutput {
+    let uv = vec2f(f32((idx << 1u) & 2u), f32(idx & 2u));
+    let pos = vec4f(uv * vec2f(2.0, -2.0) + vec2f(-1.0, 1.0), 0.0, 1.0);
+    return FullscreenOutput(pos, uv);
+}
@@ -0,0 +1,74 @@
+// Blinn-Phong lighting for the lights in the scene, and image based
+// lighting from the environment
+
+#include <common.wgsl>
+#include <shadows.wgsl>
+
+@group(0) @binding(5) var irradiance_map: texture_cube<f32>;
+@group(0) @binding(6) var specular_map: texture_cube<f32>;
+@group(0) @binding(7) var brdf_lut: texture_2d<f32>;
+@group(0) @binding(8) var environment_sampler: sampler;
+
+// Reflectance of dielectrics at normal incidence
+const F0: vec3f = vec3f(0.04);
+// Roughness matching the Blinn-Phong hardness below
+const ROUGHNESS: f32 = 0.5;
+
+fn diffuse_light(normal: vec3f, to_light: vec3f, albedo: vec3f) -> vec3f {
+    return max(0.0, dot(to_light, normal)) * albedo;
+}
+
+fn specular_light(normal: vec3f, to_light: vec3f, view_direction: vec3f) -> vec3f {
+    let half_dir = normalize(normalize(view_direction) + to_light);
+    let angle = max(0.0, dot(normal, half_dir));
+    let hardness = 32.0;
+    return 0.4*vec3f(pow(angle, hardness));
+}
+
+// Light from the environment, with the split sum approximation for reflections
+fn environment_light(normal: vec3f, view_direction: vec3f, albedo: vec3f) -> vec3f {
+    let view_dir = normalize(view_direction);
+    let n_dot_v = max(0.0, dot(normal, view_dir));
+    let fresnel = F0 + (max(vec3f(1.0 - ROUGHNESS), F0) - F0) * pow(1.0 - n_dot_v, 5.0);
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, normal, 0.0).rgb;
+    let max_lod = f32(textureNumLevels(specular_map) - 1u);
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(specular_map, environment_sampler, reflected, ROUGHNESS * max_lod).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2f(n_dot_v, ROUGHNESS), 0.0).rg;
+
+    return (1.0 - fresnel) * irradiance * albedo + prefiltered * (fresnel * brdf.x + brdf.y);
+}
+
+// Sums up the contribution of the environment and every light, shadows included
+fn shade(world_pos: vec3f, normal: vec3f, view_direction: vec3f, albedo: vec3f, view_depth: f32) -> vec3f {
+    var color = environment_light(normal, view_direction, albedo);
+
+    for (var i = 0u; i < uLights.count; i++) {
+        let light = uLights.lights[i];
+        var to_light = -light.direction;
+        var attenuation = 1.0;
+
+        if light.kind != LIGHT_DIRECTIONAL {
+            let offset = light.position - world_pos;
+            let distance = length(offset);
+            to_light = offset / distance;
+            let falloff = saturate(1.0 - distance / light.range);
+            attenuation = falloff * falloff;
+        }
+
+        if light.kind == LIGHT_SPOT {
+            attenuation *= smoothstep(light.cos_outer, light.cos_inner, dot(-to_light, light.direction));
+        }
+
+        if attenuation <= 0.0 {
+            continue;
+        }
+
+        let shadow = light_shadow(light, world_pos, normal, view_depth);
+        let radiance = light.color * light.intensity * attenuation * shadow;
+        color += radiance * (diffuse_light(normal, to_light, albedo) + specular_light(normal, to_light, view_direction));
+    }
+
+    return color;
+}
@@ -0,0 +1,11 @@
+// Textures and uniforms of the standard material
+
+@group(3) @binding(0) var text: texture_2d<f32>;
+@group(3) @binding(1) var norm: texture_2d<f32>;
+@group(3) @binding(2) var sampl: sampler;
+@group(3) @binding(3) var<uniform> uMaterial: MaterialUniform;
+
+struct MaterialUniform {
+    base_color: vec4f,
+    alpha_cutoff: f32,
+}
@@ -0,0 +1,71 @@
+// Shadow map lookups with percentage closer filtering
+
+#include <common.wgsl>
+
+@group(0) @binding(2) var shadow_maps: texture_depth_2d_array;
+@group(0) @binding(3) var shadow_sampler: sampler_comparison;
+@group(0) @binding(4) var point_shadow_maps: texture_depth_cube_array;
+
+// Pushes the lookup position off the surface to avoid shadow acne
+const SHADOW_NORMAL_OFFSET: f32 = 0.02;
+// Point light shadows compare linear depth, so they are biased by a fraction of the range
+const POINT_SHADOW_BIAS: f32 = 0.005;
+
+// Fraction of light reaching a world position, using a 3x3 PCF kernel
+fn sample_shadow(view: i32, world_pos: vec3f) -> f32 {
+    let clip = uLights.shadow_matrices[view] * vec4f(world_pos, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + 0.5;
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2f(textureDimensions(shadow_maps));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2f(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, uv + offset, view, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Same as above for point lights, with the kernel spread around the lookup direction
+fn sample_point_shadow(cube: i32, light: Light, world_pos: vec3f) -> f32 {
+    let offset = world_pos - light.position;
+    let depth = length(offset) / light.range - POINT_SHADOW_BIAS;
+    let texel = 2.0 * length(offset) / f32(textureDimensions(point_shadow_maps).x);
+
+    var lit = 0.0;
+    for (var z = -1; z <= 1; z += 2) {
+        for (var y = -1; y <= 1; y += 2) {
+            for (var x = -1; x <= 1; x += 2) {
+                let direction = offset + vec3f(f32(x), f32(y), f32(z)) * texel;
+                lit += textureSampleCompareLevel(point_shadow_maps, shadow_sampler, direction, cube, depth);
+            }
+        }
+    }
+    return lit / 8.0;
+}
+
+fn light_shadow(light: Light, world_pos: vec3f, normal: vec3f, view_depth: f32) -> f32 {
+    if uModel.receive_shadows == 0u || light.shadow_view < 0 {
+        return 1.0;
+    }
+
+    let position = world_pos + normal * SHADOW_NORMAL_OFFSET;
+    if light.kind == LIGHT_POINT {
+        return sample_point_shadow(light.shadow_view, light, position);
+    } else if light.kind == LIGHT_SPOT {
+        return sample_shadow(light.shadow_view, position);
+    }
+
+    // Directional lights pick the first cascade covering the fragment
+    for (var i = 0u; i < uLights.cascade_count; i++) {
+        if view_depth < uLights.cascade_splits[i] {
+            return sample_shadow(light.shadow_view + i32(i), position);
+        }
+    }
+    return 1.0;
+}
@@ -0,0 +1,105 @@
+// Effects applied to the tonemapped image, one fullscreen pass each
+// Entry points, in the order they are chained:
+//   fs_color_grading - 3D LUT lookup on the sRGB encoded color
+//   fs_vignette      - darkens the corners
+//   fs_fxaa          - fast approximate antialiasing
+//   fs_film_grain    - animated noise
+
+#include <fullscreen.wgsl>
+#include <exposure.wgsl>
+
+struct PostUniform {
+    texel_size: vec2f,
+    frame: u32,
+    lut_size: f32,
+    lut_strength: f32,
+    vignette_intensity: f32,
+    vignette_smoothness: f32,
+    grain_intensity: f32,
+    fxaa_span_max: f32,
+    fxaa_reduce_mul: f32,
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> uPost: PostUniform;
+@group(1) @binding(0) var lut: texture_3d<f32>;
+
+fn linear_to_srgb(color: vec3f) -> vec3f {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3f(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3f) -> vec3f {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3f(2.4));
+    return select(high, low, color <= vec3f(0.04045));
+}
+
+fn sample_source(uv: vec2f) -> vec3f {
+    return textureSampleLevel(source, source_sampler, uv, 0.0).rgb;
+}
+
+@fragment
+fn fs_color_grading(in: FullscreenOutput) -> @location(0) vec4f {
+    let color = linear_to_srgb(saturate(sample_source(in.uv)));
+    // Sample texel centers, so that the LUT's corners map to 0 and 1
+    let uvw = color * (uPost.lut_size - 1.0) / uPost.lut_size + 0.5 / uPost.lut_size;
+    let graded = textureSampleLevel(lut, source_sampler, uvw, 0.0).rgb;
+    return vec4f(srgb_to_linear(mix(color, graded, uPost.lut_strength)), 1.0);
+}
+
+@fragment
+fn fs_vignette(in: FullscreenOutput) -> @location(0) vec4f {
+    // Distance from the center, reaching 1 in the corners
+    let distance = length(in.uv - 0.5) * sqrt(2.0);
+    let falloff = smoothstep(1.0 - uPost.vignette_smoothness, 1.0, distance);
+    return vec4f(sample_source(in.uv) * (1.0 - uPost.vignette_intensity * falloff), 1.0);
+}
+
+fn fxaa_luma(color: vec3f) -> f32 {
+    return sqrt(luminance(color));
+}
+
+@fragment
+fn fs_fxaa(in: FullscreenOutput) -> @location(0) vec4f {
+    let t = uPost.texel_size;
+    let rgb_m = sample_source(in.uv);
+    let luma_nw = fxaa_luma(sample_source(in.uv + vec2f(-1.0, -1.0) * t));
+    let luma_ne = fxaa_luma(sample_source(in.uv + vec2f(1.0, -1.0) * t));
+    let luma_sw = fxaa_luma(sample_source(in.uv + vec2f(-1.0, 1.0) * t));
+    let luma_se = fxaa_luma(sample_source(in.uv + vec2f(1.0, 1.0) * t));
+    let luma_m = fxaa_luma(rgb_m);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, perpendicular to the luma gradient
+    var dir = vec2f(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * uPost.fxaa_reduce_mul, 1.0 / 128.0);
+    let dir_scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * dir_scale, vec2f(-uPost.fxaa_span_max), vec2f(uPost.fxaa_span_max)) * t;
+
+    let rgb_a = 0.5 * (sample_source(in.uv + dir * (1.0 / 3.0 - 0.5)) + sample_source(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_source(in.uv - dir * 0.5) + sample_source(in.uv + dir * 0.5));
+    let luma_b = fxaa_luma(rgb_b);
+    // The wider blur overshot, the edge is thinner than its span
+    let overshot = luma_b < luma_min || luma_b > luma_max;
+    return vec4f(select(rgb_b, rgb_a, overshot), 1.0);
+}
+
+fn pcg_hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+@fragment
+fn fs_film_grain(in: FullscreenOutput) -> @location(0) vec4f {
+    let pixel = vec2u(in.pos.xy);
+    let hash = pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(uPost.frame)));
+    let noise = f32(hash) / 4294967295.0 - 0.5;
+    // Grain is added to the encoded color so that it shows evenly in darks and lights
+    let color = linear_to_srgb(saturate(sample_source(in.uv))) + noise * uPost.grain_intensity;
+    return vec4f(srgb_to_linear(saturate(color)), 1.0);
+}
@@ -0,0 +1,50 @@
+// Depth only pass rendering shadow casters from a light's point of view
+// Variants:
+//   LINEAR_DEPTH - store the distance to the light divided by its range,
+//                  as used by point light cube maps
+//   ALPHA_TEST   - discard fragments with alpha below the material cutoff,
+//                  for masked standard materials
+
+#include <common.wgsl>
+#ifdef ALPHA_TEST
+#include <material.wgsl>
+#endif
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) world_pos: vec3f,
+    @location(1) uv: vec2f,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world_pos = uModel.model * vec4f(in.pos, 1.0);
+    return VertexOutput(uCamera.projection * uCamera.view * world_pos, world_pos.xyz, in.uv);
+}
+
+#ifdef ALPHA_TEST
+fn alpha(uv: vec2f) -> f32 {
+    return textureSample(text, sampl, uv).a * uMaterial.base_color.a;
+}
+#endif
+
+#ifdef LINEAR_DEPTH
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+#ifdef ALPHA_TEST
+    if alpha(in.uv) < uMaterial.alpha_cutoff {
+        discard;
+    }
+#endif
+    return distance(in.world_pos, uCamera.camera_pos) / uCamera.far;
+}
+#else
+#ifdef ALPHA_TEST
+@fragment
+fn fs_main(in: VertexOutput) {
+    if alpha(in.uv) < uMaterial.alpha_cutoff {
+        discard;
+    }
+}
+#endif
+#endif
@@ -1,32 +1,11 @@
-@group(0) @binding(0) var<uniform> uGlobals: GlobalsUniform;
-@group(1) @binding(0) var<uniform> uCamera: CameraUniform;
-@group(2) @binding(0) var<uniform> uModel: ModelUniform;
-@group(3) @binding(0) var text: texture_2d<f32>;
-@group(3) @binding(1) var norm: texture_2d<f32>;
-@group(3) @binding(2) var sampl: sampler;
+// Variants:
+//   NORMAL_MAP  - perturb the normals with the normal map texture
+//   ALPHA_TEST  - discard fragments with alpha below the material cutoff
+//   ALPHA_BLEND - output the texture alpha for blending instead of 1.0
 
-struct GlobalsUniform {
-    time: f32
-}
-
-struct CameraUniform {
-    projection: mat4x4f,
-    view: mat4x4f,
-    camera_pos: vec3f,
-}
-
-struct ModelUniform {
-    model: mat4x4f,
-    normal: mat4x4f,
-}
-
-struct VertexInput {
-    @location(0) pos: vec3f,
-    @location(1) tangent: vec3f,
-    @location(2) bitangent: vec3f,
-    @location(3) normal: vec3f,
-    @location(4) uv: vec2f,
-};
+#include <common.wgsl>
+#include <lighting.wgsl>
+#include <material.wgsl>
 
 struct VertexOutput {
     @builtin(position) pos: vec4f,
@@ -35,42 +14,58 @@ struct VertexOutput {
     @location(2) normal: vec3f,
     @location(3) view_direction: vec3f,
     @location(4) uv: vec2f,
+    @location(5) world_pos: vec3f,
+    @location(6) view_depth: f32,
 };
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     let world_pos = uModel.model * vec4f(in.pos, 1.0);
-    let out_pos = uCamera.projection * uCamera.view * world_pos;
+    let view_pos = uCamera.view * world_pos;
+    let out_pos = uCamera.projection * view_pos;
     let normal = (uModel.normal * vec4f(in.normal, 0.0)).xyz;
     let view_direction = normalize(uCamera.camera_pos - world_pos.xyz);
 
     let tangent = (uModel.normal * vec4f(in.tangent, 0.0)).xyz;
     let bitangent = (uModel.normal * vec4f(in.bitangent, 0.0)).xyz;
 
-    return VertexOutput(out_pos, tangent, bitangent, normal, view_direction, in.uv);
+    return VertexOutput(out_pos, tangent, bitangent, normal, view_direction, in.uv, world_pos.xyz, view_pos.z);
 }
 
 @fragment
 fn fs_main(in: VertexOutput, @builtin(front_facing) face: bool) -> @location(0) vec4f {
-    let light = vec3f(10.0, 0.0, 0.0); 
-    let texture_sample = textureSample(text, sampl, in.uv);
+    let texture_sample = textureSample(text, sampl, in.uv) * uMaterial.base_color;
+#ifdef ALPHA_TEST
+    if texture_sample.a < uMaterial.alpha_cutoff {
+        discard;
+    }
+#endif
+
+    // Back faces of double sided geometry get their tangent frame flipped
+    let facing = select(-1.0, 1.0, face);
+    let geometric_normal = facing * normalize(in.normal);
+
+#ifdef NORMAL_MAP
     let normal_sample = textureSample(norm, sampl, in.uv);
     let local_normal = normal_sample.rgb * 2.0 - 1.0;
     let local_to_world = mat3x3f(
-        normalize(in.tangent),
-        normalize(in.bitangent),
-        normalize(in.normal)
+        facing * normalize(in.tangent),
+        facing * normalize(in.bitangent),
+        geometric_normal
     );
     let world_normal = local_to_world * local_normal;
     let strength = 0.5;
-    let normal = mix(in.normal, world_normal, strength);
+    let normal = mix(geometric_normal, world_normal, strength);
+#else
+    let normal = geometric_normal;
+#endif
     
-    let diffuse = max(0.1, dot(-light, normal)) * texture_sample;
-    
-    let half_dir = normalize(normalize(in.view_direction) + normalize(light));
-    let angle = max(0.0, dot(normal, half_dir));
-    let hardness = 32.0;
-    let specular = 0.4*vec4f(vec3f(pow(angle, hardness)), 1.0);
-    
-    return diffuse + specular;
+    let color = shade(in.world_pos, normalize(normal), in.view_direction, texture_sample.rgb, in.view_depth);
+
+#ifdef ALPHA_BLEND
+    let alpha = texture_sample.a;
+#else
+    let alpha = 1.0;
+#endif
+    return vec4f(color, alpha);
 }
@@ -1,45 +1,48 @@
-@group(0) @binding(0) var<uniform> uInput: BindingInput;
-@group(0) @binding(1) var text: texture_2d<f32>;
-@group(0) @binding(2) var sampl: sampler;
+// Spinning star sprite mapped over a model, unlit
+// Drawn as a shader material, so group 3 holds:
+//   binding 0 - StarParams
+//   binding 1 - sampler
+//   binding 2 - star texture
+// Variants:
+//   ALPHA_TEST - discard the transparent parts of the sprite
 
-struct BindingInput {
-    xform: mat3x3f,
-    time: f32
+#include <common.wgsl>
+
+struct StarParams {
+    tint: vec4f,
+    // Radians the sprite is turned by, and how many times it's tiled
+    angle: f32,
+    scale: f32,
 }
 
-struct VertexInput {
-    @location(0) pos: vec3f,
-    @location(1) uv: vec3f
-};
+@group(3) @binding(0) var<uniform> uParams: StarParams;
+@group(3) @binding(1) var sampl: sampler;
+@group(3) @binding(2) var star: texture_2d<f32>;
 
 struct VertexOutput {
     @builtin(position) pos: vec4f,
-    @location(0) uv: vec3f
+    @location(0) uv: vec2f,
 };
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
-    let ratio = 640.0/480.0;
-    let xform = uInput.xform;
-    let rot = mat3x3f(cos(uInput.time), sin(uInput.time), 0, -sin(uInput.time), cos(uInput.time), 0, 0, 0, 1);
-    let pos3d = vec3f(in.pos.x, in.pos.y, 1.0);
-    let rotated = xform * rot * pos3d;
-    let pos = vec4f((rotated.x - (uInput.time % 6.5) + 3.0) / ratio, rotated.y - (uInput.time % 6.5) + 3.0, in.pos.z, 1.0);
-    return VertexOutput(pos, in.uv);
+    let world_pos = uModel.model * vec4f(in.pos, 1.0);
+    return VertexOutput(uCamera.projection * uCamera.view * world_pos, in.uv);
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4f {
-    var uv = in.uv.xy;
-    let theta = 0.62;
-    let rot = mat2x2f(cos(theta), sin(theta), -sin(theta), cos(theta));
-    uv *= rot;
-    uv *= 0.55;
-    uv += vec2f(0.5, 0.5);
+    let rotation = mat2x2f(
+        cos(uParams.angle), sin(uParams.angle),
+        -sin(uParams.angle), cos(uParams.angle)
+    );
+    let uv = rotation * (in.uv - 0.5) * uParams.scale + 0.5;
 
-    let sample = textureSample(text, sampl, uv);
-    if sample.a == 0 {
-       discard;
+    let sample = textureSample(star, sampl, uv) * uParams.tint;
+#ifdef ALPHA_TEST
+    if sample.a < 0.5 {
+        discard;
     }
+#endif
     return sample;
 }
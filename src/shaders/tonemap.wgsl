@@ -0,0 +1,65 @@
+// Maps the exposed HDR scene to the display range
+// Variants (ACES when neither is set):
+//   TONEMAP_REINHARD - Reinhard on luminance
+//   TONEMAP_AGX      - AgX base look
+
+#include <fullscreen.wgsl>
+#include <exposure.wgsl>
+
+@group(0) @binding(0) var hdr: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> uExposure: Exposure;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3f) -> vec3f {
+    let x = color * 0.6;
+    return saturate((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14));
+}
+
+fn reinhard(color: vec3f) -> vec3f {
+    return color / (1.0 + luminance(color));
+}
+
+// Polynomial fit of the AgX contrast curve
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3f) -> vec3f {
+    let inset = mat3x3f(
+        vec3f(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3f(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3f(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3f(
+        vec3f(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3f(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3f(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var x = inset * color;
+    x = clamp(log2(max(x, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+    x = agx_contrast((x - min_ev) / (max_ev - min_ev));
+    // The curve outputs display encoded values, the sRGB target expects linear ones
+    return pow(max(outset * x, vec3f(0.0)), vec3f(2.2));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4f {
+    let color = textureLoad(hdr, vec2u(in.pos.xy), 0).rgb * uExposure.exposure;
+
+#ifdef TONEMAP_REINHARD
+    let mapped = reinhard(color);
+#else
+#ifdef TONEMAP_AGX
+    let mapped = agx(color);
+#else
+    let mapped = aces(color);
+#endif
+#endif
+
+    return vec4f(mapped, 1.0);
+}
@@ -0,0 +1,432 @@
+use glam::{Mat4, Vec3, Vec4};
+use std::num::NonZero;
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    gpu::Gpu,
+    layouts::BindGroupLayouts,
+    mesh::Mesh,
+    pipeline::RenderTargets,
+    render_graph::{RenderGraph, ResourceId},
+    render_queue::{RenderState, RenderStats},
+};
+
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const POINT_SHADOW_MAP_SIZE: u32 = 1024;
+// Layers of the shadow map array, shared by every directional and spot light
+pub const MAX_SHADOW_VIEWS: usize = 8;
+// Cube maps available to point lights
+pub const MAX_POINT_SHADOWS: usize = 4;
+pub const CASCADE_COUNT: usize = 4;
+
+/// A light's point of view when rendering one layer of the shadow map.
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowView {
+    pub projection: Mat4,
+    pub view: Mat4,
+    pub position: Vec3,
+    pub far: f32,
+}
+
+impl ShadowView {
+    // Shadows are only rendered up to this distance from the camera
+    const SHADOW_DISTANCE: f32 = 50.0;
+    // Blend between logarithmic and uniform cascade splits
+    const SPLIT_LAMBDA: f32 = 0.75;
+    const SPOT_NEAR: f32 = 0.05;
+
+    fn up_vector(direction: Vec3) -> Vec3 {
+        if direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        self.projection * self.view
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, outer_angle: f32, range: f32) -> Self {
+        Self {
+            projection: Mat4::perspective_lh(2.0 * outer_angle, 1.0, Self::SPOT_NEAR, range),
+            view: Mat4::look_to_lh(position, direction, Self::up_vector(direction)),
+            position,
+            far: range,
+        }
+    }
+
+    /// One view per cube map face, in +X, -X, +Y, -Y, +Z, -Z order.
+    pub fn point(position: Vec3, range: f32) -> [Self; 6] {
+        let faces = [
+            (Vec3::X, Vec3::Y),
+            (Vec3::NEG_X, Vec3::Y),
+            (Vec3::Y, Vec3::NEG_Z),
+            (Vec3::NEG_Y, Vec3::Z),
+            (Vec3::Z, Vec3::Y),
+            (Vec3::NEG_Z, Vec3::Y),
+        ];
+        let projection =
+            Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, Self::SPOT_NEAR, range);
+
+        faces.map(|(direction, up)| Self {
+            projection,
+            view: Mat4::look_to_lh(position, direction, up),
+            position,
+            far: range,
+        })
+    }
+
+    /// Splits the camera frustum into cascades and fits a directional light
+    /// projection around each of them. Also returns the view space distance
+    /// at which each cascade ends.
+    pub fn cascades(
+        direction: Vec3,
+        camera: &Camera,
+        camera_xform: Mat4,
+        aspect: f32,
+    ) -> ([Self; CASCADE_COUNT], Vec4) {
+        let near = camera.near;
+        let far = camera.far.min(Self::SHADOW_DISTANCE);
+
+        let splits = Vec4::from_array(std::array::from_fn(|idx| {
+            let t = (idx + 1) as f32 / CASCADE_COUNT as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            Self::SPLIT_LAMBDA * log + (1.0 - Self::SPLIT_LAMBDA) * uniform
+        }));
+
+        let view = camera_xform.inverse();
+        let cascades = std::array::from_fn(|idx| {
+            let split_near = if idx == 0 { near } else { splits[idx - 1] };
+            let projection = camera.projection.matrix(aspect, split_near, splits[idx]);
+            Self::fit_directional(direction, (projection * view).inverse())
+        });
+
+        (cascades, splits)
+    }
+
+    fn fit_directional(direction: Vec3, inverse_view_projection: Mat4) -> Self {
+        let corners = [-1.0, 1.0].into_iter().flat_map(|x| {
+            [-1.0, 1.0]
+                .into_iter()
+                .flat_map(move |y| [0.0, 1.0].map(|z| Vec3::new(x, y, z)))
+        });
+        let corners: Vec<Vec3> = corners
+            .map(|corner| inverse_view_projection.project_point3(corner))
+            .collect();
+
+        // Fitting a sphere instead of a box keeps the projection size constant
+        // as the camera rotates, and rounding it avoids shimmering edges
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        // Snap the center to whole shadow map texels for the same reason
+        let view = Mat4::look_to_lh(Vec3::ZERO, direction, Self::up_vector(direction));
+        let texel = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+        let center = view.transform_point3(center);
+        let center = Vec3::new(
+            (center.x / texel).floor() * texel,
+            (center.y / texel).floor() * texel,
+            center.z,
+        );
+
+        // Casters outside of the camera frustum can still shadow it
+        let projection = Mat4::orthographic_lh(
+            center.x - radius,
+            center.x + radius,
+            center.y - radius,
+            center.y + radius,
+            center.z - radius - Self::SHADOW_DISTANCE,
+            center.z + radius,
+        );
+
+        Self {
+            projection,
+            view,
+            position: view.inverse().transform_point3(center),
+            far: 2.0 * radius + Self::SHADOW_DISTANCE,
+        }
+    }
+}
+
+/// Every shadow map to render this frame.
+#[derive(Default)]
+pub struct ShadowViews {
+    pub views: Vec<ShadowView>,
+    pub point_views: Vec<[ShadowView; 6]>,
+}
+
+/// An object drawn into the shadow maps.
+pub struct ShadowCaster<'a> {
+    pub pipeline: wgpu::RenderPipeline,
+    // Writes the linear distance to the light used by point light shadows
+    pub point_pipeline: wgpu::RenderPipeline,
+    pub object: &'a wgpu::BindGroup,
+    pub material: &'a wgpu::BindGroup,
+    pub mesh: &'a Mesh,
+}
+
+struct ShadowLayer {
+    view: wgpu::TextureView,
+    camera_uniform: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Depth textures holding the shadow maps of every light: a 2D array for
+/// directional and spot lights, and a cube array for point lights.
+pub struct ShadowMaps {
+    pub view: wgpu::TextureView,
+    pub point_view: wgpu::TextureView,
+    // Bound in place of the shadow maps while they are being rendered
+    pub placeholder: wgpu::TextureView,
+    pub point_placeholder: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    layers: Vec<ShadowLayer>,
+    point_layers: Vec<ShadowLayer>,
+    active_layers: usize,
+    active_point_layers: usize,
+}
+
+impl ShadowMaps {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub const DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+        constant: 2,
+        slope_scale: 2.0,
+        clamp: 0.0,
+    };
+
+    fn make_texture(device: &wgpu::Device, size: u32, layers: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: "Shadow map texture".into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    }
+
+    fn make_array_view(
+        texture: &wgpu::Texture,
+        dimension: wgpu::TextureViewDimension,
+    ) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
+        })
+    }
+
+    fn make_layer(gpu: &Gpu, texture: &wgpu::Texture, layer: u32) -> ShadowLayer {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: "Shadow map layer".into(),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+
+        let camera_uniform = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow view uniform buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<CameraUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Shadow view bind group".into(),
+            layout: &gpu.layouts.camera,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &camera_uniform,
+                    offset: 0,
+                    size: NonZero::new(size_of::<CameraUniform>() as u64),
+                }),
+            }],
+        });
+
+        ShadowLayer {
+            view,
+            camera_uniform,
+            bind_group,
+        }
+    }
+
+    pub fn new(gpu: &Gpu) -> Self {
+        let point_layer_count = 6 * MAX_POINT_SHADOWS as u32;
+        let texture = Self::make_texture(&gpu.device, SHADOW_MAP_SIZE, MAX_SHADOW_VIEWS as u32);
+        let point_texture =
+            Self::make_texture(&gpu.device, POINT_SHADOW_MAP_SIZE, point_layer_count);
+        let placeholder = Self::make_texture(&gpu.device, 1, 6);
+
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: "Shadow map sampler".into(),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let layers = (0..MAX_SHADOW_VIEWS as u32)
+            .map(|layer| Self::make_layer(gpu, &texture, layer))
+            .collect();
+        let point_layers = (0..point_layer_count)
+            .map(|layer| Self::make_layer(gpu, &point_texture, layer))
+            .collect();
+
+        let array = wgpu::TextureViewDimension::D2Array;
+        let cube_array = wgpu::TextureViewDimension::CubeArray;
+
+        Self {
+            view: Self::make_array_view(&texture, array),
+            point_view: Self::make_array_view(&point_texture, cube_array),
+            placeholder: Self::make_array_view(&placeholder, array),
+            point_placeholder: Self::make_array_view(&placeholder, cube_array),
+            sampler,
+            layers,
+            point_layers,
+            active_layers: 0,
+            active_point_layers: 0,
+        }
+    }
+
+    pub fn targets() -> RenderTargets {
+        RenderTargets {
+            color_format: None,
+            depth_format: Some(Self::FORMAT),
+            sample_count: 1,
+        }
+    }
+
+    fn update_layers(gpu: &Gpu, layers: &[ShadowLayer], views: &[ShadowView]) -> usize {
+        for (layer, view) in layers.iter().zip(views) {
+            let uniform_data =
+                CameraUniform::new(view.projection, view.view, view.position, view.far);
+            gpu.queue
+                .write_buffer(&layer.camera_uniform, 0, bytemuck::bytes_of(&uniform_data));
+        }
+
+        views.len().min(layers.len())
+    }
+
+    pub fn update(&mut self, gpu: &Gpu, views: &ShadowViews) {
+        self.active_layers = Self::update_layers(gpu, &self.layers, &views.views);
+        self.active_point_layers =
+            Self::update_layers(gpu, &self.point_layers, views.point_views.as_flattened());
+    }
+
+    fn render_layers(
+        encoder: &mut wgpu::CommandEncoder,
+        layers: &[ShadowLayer],
+        frame_bind_group: &wgpu::BindGroup,
+        casters: &[ShadowCaster],
+        point: bool,
+    ) -> RenderStats {
+        let mut stats = RenderStats::default();
+
+        for layer in layers {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Render Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &layer.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            let mut state = RenderState::default();
+            state.set_bind_group(
+                &mut render_pass,
+                BindGroupLayouts::FRAME_GROUP,
+                frame_bind_group,
+            );
+            state.set_bind_group(
+                &mut render_pass,
+                BindGroupLayouts::CAMERA_GROUP,
+                &layer.bind_group,
+            );
+
+            for caster in casters {
+                let pipeline = if point {
+                    &caster.point_pipeline
+                } else {
+                    &caster.pipeline
+                };
+                state.set_pipeline(&mut render_pass, pipeline);
+                state.set_bind_group(
+                    &mut render_pass,
+                    BindGroupLayouts::OBJECT_GROUP,
+                    caster.object,
+                );
+                state.set_bind_group(
+                    &mut render_pass,
+                    BindGroupLayouts::MATERIAL_GROUP,
+                    caster.material,
+                );
+                caster.mesh.set_render_pass(&mut render_pass);
+                state.stats.draw_calls += 1;
+            }
+
+            stats += state.stats;
+        }
+
+        stats
+    }
+
+    /// Adds a pass rendering every caster into each shadow map in use this
+    /// frame. Returns the shadow maps, to be read by the passes sampling them.
+    pub fn add_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        frame_bind_group: &'a wgpu::BindGroup,
+        casters: Vec<ShadowCaster<'a>>,
+        stats: &'a mut RenderStats,
+    ) -> [ResourceId; 2] {
+        let shadow_maps = graph.import_texture("Shadow maps", &self.view);
+        let point_shadow_maps = graph.import_texture("Point shadow maps", &self.point_view);
+
+        graph
+            .add_pass("Shadows")
+            .write(shadow_maps)
+            .write(point_shadow_maps)
+            .execute(move |context| {
+                *stats = Self::render_layers(
+                    context.encoder,
+                    &self.layers[..self.active_layers],
+                    frame_bind_group,
+                    &casters,
+                    false,
+                );
+                *stats += Self::render_layers(
+                    context.encoder,
+                    &self.point_layers[..self.active_point_layers],
+                    frame_bind_group,
+                    &casters,
+                    true,
+                );
+            });
+
+        [shadow_maps, point_shadow_maps]
+    }
+}
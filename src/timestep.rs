@@ -0,0 +1,144 @@
+use std::time::Instant;
+
+/// Simulation steps due this frame, and how far the frame is past the last of them.
+#[derive(Copy, Clone, Debug)]
+pub struct Steps {
+    pub count: u32,
+    /// Simulated seconds per step.
+    pub step: f32,
+    /// From 0 at the last step to 1 at the next one.
+    pub alpha: f32,
+}
+
+/// Runs the simulation in steps of a fixed length, however long frames
+/// take, so that it behaves the same at any frame rate.
+pub struct FixedTimestep {
+    step: f32,
+    /// Simulated seconds per real second.
+    pub time_scale: f32,
+    pub paused: bool,
+    accumulator: f32,
+    queued_steps: u32,
+    last_update: Option<Instant>,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_STEP)
+    }
+}
+
+impl FixedTimestep {
+    const DEFAULT_STEP: f32 = 1.0 / 60.0;
+    // Longer frames run slower instead, so that a slow simulation can't keep
+    // falling further behind
+    const MAX_FRAME_TIME: f32 = 0.25;
+    const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+    const MAX_TIME_SCALE: f32 = 16.0;
+
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            time_scale: 1.0,
+            paused: false,
+            accumulator: 0.0,
+            queued_steps: 0,
+            last_update: None,
+        }
+    }
+
+    /// Scales the time scale, within 1/16 to 16 times real time.
+    pub fn scale_time(&mut self, factor: f32) {
+        self.time_scale =
+            (self.time_scale * factor).clamp(Self::MIN_TIME_SCALE, Self::MAX_TIME_SCALE);
+    }
+
+    /// Runs a single step on the next frame, even while paused.
+    pub fn queue_step(&mut self) {
+        self.queued_steps += 1;
+    }
+
+    /// Counts the steps that fit in the time since the last call.
+    pub fn advance(&mut self) -> Steps {
+        let now = Instant::now();
+        let elapsed = self.last_update.map_or(0.0, |last_update| {
+            now.duration_since(last_update).as_secs_f32()
+        });
+        self.last_update = Some(now);
+        self.advance_by(elapsed)
+    }
+
+    /// Counts the steps that fit in `elapsed` real seconds.
+    pub fn advance_by(&mut self, elapsed: f32) -> Steps {
+        let mut count = std::mem::take(&mut self.queued_steps);
+        if !self.paused {
+            self.accumulator += elapsed.min(Self::MAX_FRAME_TIME) * self.time_scale;
+            let due = (self.accumulator / self.step).floor();
+            self.accumulator -= due * self.step;
+            count += due as u32;
+        }
+
+        Steps {
+            count,
+            step: self.step,
+            alpha: self.accumulator / self.step,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remainders_carry_over_to_the_next_frame() {
+        let mut timestep = FixedTimestep::new(0.1);
+        let steps = timestep.advance_by(0.25);
+        assert_eq!(steps.count, 2);
+        assert!((steps.alpha - 0.5).abs() < 1e-4);
+
+        let steps = timestep.advance_by(0.06);
+        assert_eq!(steps.count, 1);
+        assert!((steps.alpha - 0.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut timestep = FixedTimestep::new(0.1);
+        let steps = timestep.advance_by(10.0);
+        assert_eq!(steps.count, 2);
+        assert!((steps.alpha - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn queued_steps_run_while_paused() {
+        let mut timestep = FixedTimestep::new(0.1);
+        timestep.advance_by(0.05);
+        timestep.paused = true;
+        assert_eq!(timestep.advance_by(0.2).count, 0);
+
+        timestep.queue_step();
+        timestep.queue_step();
+        let steps = timestep.advance_by(0.2);
+        assert_eq!(steps.count, 2);
+        // Time spent paused isn't made up for
+        assert!((steps.alpha - 0.5).abs() < 1e-4);
+        assert_eq!(timestep.advance_by(0.2).count, 0);
+    }
+
+    #[test]
+    fn time_scale_is_clamped() {
+        let mut timestep = FixedTimestep::new(0.1);
+        timestep.scale_time(2.0);
+        assert_eq!(timestep.advance_by(0.1).count, 2);
+
+        for _ in 0..10 {
+            timestep.scale_time(2.0);
+        }
+        assert_eq!(timestep.time_scale, FixedTimestep::MAX_TIME_SCALE);
+        for _ in 0..20 {
+            timestep.scale_time(0.5);
+        }
+        assert_eq!(timestep.time_scale, FixedTimestep::MIN_TIME_SCALE);
+    }
+}
@@ -0,0 +1,457 @@
+use anyhow::Result;
+use glam::Vec2;
+
+use crate::{
+    gpu::Gpu,
+    pipeline::PipelineCache,
+    post::make_fullscreen_pipeline,
+    shader::ShaderKey,
+    uniform::{Uniform, uniform_struct},
+};
+
+/// Curve mapping HDR scene colors to the display range.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TonemapOperator {
+    #[default]
+    Aces,
+    Reinhard,
+    AgX,
+}
+
+impl TonemapOperator {
+    /// The operator after this one, wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Aces => Self::Reinhard,
+            Self::Reinhard => Self::AgX,
+            Self::AgX => Self::Aces,
+        }
+    }
+
+    fn shader_key(self) -> ShaderKey {
+        let key = ShaderKey::builtin("tonemap");
+        match self {
+            Self::Aces => key,
+            Self::Reinhard => key.with_define("TONEMAP_REINHARD"),
+            Self::AgX => key.with_define("TONEMAP_AGX"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Exposure {
+    /// Fixed exposure in stops, zero leaves the scene as rendered.
+    Manual(f32),
+    /// Exposes the average scene luminance as middle gray. `compensation` is
+    /// added on top in stops, `speed` sets how quickly it adapts to changes.
+    Auto { compensation: f32, speed: f32 },
+}
+
+impl Exposure {
+    /// Brightens or darkens by `stops`, on top of auto exposure if enabled.
+    pub fn offset(self, stops: f32) -> Self {
+        match self {
+            Self::Manual(exposure) => Self::Manual(exposure + stops),
+            Self::Auto {
+                compensation,
+                speed,
+            } => Self::Auto {
+                compensation: compensation + stops,
+                speed,
+            },
+        }
+    }
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self::Auto {
+            compensation: 0.0,
+            speed: 1.5,
+        }
+    }
+}
+
+uniform_struct! {
+    struct ExposureUniform {
+        luminance: f32,
+        exposure: f32,
+        _padding: Vec2,
+    }
+}
+
+uniform_struct! {
+    struct ExposureParams {
+        min_log_luminance: f32,
+        log_luminance_range: f32,
+        adaptation: f32,
+        compensation: f32,
+        pixel_count: u32,
+        _padding: [u32; 3],
+    }
+}
+
+/// Exposes the HDR scene and tonemaps it into the swapchain. Auto exposure
+/// builds a luminance histogram of every frame on the GPU.
+pub struct Tonemapper {
+    operator: TonemapOperator,
+    exposure: Exposure,
+    layout: wgpu::BindGroupLayout,
+    exposure_layout: wgpu::BindGroupLayout,
+    histogram: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
+    // Failed builds are kept as None, so that they're only reported once
+    // Also rebuilt when shaders are reloaded
+    pipeline: Option<(
+        (TonemapOperator, wgpu::TextureFormat, u64),
+        Option<wgpu::RenderPipeline>,
+    )>,
+    exposure_pipelines: Option<(u64, Option<(wgpu::ComputePipeline, wgpu::ComputePipeline)>)>,
+    // Both bind groups reference the HDR texture, and are rebuilt when it changes
+    bind_groups: Option<(wgpu::TextureView, wgpu::BindGroup, wgpu::BindGroup)>,
+    size: (u32, u32),
+}
+
+impl Tonemapper {
+    const HISTOGRAM_BINS: u64 = 256;
+    const HISTOGRAM_TILE: u32 = 16;
+    // Luminance range covered by the histogram, in stops
+    const MIN_LOG_LUMINANCE: f32 = -10.0;
+    const MAX_LOG_LUMINANCE: f32 = 6.0;
+
+    fn make_layouts(device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroupLayout) {
+        let hdr = wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let storage = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let uniform = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty,
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Tonemap bind group layout".into(),
+            entries: &[
+                entry(0, wgpu::ShaderStages::FRAGMENT, hdr),
+                entry(1, wgpu::ShaderStages::FRAGMENT, uniform),
+            ],
+        });
+        let exposure_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Exposure bind group layout".into(),
+            entries: &[
+                entry(0, wgpu::ShaderStages::COMPUTE, hdr),
+                entry(1, wgpu::ShaderStages::COMPUTE, storage),
+                entry(2, wgpu::ShaderStages::COMPUTE, uniform),
+                entry(3, wgpu::ShaderStages::COMPUTE, storage),
+            ],
+        });
+
+        (layout, exposure_layout)
+    }
+
+    pub fn new(gpu: &Gpu) -> Self {
+        let (layout, exposure_layout) = Self::make_layouts(&gpu.device);
+
+        let histogram = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance histogram buffer"),
+            usage: wgpu::BufferUsages::STORAGE,
+            size: Self::HISTOGRAM_BINS * size_of::<u32>() as u64,
+            mapped_at_creation: false,
+        });
+        let params_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure params buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<ExposureParams>() as u64,
+            mapped_at_creation: false,
+        });
+        // Written by the auto exposure pass, or directly for manual exposure
+        let exposure_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure buffer"),
+            usage: wgpu::BufferUsages::UNIFORM
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<ExposureUniform>() as u64,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            operator: TonemapOperator::default(),
+            exposure: Exposure::default(),
+            layout,
+            exposure_layout,
+            histogram,
+            params_buffer,
+            exposure_buffer,
+            pipeline: None,
+            exposure_pipelines: None,
+            bind_groups: None,
+            size: (0, 0),
+        }
+    }
+
+    pub fn operator(&self) -> TonemapOperator {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: TonemapOperator) {
+        self.operator = operator;
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        self.exposure = exposure;
+    }
+
+    fn make_pipeline(
+        gpu: &mut Gpu,
+        layout: &wgpu::BindGroupLayout,
+        operator: TonemapOperator,
+    ) -> Result<wgpu::RenderPipeline> {
+        let target = gpu.config.format.into();
+        make_fullscreen_pipeline(
+            gpu,
+            &operator.shader_key(),
+            "fs_main",
+            &[layout],
+            &[(0, 1, ExposureUniform::layout())],
+            target,
+        )
+    }
+
+    fn make_exposure_pipelines(
+        gpu: &mut Gpu,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Result<(wgpu::ComputePipeline, wgpu::ComputePipeline)> {
+        let shader = ShaderKey::builtin("exposure");
+        let module = gpu.pipelines.get_shader(&gpu.device, &shader)?;
+        if let Some(naga_module) = gpu.pipelines.module(&shader) {
+            ExposureParams::layout().check(naga_module, 0, 2)?;
+        }
+        let pipeline_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Exposure pipeline layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+        let make = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&pipeline_layout),
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    cache: None,
+                })
+        };
+
+        PipelineCache::with_validation(&gpu.device, || (make("build_histogram"), make("average")))
+    }
+
+    fn make_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        hdr: &wgpu::TextureView,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Tonemap bind group".into(),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let exposure_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Exposure bind group".into(),
+            layout: &self.exposure_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        (bind_group, exposure_bind_group)
+    }
+
+    /// Builds whatever the current settings and surface need, and uploads this
+    /// frame's exposure parameters. Errors are logged once and leave the
+    /// screen black until fixed.
+    pub fn prepare(&mut self, gpu: &mut Gpu, delta_time: f32) {
+        let generation = gpu.pipelines.shader_generation();
+        let key = (self.operator, gpu.config.format, generation);
+        if self
+            .pipeline
+            .as_ref()
+            .is_none_or(|(built, _)| *built != key)
+        {
+            let pipeline = Self::make_pipeline(gpu, &self.layout, self.operator)
+                .inspect_err(|err| log::error!("Failed to build tonemap pipeline: {err}"))
+                .ok();
+            self.pipeline = Some((key, pipeline));
+        }
+        if self
+            .exposure_pipelines
+            .as_ref()
+            .is_none_or(|(built, _)| *built != generation)
+        {
+            let pipelines = Self::make_exposure_pipelines(gpu, &self.exposure_layout)
+                .inspect_err(|err| log::error!("Failed to build auto exposure pipelines: {err}"))
+                .ok();
+            self.exposure_pipelines = Some((generation, pipelines));
+        }
+
+        self.size = (gpu.config.width, gpu.config.height);
+
+        match self.exposure {
+            // Leaving the luminance at zero makes auto exposure start over when re-enabled
+            Exposure::Manual(stops) => {
+                let uniform_data = ExposureUniform {
+                    luminance: 0.0,
+                    exposure: stops.exp2(),
+                    _padding: Vec2::ZERO,
+                };
+                gpu.queue
+                    .write_buffer(&self.exposure_buffer, 0, bytemuck::bytes_of(&uniform_data));
+            }
+            Exposure::Auto {
+                compensation,
+                speed,
+            } => {
+                let params = ExposureParams {
+                    min_log_luminance: Self::MIN_LOG_LUMINANCE,
+                    log_luminance_range: Self::MAX_LOG_LUMINANCE - Self::MIN_LOG_LUMINANCE,
+                    adaptation: 1.0 - (-delta_time * speed).exp(),
+                    compensation: compensation.exp2(),
+                    pixel_count: self.size.0 * self.size.1,
+                    _padding: Default::default(),
+                };
+                gpu.queue
+                    .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+            }
+        }
+    }
+
+    /// Records auto exposure and tonemaps `hdr` into `output`.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        if self
+            .bind_groups
+            .as_ref()
+            .is_none_or(|(view, _, _)| view != hdr)
+        {
+            let (bind_group, exposure_bind_group) = self.make_bind_groups(device, hdr);
+            self.bind_groups = Some((hdr.clone(), bind_group, exposure_bind_group));
+        }
+        let Some((_, bind_group, exposure_bind_group)) = &self.bind_groups else {
+            return;
+        };
+
+        if matches!(self.exposure, Exposure::Auto { .. })
+            && let Some((_, Some((histogram_pipeline, average_pipeline)))) =
+                &self.exposure_pipelines
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto Exposure Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, exposure_bind_group, &[]);
+            compute_pass.set_pipeline(histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                self.size.0.div_ceil(Self::HISTOGRAM_TILE),
+                self.size.1.div_ceil(Self::HISTOGRAM_TILE),
+                1,
+            );
+            compute_pass.set_pipeline(average_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some((_, Some(pipeline))) = &self.pipeline {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniforms_match_shaders() {
+        let module = ShaderKey::builtin("exposure")
+            .load(false)
+            .unwrap()
+            .parse()
+            .unwrap();
+        ExposureParams::layout().check(&module, 0, 2).unwrap();
+
+        let operators = [
+            TonemapOperator::Aces,
+            TonemapOperator::Reinhard,
+            TonemapOperator::AgX,
+        ];
+        for operator in operators {
+            let module = operator.shader_key().load(false).unwrap().parse().unwrap();
+            ExposureUniform::layout().check(&module, 0, 1).unwrap();
+        }
+    }
+}
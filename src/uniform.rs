@@ -0,0 +1,421 @@
+use anyhow::{Result, anyhow};
+use bytemuck::NoUninit;
+use glam::{IVec2, IVec3, IVec4, Mat4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+use wgpu::naga;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UniformField {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub layout: FieldLayout,
+}
+
+/// Memory layout of a field's type, for the types with members of their own.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FieldLayout {
+    /// Scalars, vectors and matrices, only compared by size.
+    Plain,
+    Struct(UniformLayout),
+    Array {
+        stride: u32,
+        element: Box<FieldLayout>,
+    },
+}
+
+/// Memory layout of a uniform buffer as written from Rust.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UniformLayout {
+    pub name: String,
+    pub fields: Vec<UniformField>,
+    pub size: u32,
+}
+
+pub trait Uniform: NoUninit {
+    fn layout() -> UniformLayout;
+}
+
+/// Types that uniform struct fields can have.
+pub trait UniformValue {
+    fn field_layout() -> FieldLayout {
+        FieldLayout::Plain
+    }
+}
+
+// `Mat3` is missing, as WGSL pads each of its columns to 16 bytes
+impl UniformValue for f32 {}
+impl UniformValue for i32 {}
+impl UniformValue for u32 {}
+impl UniformValue for Vec2 {}
+impl UniformValue for Vec3 {}
+impl UniformValue for Vec4 {}
+impl UniformValue for IVec2 {}
+impl UniformValue for IVec3 {}
+impl UniformValue for IVec4 {}
+impl UniformValue for UVec2 {}
+impl UniformValue for UVec3 {}
+impl UniformValue for UVec4 {}
+impl UniformValue for Mat4 {}
+
+impl<T: UniformValue, const N: usize> UniformValue for [T; N] {
+    fn field_layout() -> FieldLayout {
+        FieldLayout::Array {
+            stride: size_of::<T>() as u32,
+            element: Box::new(T::field_layout()),
+        }
+    }
+}
+
+/// Declares a `#[repr(C)]` uniform struct along with its [`Uniform`] layout.
+/// Implicit padding fails to compile, so it has to be spelled out with fields
+/// starting with an underscore, which are ignored when checking the layout.
+/// Padding in WGSL is ignored the same way, so it does not need a Rust field
+/// of the same name, but the Rust struct still has to cover the whole struct.
+/// Uniform structs can be fields of other uniform structs.
+macro_rules! uniform_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        $vis struct $name {
+            $($field_vis $field: $ty),*
+        }
+
+        impl $crate::uniform::Uniform for $name {
+            fn layout() -> $crate::uniform::UniformLayout {
+                $crate::uniform::UniformLayout {
+                    name: stringify!($name).into(),
+                    fields: vec![$($crate::uniform::UniformField {
+                        name: stringify!($field).into(),
+                        offset: std::mem::offset_of!($name, $field) as u32,
+                        size: std::mem::size_of::<$ty>() as u32,
+                        layout: <$ty as $crate::uniform::UniformValue>::field_layout(),
+                    }),*],
+                    size: std::mem::size_of::<$name>() as u32,
+                }
+            }
+        }
+
+        impl $crate::uniform::UniformValue for $name {
+            fn field_layout() -> $crate::uniform::FieldLayout {
+                $crate::uniform::FieldLayout::Struct(
+                    <Self as $crate::uniform::Uniform>::layout(),
+                )
+            }
+        }
+    };
+}
+
+pub(crate) use uniform_struct;
+
+impl UniformLayout {
+    /// Compares the layout against the uniform the shader declares at the given
+    /// binding, using the WGSL alignment rules as computed by naga. Nested
+    /// structs and arrays are compared member by member.
+    /// Bindings the shader does not use are not checked.
+    pub fn check(&self, module: &naga::Module, group: u32, binding: u32) -> Result<()> {
+        let Some((_, variable)) = module.global_variables.iter().find(|(_, variable)| {
+            variable.binding == Some(naga::ResourceBinding { group, binding })
+        }) else {
+            return Ok(());
+        };
+
+        let mismatch = |reason: String| {
+            anyhow!(
+                "Uniform layout mismatch for {} at @group({group}) @binding({binding}): {reason}",
+                self.name
+            )
+        };
+
+        if variable.space != naga::AddressSpace::Uniform {
+            return Err(mismatch(
+                "the shader binding is not a uniform buffer".into(),
+            ));
+        }
+
+        let ty = &module.types[variable.ty];
+        let naga::TypeInner::Struct { members, span } = &ty.inner else {
+            return Err(mismatch("the shader binding is not a struct".into()));
+        };
+
+        self.check_members(module, members, *span, "")
+            .map_err(mismatch)
+    }
+
+    // Members are named by their path from the binding, starting with `prefix`
+    fn check_members(
+        &self,
+        module: &naga::Module,
+        members: &[naga::StructMember],
+        span: u32,
+        prefix: &str,
+    ) -> std::result::Result<(), String> {
+        let gctx = module.to_ctx();
+        for member in members {
+            let name = member.name.as_deref().unwrap_or_default();
+            if name.starts_with('_') {
+                continue;
+            }
+            let path = format!("{prefix}{name}");
+            let size = module.types[member.ty].inner.size(gctx);
+            let field = self
+                .fields
+                .iter()
+                .find(|field| field.name == name)
+                .ok_or_else(|| format!("WGSL member `{path}` has no Rust field"))?;
+
+            if field.offset != member.offset {
+                return Err(format!(
+                    "`{path}` is at offset {} in WGSL, but at {} in Rust",
+                    member.offset, field.offset
+                ));
+            }
+
+            if field.size != size {
+                return Err(format!(
+                    "`{path}` is {size} bytes in WGSL, but {} bytes in Rust",
+                    field.size
+                ));
+            }
+
+            Self::check_type(&field.layout, module, member.ty, &path)?;
+        }
+
+        if let Some(field) = self.fields.iter().find(|field| {
+            !field.name.starts_with('_')
+                && !members.iter().any(|m| m.name.as_ref() == Some(&field.name))
+        }) {
+            return Err(format!(
+                "Rust field `{prefix}{}` is missing in WGSL",
+                field.name
+            ));
+        }
+
+        // Nested structs are as long as their field, which was compared already
+        if span > self.size {
+            return Err(format!(
+                "the WGSL struct is {span} bytes, but only {} bytes are written from Rust",
+                self.size
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_type(
+        layout: &FieldLayout,
+        module: &naga::Module,
+        ty: naga::Handle<naga::Type>,
+        path: &str,
+    ) -> std::result::Result<(), String> {
+        match (layout, &module.types[ty].inner) {
+            (FieldLayout::Plain, _) => Ok(()),
+            (FieldLayout::Struct(layout), naga::TypeInner::Struct { members, span }) => {
+                layout.check_members(module, members, *span, &format!("{path}."))
+            }
+            (
+                FieldLayout::Array { stride, element },
+                naga::TypeInner::Array {
+                    base,
+                    stride: wgsl_stride,
+                    ..
+                },
+            ) => {
+                if stride != wgsl_stride {
+                    return Err(format!(
+                        "`{path}` has a stride of {wgsl_stride} bytes in WGSL, but {stride} bytes in Rust"
+                    ));
+                }
+                Self::check_type(element, module, *base, &format!("{path}[]"))
+            }
+            (FieldLayout::Struct(_), _) => {
+                Err(format!("`{path}` is a struct in Rust, but not in WGSL"))
+            }
+            (FieldLayout::Array { .. }, _) => {
+                Err(format!("`{path}` is an array in Rust, but not in WGSL"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    uniform_struct! {
+        struct TestUniform {
+            position: Vec3,
+            _padding: f32,
+            size: Vec2,
+            count: u32,
+            _padding2: u32,
+        }
+    }
+
+    uniform_struct! {
+        struct Inner {
+            color: Vec3,
+            intensity: f32,
+            direction: Vec3,
+            range: f32,
+        }
+    }
+
+    uniform_struct! {
+        struct Outer {
+            count: u32,
+            _padding: [u32; 3],
+            items: [Inner; 2],
+            offsets: [Vec4; 2],
+        }
+    }
+
+    fn module(members: &str) -> naga::Module {
+        let source = format!(
+            "struct TestUniform {{ {members} }};
+            @group(1) @binding(2) var<uniform> uTest: TestUniform;
+            @fragment fn fs_main() -> @location(0) vec4f {{ return vec4f(uTest.size, 0.0, 1.0); }}"
+        );
+        naga::front::wgsl::parse_str(&source).unwrap()
+    }
+
+    fn check(members: &str) -> Result<()> {
+        TestUniform::layout().check(&module(members), 1, 2)
+    }
+
+    fn check_nested(inner: &str, outer: &str) -> Result<()> {
+        let source = format!(
+            "struct Inner {{ {inner} }};
+            struct Outer {{ {outer} }};
+            @group(0) @binding(0) var<uniform> uOuter: Outer;
+            @fragment fn fs_main() -> @location(0) vec4f {{ return vec4f(f32(uOuter.count)); }}"
+        );
+        let module = naga::front::wgsl::parse_str(&source).unwrap();
+        Outer::layout().check(&module, 0, 0)
+    }
+
+    #[test]
+    fn matching_layout() {
+        check("position: vec3f, size: vec2f, count: u32").unwrap();
+    }
+
+    #[test]
+    fn padding_is_not_compared() {
+        check("position: vec3f, _pad: f32, size: vec2f, count: u32, _padding: u32").unwrap();
+    }
+
+    #[test]
+    fn unused_binding_is_not_checked() {
+        let module = module("position: vec3f, size: vec2f, count: u32");
+        TestUniform::layout().check(&module, 0, 0).unwrap();
+    }
+
+    #[test]
+    fn missing_wgsl_member() {
+        let err = check("position: vec3f, size: vec2f").unwrap_err();
+        assert!(
+            err.to_string().contains("`count` is missing in WGSL"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn missing_rust_field() {
+        let err = check("position: vec3f, size: vec2f, count: u32, flags: u32").unwrap_err();
+        assert!(
+            err.to_string().contains("`flags` has no Rust field"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn offset_mismatch() {
+        let err = check("position: vec3f, _padding: vec4f, size: vec2f, count: u32").unwrap_err();
+        assert!(err.to_string().contains("`size` is at offset 32"), "{err}");
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let err = check("position: vec3f, size: vec3f, count: u32").unwrap_err();
+        assert!(
+            err.to_string().contains("`size` is 12 bytes in WGSL"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn struct_longer_than_rust() {
+        let err = check("position: vec3f, size: vec2f, count: u32, _padding: vec4u").unwrap_err();
+        assert!(err.to_string().contains("only 32 bytes"), "{err}");
+    }
+
+    #[test]
+    fn matching_nested_layout() {
+        check_nested(
+            "color: vec3f, intensity: f32, direction: vec3f, range: f32",
+            "count: u32, items: array<Inner, 2>, offsets: array<vec4f, 2>",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn nested_member_mismatch() {
+        let err = check_nested(
+            "color: vec3f, brightness: f32, direction: vec3f, range: f32",
+            "count: u32, items: array<Inner, 2>, offsets: array<vec4f, 2>",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("WGSL member `items[].brightness` has no Rust field"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn nested_offset_mismatch() {
+        let err = check_nested(
+            "color: vec3f, range: f32, direction: vec3f, intensity: f32",
+            "count: u32, items: array<Inner, 2>, offsets: array<vec4f, 2>",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`items[].range` is at offset 12 in WGSL, but at 28 in Rust"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn array_stride_mismatch() {
+        let err = check_nested(
+            "color: vec3f, intensity: f32, direction: vec3f, range: f32",
+            "count: u32, items: array<Inner, 2>, offsets: array<vec2f, 4>",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`offsets` has a stride of 8 bytes in WGSL, but 16 bytes in Rust"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn struct_where_wgsl_has_none() {
+        let err = check_nested(
+            "color: vec3f, intensity: f32, direction: vec3f, range: f32",
+            "count: u32, items: array<mat2x4f, 2>, offsets: array<vec4f, 2>",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`items[]` is a struct in Rust, but not in WGSL"),
+            "{err}"
+        );
+    }
+}